@@ -0,0 +1,186 @@
+use crate::config::ReconnectConfig;
+use rand::Rng;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Exponentieller Backoff mit "Full Jitter": die Wartezeit ist zufällig zwischen
+/// 0 und einer Obergrenze, die sich mit jedem Fehlversuch vervielfacht. So
+/// verteilen sich viele Agents nach einem Server-Neustart über das ganze Intervall.
+pub struct Backoff {
+    config: ReconnectConfig,
+    failures: u32,
+    /// Obergrenze, aus der die letzte Wartezeit gezogen wurde.
+    ceiling: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Backoff {
+            ceiling: config.initial_backoff,
+            config,
+            failures: 0,
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Obergrenze der zuletzt gezogenen Wartezeit.
+    pub fn ceiling(&self) -> Duration {
+        self.ceiling
+    }
+
+    fn next_ceiling(&self) -> Duration {
+        let factor = self.config.multiplier.powi(self.failures.min(64) as i32);
+        // Bei großen Werten läuft die Duration über, dann gilt die Obergrenze
+        Duration::try_from_secs_f64(self.config.initial_backoff.as_secs_f64() * factor)
+            .map_or(self.config.max_backoff, |ceiling| {
+                ceiling.min(self.config.max_backoff)
+            })
+    }
+
+    /// Wartezeit vor dem nächsten Versuch; zählt einen weiteren Fehlversuch.
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.next_ceiling();
+        self.ceiling = ceiling;
+        self.failures = self.failures.saturating_add(1);
+        ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// Nach einer stabilen Session beginnt der Backoff wieder bei der ersten Stufe.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.ceiling = self.config.initial_backoff;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionPhase {
+    Connecting,
+    /// Beim Server registriert.
+    Connected,
+    /// Wartet bis zum nächsten Verbindungsversuch.
+    Backoff,
+}
+
+/// Verbindungszustand, wie ihn der Health-Endpunkt ausgibt.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionStatus {
+    pub phase: ConnectionPhase,
+    /// Fehlversuche seit der letzten stabilen Session.
+    pub consecutive_failures: u32,
+    /// Obergrenze der aktuellen Wartezeit.
+    pub backoff_ceiling_secs: f64,
+    /// Unix-Zeitpunkt des nächsten Versuchs, nur im Backoff.
+    pub next_attempt_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Gemeinsamer Verbindungszustand von gRPC-Client und Health-Server.
+#[derive(Clone)]
+pub struct ConnectionTracker {
+    status: Arc<Mutex<ConnectionStatus>>,
+}
+
+impl ConnectionTracker {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        ConnectionTracker {
+            status: Arc::new(Mutex::new(ConnectionStatus {
+                phase: ConnectionPhase::Connecting,
+                consecutive_failures: 0,
+                backoff_ceiling_secs: config.initial_backoff.as_secs_f64(),
+                next_attempt_at: None,
+                last_error: None,
+            })),
+        }
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn connecting(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.phase = ConnectionPhase::Connecting;
+        status.next_attempt_at = None;
+    }
+
+    pub fn connected(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.phase = ConnectionPhase::Connected;
+        status.next_attempt_at = None;
+    }
+
+    /// Der nächste Versuch folgt nach `delay`. `error` ist der Grund, falls die
+    /// Session mit einem Fehler endete.
+    pub fn waiting(&self, backoff: &Backoff, delay: Duration, error: Option<String>) {
+        let next_attempt_at = SystemTime::now() + delay;
+        let mut status = self.status.lock().unwrap();
+        status.phase = ConnectionPhase::Backoff;
+        status.consecutive_failures = backoff.failures();
+        status.backoff_ceiling_secs = backoff.ceiling().as_secs_f64();
+        status.next_attempt_at = Some(
+            next_attempt_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        if error.is_some() {
+            status.last_error = error;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(initial_backoff: Duration, max_backoff: Duration) -> Backoff {
+        Backoff::new(ReconnectConfig {
+            initial_backoff,
+            max_backoff,
+            multiplier: 2.0,
+            connect_timeout: Duration::from_secs(5),
+            stable_after: Duration::from_secs(60),
+        })
+    }
+
+    #[test]
+    fn ceiling_grows_per_failure_up_to_the_cap() {
+        let mut backoff = backoff(Duration::from_secs(1), Duration::from_secs(10));
+        let mut ceilings = Vec::new();
+        for _ in 0..6 {
+            let delay = backoff.next_delay();
+            // Full Jitter: irgendwo zwischen 0 und der Obergrenze
+            assert!(delay <= backoff.ceiling(), "{:?}", delay);
+            ceilings.push(backoff.ceiling().as_secs());
+        }
+        assert_eq!(ceilings, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(backoff.failures(), 6);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = backoff(Duration::from_secs(1), Duration::from_secs(10));
+        for _ in 0..4 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.ceiling(), Duration::from_secs(1));
+        backoff.next_delay();
+        assert_eq!(backoff.ceiling(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn huge_backoff_does_not_overflow() {
+        let mut backoff = backoff(Duration::from_secs(u64::MAX / 2), Duration::MAX);
+        for _ in 0..3 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.ceiling(), Duration::MAX);
+    }
+}
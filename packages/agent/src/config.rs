@@ -1,23 +1,550 @@
+use crate::error::{AgentError, Result};
+use clap::Parser;
+use serde::Deserialize;
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use tonic::transport::Endpoint;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_CONFIG_FILE: &str = "deliversphere-agent.toml";
+const DEFAULT_SERVER_URL: &str = "http://localhost:3001";
+const DEFAULT_WORKDIR: &str = ".deliversphere/work";
+const DEFAULT_CREDENTIAL_FILE: &str = ".deliversphere/agent-credential";
+const DEFAULT_CONCURRENCY: usize = 1;
+const DEFAULT_HEALTH_PORT: u16 = 3002;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_INITIAL_BACKOFF_SECS: u64 = 1;
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 60;
+const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_STABLE_AFTER_SECS: u64 = 60;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_OTEL_SERVICE_NAME: &str = "deliversphere-agent";
 
 #[derive(Clone, Debug)]
 pub struct AgentConfig {
     pub agent_id: String,
     pub hostname: String,
+    pub server_url: String,
     pub server_endpoint: Endpoint,
+    /// Meldet der Agent bei der Registrierung an den Server.
+    pub labels: Vec<String>,
+    /// Arbeitsverzeichnis, in dem die Jobs ausgeführt werden.
+    pub workdir: PathBuf,
+    /// Wie viele Jobs der Agent gleichzeitig annimmt.
+    pub concurrency: usize,
+    /// Gewünschtes Heartbeat-Intervall; ohne Angabe gilt die Vorgabe des Servers.
+    /// Der Server legt das Intervall bei der Registrierung fest.
+    pub heartbeat_interval: Option<Duration>,
+    pub health_port: u16,
+    /// So lange wartet der Agent beim Herunterfahren auf laufende Jobs, danach werden sie abgebrochen.
+    pub drain_timeout: Duration,
+    /// Hier wird das Credential nach dem Enrollment gespeichert.
+    pub credential_file: PathBuf,
+    /// Einmaliger Token für das erste Enrollment, danach nicht mehr nötig.
+    pub enrollment_token: Option<String>,
+    /// Bei mTLS identifiziert das Client-Zertifikat den Agent, ein Credential ist dann optional.
+    pub uses_client_certificate: bool,
+    pub reconnect: ReconnectConfig,
+    pub logging: LoggingConfig,
+    pub otel: OtelConfig,
+    /// Die gelesene Config-Datei, falls vorhanden.
+    pub config_file: Option<PathBuf>,
+}
+
+/// Ausgabeformat der Logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lesbare Zeilen für die Konsole.
+    #[default]
+    Pretty,
+    /// Ein JSON-Objekt pro Zeile, samt Feldern der aktiven Spans.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("erlaubt sind 'pretty' und 'json'".to_string()),
+        }
+    }
+}
+
+/// Export der Job-Ausführung als Spans per OTLP (gRPC), siehe [`crate::otel`].
+#[derive(Clone, Debug)]
+pub struct OtelConfig {
+    /// Adresse des Collectors, z.B. `http://localhost:4317`. Ohne Angabe kein Export.
+    pub endpoint: Option<String>,
+    pub service_name: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Filter im Format von `RUST_LOG`, z.B. `info,agent::grpc_client=debug`.
+    pub level: String,
+}
+
+/// Wartezeiten zwischen zwei Verbindungsversuchen zum Server.
+#[derive(Clone, Debug)]
+pub struct ReconnectConfig {
+    /// Obergrenze der ersten Wartezeit nach einem Fehler.
+    pub initial_backoff: Duration,
+    /// Obergrenze, egal wie viele Versuche fehlschlagen.
+    pub max_backoff: Duration,
+    /// Faktor, um den die Obergrenze mit jedem Fehlversuch wächst.
+    pub multiplier: f64,
+    pub connect_timeout: Duration,
+    /// Hielt eine Session so lange, beginnt der Backoff wieder von vorn.
+    pub stable_after: Duration,
+}
+
+/// CLI-Flags des Agents. Sie haben Vorrang vor Env-Vars und Config-Datei.
+#[derive(Debug, Default, Parser)]
+#[command(name = "agent", about = "Deliversphere Agent")]
+pub struct ConfigArgs {
+    /// Config-Datei (TOML). Default: `AGENT_CONFIG` oder ./deliversphere-agent.toml, falls vorhanden
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    #[arg(long)]
+    pub server_url: Option<String>,
+    #[arg(long)]
+    pub agent_id: Option<String>,
+    /// Kann mehrfach angegeben werden
+    #[arg(long = "label")]
+    pub labels: Vec<String>,
+    #[arg(long)]
+    pub workdir: Option<PathBuf>,
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+    #[arg(long)]
+    pub heartbeat_interval_secs: Option<u64>,
+    #[arg(long)]
+    pub health_port: Option<u16>,
+    #[arg(long)]
+    pub drain_timeout_secs: Option<u64>,
+}
+
+/// Eine Ebene der Konfiguration. Spätere Ebenen überschreiben frühere:
+/// Defaults < Config-Datei < Env-Vars < CLI-Flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigLayer {
+    server_url: Option<String>,
+    agent_id: Option<String>,
+    labels: Option<Vec<String>>,
+    workdir: Option<PathBuf>,
+    concurrency: Option<usize>,
+    heartbeat_interval_secs: Option<u64>,
+    health_port: Option<u16>,
+    drain_timeout_secs: Option<u64>,
+    credential_file: Option<PathBuf>,
+    enrollment_token: Option<String>,
+    #[serde(default)]
+    tls: TlsLayer,
+    #[serde(default)]
+    reconnect: ReconnectLayer,
+    #[serde(default)]
+    logging: LoggingLayer,
+    #[serde(default)]
+    otel: OtelLayer,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TlsLayer {
+    /// CA des Servers
+    ca: Option<PathBuf>,
+    /// Client-Zertifikat und Key für mTLS
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    /// Falls der Zertifikatsname vom Hostnamen der URL abweicht
+    domain: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReconnectLayer {
+    initial_backoff_secs: Option<u64>,
+    max_backoff_secs: Option<u64>,
+    multiplier: Option<f64>,
+    connect_timeout_secs: Option<u64>,
+    stable_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoggingLayer {
+    format: Option<LogFormat>,
+    level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OtelLayer {
+    endpoint: Option<String>,
+    service_name: Option<String>,
 }
 
-pub fn load_config() -> Result<AgentConfig, Box<dyn std::error::Error>> {
-    println!("Lade Agenten-Konfiguration...");
+impl ConfigLayer {
+    fn merge(self, over: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            server_url: over.server_url.or(self.server_url),
+            agent_id: over.agent_id.or(self.agent_id),
+            labels: over.labels.or(self.labels),
+            workdir: over.workdir.or(self.workdir),
+            concurrency: over.concurrency.or(self.concurrency),
+            heartbeat_interval_secs: over
+                .heartbeat_interval_secs
+                .or(self.heartbeat_interval_secs),
+            health_port: over.health_port.or(self.health_port),
+            drain_timeout_secs: over.drain_timeout_secs.or(self.drain_timeout_secs),
+            credential_file: over.credential_file.or(self.credential_file),
+            enrollment_token: over.enrollment_token.or(self.enrollment_token),
+            tls: TlsLayer {
+                ca: over.tls.ca.or(self.tls.ca),
+                cert: over.tls.cert.or(self.tls.cert),
+                key: over.tls.key.or(self.tls.key),
+                domain: over.tls.domain.or(self.tls.domain),
+            },
+            reconnect: ReconnectLayer {
+                initial_backoff_secs: over
+                    .reconnect
+                    .initial_backoff_secs
+                    .or(self.reconnect.initial_backoff_secs),
+                max_backoff_secs: over
+                    .reconnect
+                    .max_backoff_secs
+                    .or(self.reconnect.max_backoff_secs),
+                multiplier: over.reconnect.multiplier.or(self.reconnect.multiplier),
+                connect_timeout_secs: over
+                    .reconnect
+                    .connect_timeout_secs
+                    .or(self.reconnect.connect_timeout_secs),
+                stable_after_secs: over
+                    .reconnect
+                    .stable_after_secs
+                    .or(self.reconnect.stable_after_secs),
+            },
+            logging: LoggingLayer {
+                format: over.logging.format.or(self.logging.format),
+                level: over.logging.level.or(self.logging.level),
+            },
+            otel: OtelLayer {
+                endpoint: over.otel.endpoint.or(self.otel.endpoint),
+                service_name: over.otel.service_name.or(self.otel.service_name),
+            },
+        }
+    }
 
-    let server_name =
-        env::var("CS_SERVER").map_err(|e| format!("Env-Var CS_SERVER nicht gefunden: {}", e))?;
-    let replica_name =
-        env::var("CS_REPLICA").map_err(|e| format!("Env-Var CS_REPLICA nicht gefunden: {}", e))?;
+    fn from_file(path: &Path) -> Result<ConfigLayer> {
+        let content = fs::read_to_string(path).map_err(|source| AgentError::ConfigFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&content).map_err(|source| AgentError::ConfigParse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn from_env() -> Result<ConfigLayer> {
+        Ok(ConfigLayer {
+            server_url: non_empty_env("AGENT_SERVER_URL"),
+            agent_id: non_empty_env("AGENT_ID"),
+            labels: non_empty_env("AGENT_LABELS").map(|labels| {
+                labels
+                    .split(',')
+                    .map(|label| label.trim().to_string())
+                    .collect()
+            }),
+            workdir: non_empty_env("AGENT_WORKDIR").map(PathBuf::from),
+            concurrency: parse_env("AGENT_CONCURRENCY")?,
+            heartbeat_interval_secs: parse_env("AGENT_HEARTBEAT_INTERVAL_SECS")?,
+            health_port: parse_env("AGENT_HEALTH_PORT")?,
+            drain_timeout_secs: parse_env("AGENT_DRAIN_TIMEOUT_SECS")?,
+            credential_file: non_empty_env("AGENT_CREDENTIAL_FILE").map(PathBuf::from),
+            enrollment_token: non_empty_env("AGENT_ENROLLMENT_TOKEN"),
+            tls: TlsLayer {
+                ca: non_empty_env("AGENT_TLS_CA").map(PathBuf::from),
+                cert: non_empty_env("AGENT_TLS_CERT").map(PathBuf::from),
+                key: non_empty_env("AGENT_TLS_KEY").map(PathBuf::from),
+                domain: non_empty_env("AGENT_TLS_DOMAIN"),
+            },
+            reconnect: ReconnectLayer {
+                initial_backoff_secs: parse_env("AGENT_RECONNECT_INITIAL_BACKOFF_SECS")?,
+                max_backoff_secs: parse_env("AGENT_RECONNECT_MAX_BACKOFF_SECS")?,
+                multiplier: parse_env("AGENT_RECONNECT_MULTIPLIER")?,
+                connect_timeout_secs: parse_env("AGENT_RECONNECT_CONNECT_TIMEOUT_SECS")?,
+                stable_after_secs: parse_env("AGENT_RECONNECT_STABLE_AFTER_SECS")?,
+            },
+            logging: LoggingLayer {
+                format: parse_env("AGENT_LOG_FORMAT")?,
+                level: non_empty_env("AGENT_LOG_LEVEL"),
+            },
+            otel: OtelLayer {
+                endpoint: non_empty_env("AGENT_OTEL_ENDPOINT"),
+                service_name: non_empty_env("AGENT_OTEL_SERVICE_NAME"),
+            },
+        })
+    }
+
+    fn from_args(args: &ConfigArgs) -> ConfigLayer {
+        ConfigLayer {
+            server_url: args.server_url.clone(),
+            agent_id: args.agent_id.clone(),
+            labels: Some(args.labels.clone()).filter(|labels| !labels.is_empty()),
+            workdir: args.workdir.clone(),
+            concurrency: args.concurrency,
+            heartbeat_interval_secs: args.heartbeat_interval_secs,
+            health_port: args.health_port,
+            drain_timeout_secs: args.drain_timeout_secs,
+            ..ConfigLayer::default()
+        }
+    }
+}
+
+fn non_empty_env(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_env<T: std::str::FromStr>(var: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    non_empty_env(var)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|e| AgentError::invalid(var, format!("'{}': {}", value, e)))
+        })
+        .transpose()
+}
+
+fn read_pem(key: &str, path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| {
+        AgentError::invalid(
+            key,
+            format!("{} konnte nicht gelesen werden: {}", path.display(), e),
+        )
+    })
+}
 
-    let agent_id = format!("{}_{}", server_name, replica_name);
+fn build_tls_config(tls: &TlsLayer) -> Result<Option<ClientTlsConfig>> {
+    if tls.ca.is_none() && tls.cert.is_none() && tls.key.is_none() {
+        return Ok(None);
+    }
+
+    let mut config = ClientTlsConfig::new();
+    if let Some(ca) = &tls.ca {
+        config = config.ca_certificate(Certificate::from_pem(read_pem("tls.ca", ca)?));
+    }
+    match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => {
+            config = config.identity(Identity::from_pem(
+                read_pem("tls.cert", cert)?,
+                read_pem("tls.key", key)?,
+            ));
+        }
+        (None, None) => {}
+        (Some(_), None) => {
+            return Err(AgentError::invalid(
+                "tls.key",
+                "muss zusammen mit tls.cert gesetzt sein",
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(AgentError::invalid(
+                "tls.cert",
+                "muss zusammen mit tls.key gesetzt sein",
+            ))
+        }
+    }
+    if let Some(domain) = &tls.domain {
+        config = config.domain_name(domain.clone());
+    }
+    Ok(Some(config))
+}
+
+fn is_valid_name(value: &str, extra: &[char]) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') || extra.contains(&c)
+        })
+}
+
+/// Ohne explizite Agent-ID wird wie bisher die Codesphere-Umgebung verwendet.
+fn codesphere_agent_id() -> Option<String> {
+    let server_name = non_empty_env("CS_SERVER")?;
+    let replica_name = non_empty_env("CS_REPLICA")?;
+    Some(format!("{}_{}", server_name, replica_name))
+}
+
+fn resolve_reconnect(layer: &ReconnectLayer) -> Result<ReconnectConfig> {
+    let positive_secs = |key: &str, value: Option<u64>, default: u64| match value {
+        Some(0) => Err(AgentError::invalid(key, "muss mindestens 1 sein")),
+        value => Ok(Duration::from_secs(value.unwrap_or(default))),
+    };
+    let initial_backoff = positive_secs(
+        "reconnect.initial_backoff_secs",
+        layer.initial_backoff_secs,
+        DEFAULT_INITIAL_BACKOFF_SECS,
+    )?;
+    let max_backoff = positive_secs(
+        "reconnect.max_backoff_secs",
+        layer.max_backoff_secs,
+        DEFAULT_MAX_BACKOFF_SECS,
+    )?;
+    if max_backoff < initial_backoff {
+        return Err(AgentError::invalid(
+            "reconnect.max_backoff_secs",
+            format!(
+                "muss mindestens so groß sein wie reconnect.initial_backoff_secs ({})",
+                initial_backoff.as_secs()
+            ),
+        ));
+    }
+    let multiplier = layer.multiplier.unwrap_or(DEFAULT_BACKOFF_MULTIPLIER);
+    if !(multiplier >= 1.0 && multiplier.is_finite()) {
+        return Err(AgentError::invalid(
+            "reconnect.multiplier",
+            "muss mindestens 1.0 sein",
+        ));
+    }
+    Ok(ReconnectConfig {
+        initial_backoff,
+        max_backoff,
+        multiplier,
+        connect_timeout: positive_secs(
+            "reconnect.connect_timeout_secs",
+            layer.connect_timeout_secs,
+            DEFAULT_CONNECT_TIMEOUT_SECS,
+        )?,
+        stable_after: positive_secs(
+            "reconnect.stable_after_secs",
+            layer.stable_after_secs,
+            DEFAULT_STABLE_AFTER_SECS,
+        )?,
+    })
+}
+
+fn resolve(layer: ConfigLayer) -> Result<AgentConfig> {
+    let agent_id = layer.agent_id.or_else(codesphere_agent_id).ok_or_else(|| {
+        AgentError::invalid(
+            "agent_id",
+            "nicht gesetzt (Config-Datei, AGENT_ID, --agent-id oder CS_SERVER/CS_REPLICA)",
+        )
+    })?;
+    if !is_valid_name(&agent_id, &[]) {
+        return Err(AgentError::invalid(
+            "agent_id",
+            format!(
+                "'{}' darf nur Buchstaben, Ziffern, '-', '_' und '.' enthalten",
+                agent_id
+            ),
+        ));
+    }
+
+    let mut labels = layer.labels.unwrap_or_default();
+    if let Some(label) = labels
+        .iter()
+        .find(|label| !is_valid_name(label, &[':', '=']))
+    {
+        return Err(AgentError::invalid(
+            "labels",
+            format!(
+                "'{}' darf nur Buchstaben, Ziffern, '-', '_', '.', ':' und '=' enthalten",
+                label
+            ),
+        ));
+    }
+    labels.sort();
+    labels.dedup();
+
+    let workdir = layer
+        .workdir
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_WORKDIR));
+    if workdir.exists() && !workdir.is_dir() {
+        return Err(AgentError::invalid(
+            "workdir",
+            format!("{} ist kein Verzeichnis", workdir.display()),
+        ));
+    }
+
+    let concurrency = layer.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+    if concurrency == 0 {
+        return Err(AgentError::invalid("concurrency", "muss mindestens 1 sein"));
+    }
+
+    if layer.heartbeat_interval_secs == Some(0) {
+        return Err(AgentError::invalid(
+            "heartbeat_interval_secs",
+            "muss mindestens 1 sein",
+        ));
+    }
+
+    let health_port = layer.health_port.unwrap_or(DEFAULT_HEALTH_PORT);
+    if health_port == 0 {
+        return Err(AgentError::invalid("health_port", "darf nicht 0 sein"));
+    }
+
+    let drain_timeout_secs = layer
+        .drain_timeout_secs
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT_SECS);
+
+    let server_url = layer
+        .server_url
+        .unwrap_or_else(|| DEFAULT_SERVER_URL.to_string());
+    let tls_config = build_tls_config(&layer.tls)?;
+    let is_https = server_url.starts_with("https://");
+    if !is_https && !server_url.starts_with("http://") {
+        return Err(AgentError::invalid(
+            "server_url",
+            format!("'{}' muss mit http:// oder https:// beginnen", server_url),
+        ));
+    }
+    if tls_config.is_some() && !is_https {
+        return Err(AgentError::invalid(
+            "server_url",
+            "TLS ist konfiguriert, die URL muss mit https:// beginnen",
+        ));
+    }
+
+    let reconnect = resolve_reconnect(&layer.reconnect)?;
+
+    let log_level = layer
+        .logging
+        .level
+        .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+    if let Err(e) = EnvFilter::try_new(&log_level) {
+        return Err(AgentError::invalid(
+            "logging.level",
+            format!("'{}': {}", log_level, e),
+        ));
+    }
+    if let Some(endpoint) = &layer.otel.endpoint {
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(AgentError::invalid(
+                "otel.endpoint",
+                format!("'{}' muss mit http:// oder https:// beginnen", endpoint),
+            ));
+        }
+    }
+
+    let mut server_endpoint = Endpoint::from_shared(server_url.clone())
+        .map_err(|e| AgentError::invalid("server_url", e.to_string()))?
+        .http2_keep_alive_interval(Duration::from_secs(10));
+    if let Some(tls_config) = tls_config {
+        server_endpoint = server_endpoint.tls_config(tls_config)?;
+    }
 
     let hostname = match hostname::get() {
         Ok(os_string) => os_string
@@ -26,21 +553,220 @@ pub fn load_config() -> Result<AgentConfig, Box<dyn std::error::Error>> {
         Err(_) => "unknown_hostname".to_string(),
     };
 
-    let server_addr = "http://ws-server-71635-server.workspaces:3001";
-    println!("Server Adresse: {}", server_addr);
-
-    let server_endpoint =
-        Endpoint::from_static(server_addr).http2_keep_alive_interval(Duration::from_secs(10));
-
-    let config = AgentConfig {
+    Ok(AgentConfig {
         agent_id,
         hostname,
+        server_url,
         server_endpoint,
-    };
+        labels,
+        workdir,
+        concurrency,
+        heartbeat_interval: layer.heartbeat_interval_secs.map(Duration::from_secs),
+        health_port,
+        drain_timeout: Duration::from_secs(drain_timeout_secs),
+        credential_file: layer
+            .credential_file
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CREDENTIAL_FILE)),
+        enrollment_token: layer.enrollment_token,
+        uses_client_certificate: layer.tls.cert.is_some(),
+        reconnect,
+        logging: LoggingConfig {
+            format: layer.logging.format.unwrap_or_default(),
+            level: log_level,
+        },
+        otel: OtelConfig {
+            endpoint: layer.otel.endpoint,
+            service_name: layer
+                .otel
+                .service_name
+                .unwrap_or_else(|| DEFAULT_OTEL_SERVICE_NAME.to_string()),
+        },
+        config_file: None,
+    })
+}
+
+/// Lädt die Konfiguration. Das Logging ist zu diesem Zeitpunkt noch nicht
+/// eingerichtet, es hängt selbst von der Konfiguration ab.
+pub fn load_config(args: &ConfigArgs) -> Result<AgentConfig> {
+    // Eine explizit angegebene Datei muss existieren, die Default-Datei ist optional
+    let config_file = args
+        .config
+        .clone()
+        .or_else(|| non_empty_env("AGENT_CONFIG").map(PathBuf::from))
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
 
-    println!(
-        "Konfiguration geladen: ID={}, Hostname={}",
-        config.agent_id, config.hostname
-    );
+    let file_layer = match &config_file {
+        Some(path) => ConfigLayer::from_file(path)?,
+        None => ConfigLayer::default(),
+    };
+    let layer = file_layer
+        .merge(ConfigLayer::from_env()?)
+        .merge(ConfigLayer::from_args(args));
+    let mut config = resolve(layer)?;
+    config.config_file = config_file;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer() -> ConfigLayer {
+        ConfigLayer {
+            agent_id: Some("a1".to_string()),
+            ..ConfigLayer::default()
+        }
+    }
+
+    fn invalid_key(layer: ConfigLayer) -> String {
+        match resolve(layer) {
+            Err(AgentError::InvalidConfig { key, .. }) => key,
+            other => panic!("kein Konfigurationsfehler: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cli_overrides_env_overrides_file() {
+        let path = env::temp_dir().join(format!("agent-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+            server_url = "http://file:3001"
+            agent_id = "from-file"
+            labels = ["os=linux", "gpu"]
+            concurrency = 2
+            health_port = 4000
+            "#,
+        )
+        .unwrap();
+        // Der einzige Test, der Env-Vars setzt
+        env::set_var("AGENT_SERVER_URL", "http://env:3001");
+        env::set_var("AGENT_CONCURRENCY", "3");
+        let args = ConfigArgs {
+            config: Some(path.clone()),
+            concurrency: Some(4),
+            ..ConfigArgs::default()
+        };
+        let config = load_config(&args).unwrap();
+
+        env::set_var("AGENT_HEALTH_PORT", "abc");
+        let invalid = load_config(&args);
+        env::remove_var("AGENT_SERVER_URL");
+        env::remove_var("AGENT_CONCURRENCY");
+        env::remove_var("AGENT_HEALTH_PORT");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.server_url, "http://env:3001");
+        assert_eq!(config.agent_id, "from-file");
+        assert_eq!(config.labels, ["gpu", "os=linux"]);
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(config.health_port, 4000);
+        assert_eq!(config.config_file, Some(path));
+        // Fehler in Env-Vars nennen die Variable
+        assert!(matches!(
+            invalid,
+            Err(AgentError::InvalidConfig { key, .. }) if key == "AGENT_HEALTH_PORT"
+        ));
+    }
+
+    #[test]
+    fn defaults_apply_without_settings() {
+        let config = resolve(layer()).unwrap();
+        assert_eq!(config.server_url, DEFAULT_SERVER_URL);
+        assert_eq!(config.concurrency, DEFAULT_CONCURRENCY);
+        assert!(config.labels.is_empty());
+        assert_eq!(config.heartbeat_interval, None);
+    }
+
+    #[test]
+    fn validation_errors_name_the_key() {
+        let cases = [
+            (
+                ConfigLayer {
+                    agent_id: Some("a 1".to_string()),
+                    ..layer()
+                },
+                "agent_id",
+            ),
+            (
+                ConfigLayer {
+                    labels: Some(vec!["gpu!".to_string()]),
+                    ..layer()
+                },
+                "labels",
+            ),
+            (
+                ConfigLayer {
+                    concurrency: Some(0),
+                    ..layer()
+                },
+                "concurrency",
+            ),
+            (
+                ConfigLayer {
+                    heartbeat_interval_secs: Some(0),
+                    ..layer()
+                },
+                "heartbeat_interval_secs",
+            ),
+            (
+                ConfigLayer {
+                    health_port: Some(0),
+                    ..layer()
+                },
+                "health_port",
+            ),
+            (
+                ConfigLayer {
+                    server_url: Some("ftp://server".to_string()),
+                    ..layer()
+                },
+                "server_url",
+            ),
+            (
+                ConfigLayer {
+                    reconnect: ReconnectLayer {
+                        initial_backoff_secs: Some(30),
+                        max_backoff_secs: Some(10),
+                        ..ReconnectLayer::default()
+                    },
+                    ..layer()
+                },
+                "reconnect.max_backoff_secs",
+            ),
+            (
+                ConfigLayer {
+                    reconnect: ReconnectLayer {
+                        multiplier: Some(0.5),
+                        ..ReconnectLayer::default()
+                    },
+                    ..layer()
+                },
+                "reconnect.multiplier",
+            ),
+            (
+                ConfigLayer {
+                    tls: TlsLayer {
+                        cert: Some(PathBuf::from("agent.pem")),
+                        ..TlsLayer::default()
+                    },
+                    ..layer()
+                },
+                "tls.key",
+            ),
+            (
+                ConfigLayer {
+                    logging: LoggingLayer {
+                        level: Some("info,[".to_string()),
+                        ..LoggingLayer::default()
+                    },
+                    ..layer()
+                },
+                "logging.level",
+            ),
+        ];
+        for (layer, key) in cases {
+            assert_eq!(invalid_key(layer), key);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::config::AgentConfig;
+use crate::runner::{EnrollRequest, RunnerServiceClient};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use tonic::transport::Channel;
+use tracing::info;
+
+fn load_credential(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content.trim().to_string()).filter(|c| !c.is_empty())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn store_credential(path: &Path, credential: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, credential)?;
+
+    // Das Credential ist ein Secret und soll nur für den Agent-User lesbar sein
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// Liefert das gespeicherte Credential oder tauscht den Enrollment-Token
+/// beim Server gegen ein neues ein und speichert es lokal. Mit mTLS reicht das
+/// Client-Zertifikat, dann kann das Credential fehlen.
+pub async fn ensure_credential(
+    config: &AgentConfig,
+    client: &mut RunnerServiceClient<Channel>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if let Some(credential) = load_credential(&config.credential_file)? {
+        return Ok(Some(credential));
+    }
+    if config.enrollment_token.is_none() && config.uses_client_certificate {
+        return Ok(None);
+    }
+
+    let enrollment_token = config.enrollment_token.clone().ok_or_else(|| {
+        format!(
+            "No credential in {} and AGENT_ENROLLMENT_TOKEN is not set",
+            config.credential_file.display()
+        )
+    })?;
+
+    info!(agent_id = %config.agent_id, "No credential found, enrolling agent...");
+    let response = client
+        .enroll(EnrollRequest {
+            enrollment_token,
+            agent_id: config.agent_id.clone(),
+            hostname: config.hostname.clone(),
+        })
+        .await?
+        .into_inner();
+
+    store_credential(&config.credential_file, &response.credential)?;
+    info!(
+        "Enrollment successful, credential stored in {}.",
+        config.credential_file.display()
+    );
+    Ok(Some(response.credential))
+}
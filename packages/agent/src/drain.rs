@@ -0,0 +1,77 @@
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Betriebsmodus des Agents. Die Modi werden nur in dieser Reihenfolge durchlaufen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrainMode {
+    /// Nimmt Jobs an.
+    Active,
+    /// Nimmt keine neuen Jobs an, bleibt aber verbunden (Drain-Befehl vom Server).
+    Draining,
+    /// Beendet laufende Jobs und fährt danach herunter (SIGTERM/SIGINT).
+    Stopping,
+}
+
+/// Gemeinsamer Drain-Zustand von Signal-Handler und gRPC-Session.
+#[derive(Clone, Debug)]
+pub struct Drain {
+    tx: Arc<watch::Sender<DrainMode>>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drain {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(DrainMode::Active);
+        Drain { tx: Arc::new(tx) }
+    }
+
+    pub fn mode(&self) -> DrainMode {
+        *self.tx.borrow()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.mode() >= DrainMode::Draining
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.mode() == DrainMode::Stopping
+    }
+
+    /// Keine neuen Jobs mehr annehmen.
+    pub fn drain(&self) {
+        self.advance(DrainMode::Draining);
+    }
+
+    /// Laufende Jobs beenden und danach herunterfahren.
+    pub fn stop(&self) {
+        self.advance(DrainMode::Stopping);
+    }
+
+    fn advance(&self, mode: DrainMode) {
+        self.tx.send_if_modified(|current| {
+            if *current < mode {
+                *current = mode;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<DrainMode> {
+        self.tx.subscribe()
+    }
+
+    /// Wartet, bis der Agent herunterfahren soll.
+    pub async fn stopped(&self) {
+        let mut rx = self.subscribe();
+        let _ = rx.wait_for(|mode| *mode == DrainMode::Stopping).await;
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AgentError {
+    /// `key` ist der Name in der Config-Datei, die Env-Var oder das CLI-Flag,
+    /// aus dem der ungültige Wert stammt.
+    #[error("Ungültige Konfiguration für '{key}': {message}")]
+    InvalidConfig { key: String, message: String },
+
+    #[error("Config-Datei {} konnte nicht gelesen werden: {source}", path.display())]
+    ConfigFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Config-Datei {} ist ungültig: {source}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("Transport-Fehler: {0}")]
+    Transport(#[from] tonic::transport::Error),
+
+    #[error("OTLP-Export konnte nicht eingerichtet werden: {0}")]
+    Otel(#[from] opentelemetry::trace::TraceError),
+}
+
+impl AgentError {
+    pub fn invalid(key: impl Into<String>, message: impl Into<String>) -> Self {
+        AgentError::InvalidConfig {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, AgentError>;
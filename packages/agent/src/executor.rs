@@ -0,0 +1,347 @@
+//! Führt einen Job aus: Checkout des Repositories in ein eigenes Verzeichnis,
+//! danach die Befehle nacheinander per Shell. Ausgaben gehen Zeile für Zeile
+//! als Log an den Server, jeder Schritt meldet Beginn und Ende.
+
+use crate::jobs::{step_log_message, step_result};
+use crate::runner::{AgentRequest, FailureReason, LogStream, RunJob, StepResult, StepStatus};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{info, info_span, warn, Instrument};
+
+/// Ergebnis eines Jobs, wie es an den Server geht.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobOutcome {
+    pub success: bool,
+    pub failure_reason: FailureReason,
+    pub message: String,
+}
+
+impl JobOutcome {
+    pub fn succeeded() -> Self {
+        JobOutcome {
+            success: true,
+            failure_reason: FailureReason::Unspecified,
+            message: String::new(),
+        }
+    }
+
+    pub fn failed(reason: FailureReason, message: impl Into<String>) -> Self {
+        JobOutcome {
+            success: false,
+            failure_reason: reason,
+            message: message.into(),
+        }
+    }
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Arbeitsverzeichnis eines Jobs. Es wird auch bei einem Abbruch entfernt,
+/// wenn der Task des Jobs verworfen wird.
+struct Workspace(PathBuf);
+
+impl Workspace {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        // Reste eines früheren Laufs, etwa nach einem Absturz des Agents
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        Ok(Workspace(path))
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.0) {
+            warn!(
+                "Arbeitsverzeichnis {} nicht entfernt: {}",
+                self.0.display(),
+                e
+            );
+        }
+    }
+}
+
+enum ProcessEnd {
+    Exited(ExitStatus),
+    TimedOut,
+}
+
+/// Exit-Code und Signal eines beendeten Prozesses.
+fn exit_details(status: &ExitStatus) -> (Option<i32>, Option<i32>) {
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(status);
+    #[cfg(not(unix))]
+    let signal = None;
+    (status.code(), signal)
+}
+
+fn describe(status: &ExitStatus) -> String {
+    match exit_details(status) {
+        (Some(code), _) => format!("Exit-Code {}", code),
+        (None, Some(signal)) => format!("Signal {}", signal),
+        (None, None) => "unbekanntem Status".to_string(),
+    }
+}
+
+fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+    #[cfg(not(unix))]
+    {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    }
+}
+
+/// Schickt die Ausgabe eines Prozesses zeilenweise als Log an den Server,
+/// getrennt nach stdout und stderr und mit dem Zeitpunkt jeder Zeile.
+async fn forward_output(
+    stdout: impl AsyncRead + Unpin,
+    stderr: impl AsyncRead + Unpin,
+    job_id: &str,
+    step_index: Option<u32>,
+    tx: &mpsc::Sender<AgentRequest>,
+) {
+    let mut stdout = BufReader::new(stdout);
+    let mut stderr = BufReader::new(stderr);
+    let (mut out_line, mut err_line) = (Vec::new(), Vec::new());
+    let (mut out_done, mut err_done) = (false, false);
+    while !(out_done && err_done) {
+        // read_until behält angefangene Zeilen im Puffer, auch wenn der andere Zweig gewinnt
+        let (line, stream) = tokio::select! {
+            read = stdout.read_until(b'\n', &mut out_line), if !out_done => {
+                if matches!(read, Ok(0) | Err(_)) {
+                    out_done = true;
+                }
+                (&mut out_line, LogStream::Stdout)
+            }
+            read = stderr.read_until(b'\n', &mut err_line), if !err_done => {
+                if matches!(read, Ok(0) | Err(_)) {
+                    err_done = true;
+                }
+                (&mut err_line, LogStream::Stderr)
+            }
+        };
+        if line.is_empty() {
+            continue;
+        }
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches(['\n', '\r']).to_string();
+        line.clear();
+        let message = step_log_message(job_id, step_index, stream, now_ms(), text);
+        let _ = tx.send(message).await;
+    }
+}
+
+/// Startet einen Prozess im Arbeitsverzeichnis und wartet bis zu seinem Ende
+/// oder der Frist des Jobs. Bei einem Abbruch wird der Prozess beendet.
+async fn run_process(
+    mut command: Command,
+    dir: &Path,
+    deadline: Option<Instant>,
+    job_id: &str,
+    step_index: Option<u32>,
+    tx: &mpsc::Sender<AgentRequest>,
+) -> io::Result<ProcessEnd> {
+    command
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let run = async {
+        forward_output(stdout, stderr, job_id, step_index, tx).await;
+        child.wait().await
+    };
+    let result = match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, run).await.ok(),
+        None => Some(run.await),
+    };
+    match result {
+        Some(status) => Ok(ProcessEnd::Exited(status?)),
+        None => {
+            let _ = child.kill().await;
+            Ok(ProcessEnd::TimedOut)
+        }
+    }
+}
+
+/// Meldet die Befehle ab `from` als übersprungen.
+async fn skip_steps(job: &RunJob, from: usize, tx: &mpsc::Sender<AgentRequest>) {
+    for (index, command) in job.commands.iter().enumerate().skip(from) {
+        let step = StepResult {
+            job_id: job.job_id.clone(),
+            index: index as u32,
+            command: command.clone(),
+            status: StepStatus::Skipped.into(),
+            ..StepResult::default()
+        };
+        let _ = tx.send(step_result(step)).await;
+    }
+}
+
+async fn checkout(
+    job: &RunJob,
+    dir: &Path,
+    deadline: Option<Instant>,
+    tx: &mpsc::Sender<AgentRequest>,
+) -> Result<(), JobOutcome> {
+    let mut git = Command::new("git");
+    git.args(["clone", "--quiet", "--", &job.repository_url, "."]);
+    match run_process(git, dir, deadline, &job.job_id, None, tx).await {
+        Ok(ProcessEnd::Exited(status)) if status.success() => Ok(()),
+        Ok(ProcessEnd::Exited(status)) => Err(JobOutcome::failed(
+            FailureReason::CheckoutFailed,
+            format!("git clone endete mit {}", describe(&status)),
+        )),
+        Ok(ProcessEnd::TimedOut) => Err(JobOutcome::failed(
+            FailureReason::Timeout,
+            "Zeitlimit beim Checkout überschritten",
+        )),
+        Err(e) => Err(JobOutcome::failed(
+            FailureReason::CheckoutFailed,
+            format!("git konnte nicht gestartet werden: {}", e),
+        )),
+    }
+}
+
+/// Führt einen Befehl aus und meldet Beginn und Ende des Schritts. Liefert
+/// `Err` mit dem Ergebnis des Jobs, wenn der Schritt nicht erfolgreich war.
+async fn run_step(
+    job: &RunJob,
+    index: usize,
+    dir: &Path,
+    deadline: Option<Instant>,
+    tx: &mpsc::Sender<AgentRequest>,
+) -> Result<(), JobOutcome> {
+    let command = &job.commands[index];
+    let mut step = StepResult {
+        job_id: job.job_id.clone(),
+        index: index as u32,
+        command: command.clone(),
+        status: StepStatus::Running.into(),
+        started_at_ms: now_ms(),
+        ..StepResult::default()
+    };
+    let _ = tx.send(step_result(step.clone())).await;
+
+    let started = Instant::now();
+    let end = run_process(
+        shell(command),
+        dir,
+        deadline,
+        &job.job_id,
+        Some(index as u32),
+        tx,
+    )
+    .await;
+    step.finished_at_ms = now_ms();
+    step.duration_ms = started.elapsed().as_millis() as u64;
+    let result = match end {
+        Ok(ProcessEnd::Exited(status)) => {
+            (step.exit_code, step.signal) = exit_details(&status);
+            if status.success() {
+                step.status = StepStatus::Success.into();
+                Ok(())
+            } else {
+                step.status = StepStatus::Failed.into();
+                Err(JobOutcome::failed(
+                    FailureReason::CommandFailed,
+                    format!(
+                        "Schritt {} ('{}') endete mit {}",
+                        index,
+                        command,
+                        describe(&status)
+                    ),
+                ))
+            }
+        }
+        Ok(ProcessEnd::TimedOut) => {
+            step.status = StepStatus::TimedOut.into();
+            Err(JobOutcome::failed(
+                FailureReason::Timeout,
+                format!(
+                    "Zeitlimit in Schritt {} ('{}') überschritten",
+                    index, command
+                ),
+            ))
+        }
+        Err(e) => {
+            step.status = StepStatus::Failed.into();
+            Err(JobOutcome::failed(
+                FailureReason::CommandFailed,
+                format!(
+                    "Schritt {} ('{}') konnte nicht gestartet werden: {}",
+                    index, command, e
+                ),
+            ))
+        }
+    };
+    let _ = tx.send(step_result(step)).await;
+    result
+}
+
+/// Führt einen Job im Verzeichnis `workdir/<job_id>` aus.
+pub async fn run_job(job: RunJob, workdir: PathBuf, tx: mpsc::Sender<AgentRequest>) -> JobOutcome {
+    info!("Starte Job mit {} Befehl(en)", job.commands.len());
+    let deadline = (job.timeout_secs > 0)
+        .then(|| Instant::now() + Duration::from_secs(job.timeout_secs.into()));
+    let workspace = match Workspace::create(workdir.join(&job.job_id)) {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            skip_steps(&job, 0, &tx).await;
+            return JobOutcome::failed(
+                FailureReason::CheckoutFailed,
+                format!("Arbeitsverzeichnis konnte nicht angelegt werden: {}", e),
+            );
+        }
+    };
+
+    // Ohne Repository laufen die Befehle in einem leeren Verzeichnis
+    if !job.repository_url.is_empty() {
+        let span = info_span!("checkout", repository_url = %job.repository_url);
+        if let Err(outcome) = checkout(&job, &workspace.0, deadline, &tx)
+            .instrument(span)
+            .await
+        {
+            skip_steps(&job, 0, &tx).await;
+            return outcome;
+        }
+    }
+
+    for (index, command) in job.commands.iter().enumerate() {
+        let span = info_span!("step", index, command = %command);
+        if let Err(outcome) = run_step(&job, index, &workspace.0, deadline, &tx)
+            .instrument(span)
+            .await
+        {
+            skip_steps(&job, index + 1, &tx).await;
+            info!("Job fehlgeschlagen: {}", outcome.message);
+            return outcome;
+        }
+    }
+    info!("Job erfolgreich");
+    JobOutcome::succeeded()
+}
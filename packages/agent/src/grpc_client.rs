@@ -1,86 +1,566 @@
+use crate::backoff::Backoff;
 use crate::config::AgentConfig; // Importiere die Config-Struktur
-use crate::runner::{AgentRequest, Heartbeat, Payload, RegisterAgent, RunnerServiceClient};
+use crate::credentials::ensure_credential;
+use crate::drain::Drain;
+use crate::drain::DrainMode;
+use crate::executor::JobOutcome;
+use crate::jobs::{job_result, log_message, JobRegistry, RunningJobs};
+use crate::outbox::Outbox;
+use crate::protocol::{
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, SESSION_RESUME_VERSION, TRACE_ID_METADATA,
+};
+use crate::runner::{
+    AgentRequest, CommandPayload, Draining, FailureReason, Heartbeat, Payload, RegisterAck,
+    RegisterAgent, RunnerServiceClient, ServerCommand,
+};
+use crate::status::AgentState;
+use crate::telemetry::{TelemetryCollector, AGENT_VERSION};
 
+use std::path::PathBuf;
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio::time::timeout;
+use tokio::time::{timeout, Instant};
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::Streaming;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-async fn run_agent_session(config: AgentConfig) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Versuche, Server zu kontaktieren...");
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wie eine Session geendet hat, wenn nicht durch einen Fehler.
+enum SessionEnd {
+    Disconnected,
+    /// Der Agent fährt nach SIGTERM/SIGINT herunter, alle Jobs sind beendet.
+    Stopped,
+    /// Der Server fährt geordnet herunter und nennt die Wartezeit bis zum Reconnect.
+    ServerShutdown {
+        reconnect_after: Duration,
+    },
+}
+
+/// Zustand, der eine Session überdauert: laufende Jobs und die Logs und
+/// Ergebnisse, die der Server noch nicht bestätigt hat.
+struct Worker {
+    jobs: RunningJobs,
+    /// Über diesen Kanal senden die Jobs ihre Logs, auch ohne Verbindung.
+    output_tx: mpsc::Sender<AgentRequest>,
+    output_rx: mpsc::Receiver<AgentRequest>,
+    outbox: Outbox,
+    /// Session aus dem letzten RegisterAck, leer ohne Session.
+    session_id: String,
+    /// Der Server der aktuellen Session bestätigt Nachrichten mit einem Ack.
+    acks: bool,
+    /// Frist für die laufenden Jobs, sobald der Agent herunterfährt. Sie gilt
+    /// über Reconnects hinweg, auch wenn der Server gerade nicht erreichbar ist.
+    stop_deadline: Option<Instant>,
+}
+
+impl Worker {
+    fn new(registry: JobRegistry, workdir: PathBuf, concurrency: usize) -> Self {
+        let (output_tx, output_rx) = mpsc::channel(128);
+        Worker {
+            jobs: RunningJobs::new(registry, workdir, concurrency),
+            output_tx,
+            output_rx,
+            outbox: Outbox::default(),
+            session_id: String::new(),
+            acks: true,
+            stop_deadline: None,
+        }
+    }
+
+    fn stop_deadline(&mut self, drain_timeout: Duration) -> Instant {
+        *self
+            .stop_deadline
+            .get_or_insert_with(|| Instant::now() + drain_timeout)
+    }
+
+    /// Keine laufenden Jobs und nichts mehr, das der Server bestätigen muss.
+    fn is_idle(&self) -> bool {
+        self.jobs.is_empty() && self.outbox.unacked().next().is_none()
+    }
+
+    /// Übernimmt die bereits gesendeten Logs der Jobs und danach das Ergebnis
+    /// eines beendeten Jobs in die Outbox, damit die Reihenfolge erhalten bleibt.
+    fn record_result(&mut self, job_id: &str, outcome: &JobOutcome) -> Vec<AgentRequest> {
+        let mut requests = Vec::new();
+        while let Ok(request) = self.output_rx.try_recv() {
+            requests.push(self.outbox.push(request));
+        }
+        requests.push(self.outbox.push(job_result(job_id, outcome)));
+        requests
+    }
+
+    /// Sendet bereits in der Outbox erfasste Nachrichten. Ein Server ohne Acks
+    /// bekommt jede Nachricht nur einmal.
+    async fn send(
+        &mut self,
+        tx: &mpsc::Sender<AgentRequest>,
+        requests: Vec<AgentRequest>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for request in requests {
+            let seq = request.seq;
+            tx.send(request).await?;
+            if !self.acks {
+                self.outbox.ack(seq);
+            }
+        }
+        Ok(())
+    }
+
+    /// Jobs, die der Agent bei der Registrierung meldet.
+    fn job_ids(&self) -> Vec<String> {
+        let mut job_ids: Vec<String> = self
+            .jobs
+            .job_ids()
+            .chain(self.outbox.unconfirmed_job_ids())
+            .map(str::to_string)
+            .collect();
+        job_ids.sort();
+        job_ids.dedup();
+        job_ids
+    }
+
+    /// Wartet ohne Verbindung bis zum nächsten Versuch und sammelt dabei die
+    /// Logs und Ergebnisse der weiterlaufenden Jobs. Fährt der Agent herunter,
+    /// versucht er es bis zur Frist weiter, damit die Jobs ihre Ergebnisse
+    /// noch melden können. Liefert `false`, wenn der Agent jetzt enden soll.
+    async fn wait_offline(
+        &mut self,
+        delay: Duration,
+        drain: &Drain,
+        drain_timeout: Duration,
+    ) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            let stop_deadline = drain
+                .is_stopping()
+                .then(|| self.stop_deadline(drain_timeout));
+            if stop_deadline.is_some() && self.is_idle() {
+                return false;
+            }
+            tokio::select! {
+                _ = &mut sleep => return true,
+                _ = drain.stopped(), if stop_deadline.is_none() => {}
+                _ = tokio::time::sleep_until(stop_deadline.unwrap_or_else(Instant::now)),
+                    if stop_deadline.is_some() => {
+                    self.abandon_jobs();
+                    return false;
+                }
+                Some(request) = self.output_rx.recv() => {
+                    self.outbox.push(request);
+                }
+                Some((job_id, outcome)) = self.jobs.join_next() => {
+                    self.record_result(&job_id, &outcome);
+                }
+            }
+        }
+    }
+
+    /// Die Frist ist abgelaufen, ohne dass der Server erreichbar war.
+    fn abandon_jobs(&mut self) {
+        if !self.jobs.is_empty() {
+            warn!(
+                "Keine Verbindung zum Server, breche {} Job(s) ab.",
+                self.jobs.len()
+            );
+            self.jobs.cancel_all();
+        }
+    }
+}
+
+async fn run_agent_session(
+    config: AgentConfig,
+    drain: Drain,
+    worker: &mut Worker,
+    state: &AgentState,
+    trace_id: &str,
+) -> Result<SessionEnd, Box<dyn std::error::Error>> {
+    info!("Versuche, Server zu kontaktieren...");
     let connect_future = RunnerServiceClient::connect(config.server_endpoint.clone());
-    let connect_timeout = Duration::from_secs(5);
+    let connect_timeout = config.reconnect.connect_timeout;
 
     let mut client = match timeout(connect_timeout, connect_future).await {
         Ok(Ok(client)) => client,
         Ok(Err(e)) => return Err(format!("Verbindungsfehler: {}", e).into()),
-        Err(_) => return Err("Verbindungs-Timeout nach 5 Sekunden".into()),
+        Err(_) => {
+            return Err(format!(
+                "Verbindungs-Timeout nach {} Sekunden",
+                connect_timeout.as_secs()
+            )
+            .into())
+        }
     };
-    println!("Erfolgreich mit gRPC Server verbunden!");
+    info!("Erfolgreich mit gRPC Server verbunden!");
+
+    let credential = ensure_credential(&config, &mut client).await?;
 
     let (tx, rx) = mpsc::channel(128);
-    let outbound = ReceiverStream::new(rx);
+    let mut outbound = tonic::Request::new(ReceiverStream::new(rx));
+    outbound
+        .metadata_mut()
+        .insert(TRACE_ID_METADATA, trace_id.parse()?);
+    if let Some(credential) = credential {
+        outbound
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {}", credential).parse()?);
+    }
     let response = client.communicate(outbound).await?;
     let mut inbound = response.into_inner();
 
     tx.send(AgentRequest {
+        seq: 0,
         payload: Some(Payload::Register(RegisterAgent {
             agent_id: config.agent_id.clone(),
             hostname: config.hostname.clone(),
+            heartbeat_interval_secs: config
+                .heartbeat_interval
+                .map_or(0, |interval| interval.as_secs() as u32),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            agent_version: AGENT_VERSION.to_string(),
+            resume_session_id: worker.session_id.clone(),
+            job_ids: worker.job_ids(),
+            labels: config.labels.clone(),
+            concurrency: config.concurrency as u32,
         })),
     })
     .await?;
-    println!("Registration sent to Worker {}.", config.agent_id);
+    debug!("Registration sent.");
+    let ack = await_register_ack(&mut inbound).await?;
+    let settings = ack.settings.unwrap_or_default();
+    info!(
+        "Registrierung bestätigt: Protokoll {}, Heartbeat alle {}s, offline nach {}s ohne Heartbeat.",
+        ack.protocol_version, settings.heartbeat_interval_secs, settings.offline_after_secs
+    );
+
+    // Nach einem Resume fehlt dem Server nur, was nach last_seq kam. In einer
+    // neuen Session zählen nur noch die Ergebnisse, die Logs sind verloren.
+    worker.acks = ack.protocol_version >= SESSION_RESUME_VERSION;
+    if ack.resumed {
+        worker.outbox.ack(ack.last_seq);
+    } else {
+        worker.outbox.discard_logs();
+    }
+    worker.session_id = ack.session_id;
+    state.connected(&worker.session_id, ack.protocol_version, ack.resumed);
+    let replay: Vec<AgentRequest> = worker.outbox.unacked().cloned().collect();
+    if ack.resumed {
+        info!(
+            "Session {} fortgesetzt, sende {} Nachricht(en) erneut.",
+            worker.session_id,
+            replay.len()
+        );
+    } else if !replay.is_empty() {
+        info!("Sende {} ausstehende(s) Ergebnis(se) erneut.", replay.len());
+    }
+    worker.send(&tx, replay).await?;
 
+    let running_jobs = worker.jobs.registry();
+    let mut telemetry = TelemetryCollector::new(&config.workdir);
     let tx_clone = tx.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(10));
-        loop {
-            interval.tick().await;
-            let heartbeat_msg = AgentRequest {
-                payload: Some(Payload::Heartbeat(Heartbeat {
-                    timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs(),
-                })),
-            };
-            if tx_clone.send(heartbeat_msg).await.is_err() {
-                eprintln!("Heartbeat failed, connection closed.");
-                break;
+    // Das Intervall legt der Server bei der Registrierung fest
+    let heartbeat_interval = Duration::from_secs(settings.heartbeat_interval_secs.max(1).into());
+    let heartbeat_task = tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(heartbeat_interval);
+            loop {
+                interval.tick().await;
+                let heartbeat_msg = AgentRequest {
+                    seq: 0,
+                    payload: Some(Payload::Heartbeat(Heartbeat {
+                        timestamp: std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                        telemetry: Some(telemetry.collect(running_jobs.lock().unwrap().len())),
+                    })),
+                };
+                if tx_clone.send(heartbeat_msg).await.is_err() {
+                    warn!("Heartbeat failed, connection closed.");
+                    break;
+                }
             }
         }
-    });
-
-    println!("Worker {} is waiting for a job...", config.agent_id);
-    while let Some(result) = inbound.next().await {
-        match result {
-            Ok(command) => {
-                println!("\nGot command: {:?}", command);
-                println!("Simulate Job execution...");
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                println!("Simulation successful!!");
+        .in_current_span(),
+    );
+
+    info!("Worker is waiting for a job...");
+    let mut drain_rx = drain.subscribe();
+    let mut announced = DrainMode::Active;
+    let mut session_end = SessionEnd::Disconnected;
+    loop {
+        // Auch nach einem Reconnect soll der Server wissen, dass der Agent drained
+        if drain.mode() > announced {
+            announced = drain.mode();
+            announce_draining(&tx, &drain).await?;
+        }
+        if drain.is_stopping() {
+            let deadline = worker.stop_deadline(config.drain_timeout);
+            finish_jobs(worker, &tx, deadline).await?;
+            session_end = SessionEnd::Stopped;
+            break;
+        }
+
+        tokio::select! {
+            result = inbound.next() => match result {
+                Some(Ok(ServerCommand {
+                    payload: Some(CommandPayload::Shutdown(notice)),
+                })) => {
+                    info!(
+                        "Server fährt herunter ({}), neuer Verbindungsversuch in {}s.",
+                        notice.reason, notice.reconnect_after_secs
+                    );
+                    session_end = SessionEnd::ServerShutdown {
+                        reconnect_after: Duration::from_secs(notice.reconnect_after_secs.into()),
+                    };
+                    break;
+                }
+                Some(Ok(ServerCommand {
+                    payload: Some(CommandPayload::Drain(notice)),
+                })) => {
+                    info!("Drain angefordert ({}), nehme keine neuen Jobs mehr an.", notice.reason);
+                    drain.drain();
+                }
+                Some(Ok(ServerCommand {
+                    payload: Some(CommandPayload::Job(job)),
+                })) => {
+                    if drain.is_draining() {
+                        warn!(job_id = %job.job_id, "Job abgelehnt, Agent nimmt keine neuen Jobs an.");
+                        let outcome = JobOutcome::failed(
+                            FailureReason::Cancelled,
+                            "Agent is draining, job rejected",
+                        );
+                        let requests = vec![
+                            worker.outbox.push(log_message(&job.job_id, outcome.message.clone())),
+                            worker.outbox.push(job_result(&job.job_id, &outcome)),
+                        ];
+                        worker.send(&tx, requests).await?;
+                    } else {
+                        info!(job_id = %job.job_id, "Got job");
+                        worker.jobs.spawn(job, worker.output_tx.clone());
+                    }
+                }
+                Some(Ok(ServerCommand {
+                    payload: Some(CommandPayload::Cancel(cancel)),
+                })) => {
+                    if !worker.jobs.cancel(&cancel.job_id) {
+                        info!(job_id = %cancel.job_id, "Job läuft nicht, nichts abzubrechen.");
+                    }
+                }
+                Some(Ok(ServerCommand {
+                    payload: Some(CommandPayload::Ack(ack)),
+                })) => {
+                    // Der Server bestätigt mit jedem Heartbeat den Stand der Session
+                    worker.outbox.ack(ack.seq);
+                    state.heartbeat_acked();
+                }
+                Some(Ok(command)) => debug!("Got command: {:?}", command),
+                Some(Err(err)) => {
+                    warn!("Connection to Server failed: {}", err);
+                    break;
+                }
+                None => break,
+            },
+            Some(request) = worker.output_rx.recv() => {
+                let request = worker.outbox.push(request);
+                worker.send(&tx, vec![request]).await?;
             }
-            Err(err) => {
-                eprintln!("Connection to Server failed: {}", err);
-                break;
+            Some((job_id, outcome)) = worker.jobs.join_next() => {
+                let requests = worker.record_result(&job_id, &outcome);
+                worker.send(&tx, requests).await?;
             }
+            Ok(()) = drain_rx.changed() => {}
+        }
+    }
+
+    // Der Heartbeat hält sonst den ausgehenden Stream offen und der Server
+    // wartet beim Herunterfahren vergeblich auf das Ende der Verbindung
+    heartbeat_task.abort();
+    if matches!(session_end, SessionEnd::Stopped) {
+        // Ausstehende Logs und Ergebnisse noch zustellen, bevor der Prozess endet
+        drop(tx);
+        let _ = timeout(FLUSH_TIMEOUT, async {
+            while inbound.next().await.is_some() {}
+        })
+        .await;
+    }
+    info!("Connection ended.");
+    Ok(session_end)
+}
+
+/// Wartet auf die Antwort des Servers auf die Registrierung und prüft die
+/// ausgehandelte Protokollversion.
+async fn await_register_ack(
+    inbound: &mut Streaming<ServerCommand>,
+) -> Result<RegisterAck, Box<dyn std::error::Error>> {
+    let first = match timeout(REGISTER_TIMEOUT, inbound.next()).await {
+        Ok(Some(result)) => result?,
+        Ok(None) => return Err("Server hat die Verbindung bei der Registrierung beendet".into()),
+        Err(_) => {
+            return Err(format!(
+                "Keine Antwort auf die Registrierung nach {}s, Server zu alt?",
+                REGISTER_TIMEOUT.as_secs()
+            )
+            .into())
         }
+    };
+    let Some(CommandPayload::RegisterAck(ack)) = first.payload else {
+        return Err(format!("Erwartete RegisterAck, erhalten: {:?}", first.payload).into());
+    };
+    if !ack.accepted {
+        return Err(format!(
+            "Server lehnt den Agent ab (Server-Protokoll {}): {}",
+            ack.protocol_version, ack.rejection_reason
+        )
+        .into());
     }
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&ack.protocol_version) {
+        return Err(format!(
+            "Server hat Protokoll {} ausgehandelt, Agent spricht {} bis {}",
+            ack.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        )
+        .into());
+    }
+    Ok(ack)
+}
 
-    println!("Connection ended.");
+async fn announce_draining(
+    tx: &mpsc::Sender<AgentRequest>,
+    drain: &Drain,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reason = if drain.is_stopping() {
+        "agent is shutting down"
+    } else {
+        "drain requested"
+    };
+    tx.send(AgentRequest {
+        seq: 0,
+        payload: Some(Payload::Draining(Draining {
+            reason: reason.to_string(),
+            stopping: drain.is_stopping(),
+        })),
+    })
+    .await?;
     Ok(())
 }
 
-pub async fn run_client_loop(config: AgentConfig) -> Result<(), Box<dyn std::error::Error>> {
+/// Wartet bis zu `deadline` auf die laufenden Jobs und bricht den Rest danach ab.
+/// Die Ergebnisse aller Jobs werden an den Server gemeldet.
+async fn finish_jobs(
+    worker: &mut Worker,
+    tx: &mpsc::Sender<AgentRequest>,
+    deadline: Instant,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if worker.jobs.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Warte bis zu {}s auf {} laufende Job(s)...",
+        deadline.saturating_duration_since(Instant::now()).as_secs(),
+        worker.jobs.len()
+    );
+    let deadline = tokio::time::sleep_until(deadline);
+    tokio::pin!(deadline);
     loop {
-        println!("--- start Worker session for {} ---", config.agent_id);
+        tokio::select! {
+            Some(request) = worker.output_rx.recv() => {
+                let request = worker.outbox.push(request);
+                worker.send(tx, vec![request]).await?;
+            }
+            next = worker.jobs.join_next() => match next {
+                Some((job_id, outcome)) => {
+                    let requests = worker.record_result(&job_id, &outcome);
+                    worker.send(tx, requests).await?;
+                }
+                None => return Ok(()),
+            },
+            _ = &mut deadline => break,
+        }
+    }
+
+    warn!("Frist abgelaufen, breche {} Job(s) ab.", worker.jobs.len());
+    worker.jobs.cancel_all();
+    while let Some((job_id, _)) = worker.jobs.join_next().await {
+        let outcome = JobOutcome::failed(
+            FailureReason::Cancelled,
+            "Job cancelled, agent is shutting down",
+        );
+        let mut requests = vec![worker
+            .outbox
+            .push(log_message(&job_id, outcome.message.clone()))];
+        requests.extend(worker.record_result(&job_id, &outcome));
+        worker.send(tx, requests).await?;
+    }
+    Ok(())
+}
 
-        if let Err(e) = run_agent_session(config.clone()).await {
-            eprintln!("Worker session failed: {}", e);
+/// Zufällige Trace-ID im Format der W3C-Trace-IDs (32 Hex-Zeichen).
+fn new_trace_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+pub async fn run_client_loop(
+    config: AgentConfig,
+    drain: Drain,
+    state: AgentState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Jobs und unbestätigte Nachrichten überdauern einen Reconnect
+    let mut worker = Worker::new(
+        state.jobs(),
+        config.workdir.join("jobs"),
+        config.concurrency,
+    );
+    let mut backoff = Backoff::new(config.reconnect.clone());
+    loop {
+        // Jeder Verbindungsversuch bekommt eine eigene Trace-ID, die auch der Server loggt
+        let trace_id = new_trace_id();
+        let span = info_span!("session", agent_id = %config.agent_id, %trace_id);
+        state.connecting();
+        let started = Instant::now();
+        let result = run_agent_session(
+            config.clone(),
+            drain.clone(),
+            &mut worker,
+            &state,
+            &trace_id,
+        )
+        .instrument(span)
+        .await;
+        // Eine Session, die lange genug hielt, zählt nicht als Fehlversuch
+        if started.elapsed() >= config.reconnect.stable_after {
+            backoff.reset();
+        }
+        let (delay, error) = match result {
+            Ok(SessionEnd::Stopped) => return Ok(()),
+            Ok(SessionEnd::ServerShutdown { reconnect_after }) => {
+                // Der Jitter verteilt die Agents über die Zeit nach dem Neustart
+                backoff.reset();
+                (reconnect_after + backoff.next_delay(), None)
+            }
+            Ok(SessionEnd::Disconnected) => (backoff.next_delay(), None),
+            Err(e) => {
+                error!(%trace_id, "Worker session failed: {}", e);
+                (backoff.next_delay(), Some(e.to_string()))
+            }
+        };
+        if drain.is_stopping() && worker.is_idle() {
+            return Ok(());
         }
 
-        println!("wait 5 seconds...");
-        tokio::time::sleep(Duration::from_secs(5)).await;
+        state.waiting(&backoff, delay, error);
+        info!(
+            "wait {:.1} seconds (attempt {}, backoff up to {}s)...",
+            delay.as_secs_f64(),
+            backoff.failures(),
+            backoff.ceiling().as_secs()
+        );
+        if !worker
+            .wait_offline(delay, &drain, config.drain_timeout)
+            .await
+        {
+            return Ok(());
+        }
     }
 }
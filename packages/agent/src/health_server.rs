@@ -1,16 +1,43 @@
-use axum::{response::Html, routing::get, Router};
+use crate::status::{AgentState, StatusReport};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use tracing::info;
 
-async fn health_check_handler() -> Html<&'static str> {
-    Html("OK")
+/// Der Prozess lebt.
+async fn healthz_handler() -> &'static str {
+    "OK"
 }
 
-pub async fn run_health_server() -> Result<(), Box<dyn std::error::Error>> {
-    let rest_addr = SocketAddr::from(([0, 0, 0, 0], 3002));
-    let rest_app = Router::new().route("/", get(health_check_handler));
+/// Der Agent ist beim Server registriert und kann Jobs annehmen.
+async fn readyz_handler(State(state): State<AgentState>) -> (StatusCode, &'static str) {
+    if state.is_ready() {
+        (StatusCode::OK, "ready")
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not registered with server",
+        )
+    }
+}
+
+async fn status_handler(State(state): State<AgentState>) -> Json<StatusReport> {
+    Json(state.report())
+}
+
+pub async fn run_health_server(
+    port: u16,
+    state: AgentState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rest_addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let rest_app = Router::new()
+        .route("/", get(healthz_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .route("/status", get(status_handler))
+        .with_state(state);
 
-    println!("worker online...");
+    info!(port, "worker online...");
     let listener = TcpListener::bind(rest_addr).await?;
     axum::serve(listener, rest_app.into_make_service()).await?;
     Ok(())
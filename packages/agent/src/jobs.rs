@@ -0,0 +1,168 @@
+use crate::executor::{now_ms, run_job, JobOutcome};
+use crate::otel;
+use crate::runner::{
+    AgentRequest, FailureReason, JobResult, LogMessage, LogStream, Payload, RunJob, StepResult,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{AbortHandle, Id, JoinSet};
+use tracing::{info_span, Instrument};
+
+/// Laufende Jobs mit ihrem Startzeitpunkt (Unix-Sekunden), auch für den
+/// Heartbeat-Task und den Status-Endpunkt lesbar.
+pub type JobRegistry = Arc<Mutex<BTreeMap<String, u64>>>;
+
+/// Die Jobs, die der Agent ausführt. Sie laufen über einen Reconnect hinweg weiter.
+pub struct RunningJobs {
+    tasks: JoinSet<JobOutcome>,
+    job_ids: HashMap<Id, String>,
+    handles: HashMap<String, AbortHandle>,
+    registry: JobRegistry,
+    /// Jeder Job bekommt darunter ein eigenes Verzeichnis.
+    workdir: PathBuf,
+    /// Ein Platz je Job, der gleichzeitig laufen darf. Weitere Jobs warten auf einen freien Platz.
+    slots: Arc<Semaphore>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn log_message(job_id: &str, output: impl Into<String>) -> AgentRequest {
+    step_log_message(job_id, None, LogStream::Unspecified, now_ms(), output)
+}
+
+/// Ausgabe eines Schritts, `None` für Ausgaben außerhalb der Schritte.
+/// `timestamp_ms` ist der Zeitpunkt, zu dem die Zeile gelesen wurde.
+pub fn step_log_message(
+    job_id: &str,
+    step_index: Option<u32>,
+    stream: LogStream,
+    timestamp_ms: u64,
+    output: impl Into<String>,
+) -> AgentRequest {
+    AgentRequest {
+        seq: 0,
+        payload: Some(Payload::Log(LogMessage {
+            job_id: job_id.to_string(),
+            timestamp: timestamp_ms / 1000,
+            output: output.into(),
+            step_index,
+            stream: stream.into(),
+            timestamp_ms,
+        })),
+    }
+}
+
+pub fn step_result(step: StepResult) -> AgentRequest {
+    AgentRequest {
+        seq: 0,
+        payload: Some(Payload::Step(step)),
+    }
+}
+
+pub fn job_result(job_id: &str, outcome: &JobOutcome) -> AgentRequest {
+    AgentRequest {
+        seq: 0,
+        payload: Some(Payload::Result(JobResult {
+            job_id: job_id.to_string(),
+            success: outcome.success,
+            failure_reason: outcome.failure_reason.into(),
+            failure_message: outcome.message.clone(),
+        })),
+    }
+}
+
+impl RunningJobs {
+    pub fn new(registry: JobRegistry, workdir: PathBuf, concurrency: usize) -> Self {
+        RunningJobs {
+            tasks: JoinSet::new(),
+            job_ids: HashMap::new(),
+            handles: HashMap::new(),
+            registry,
+            workdir,
+            slots: Arc::new(Semaphore::new(concurrency)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn job_ids(&self) -> impl Iterator<Item = &str> {
+        self.handles.keys().map(String::as_str)
+    }
+
+    pub fn registry(&self) -> JobRegistry {
+        self.registry.clone()
+    }
+
+    pub fn spawn(&mut self, job: RunJob, tx: mpsc::Sender<AgentRequest>) {
+        let job_id = job.job_id.clone();
+        // Der Span hängt an der Session, in der der Job angenommen wurde
+        let span = info_span!("job", job_id = %job_id, otel.name = "execute");
+        otel::attach_to_job_trace(&span, &job_id);
+        let slots = self.slots.clone();
+        let workdir = self.workdir.clone();
+        let handle = self.tasks.spawn(
+            async move {
+                let _slot = slots.acquire_owned().await;
+                run_job(job, workdir, tx).await
+            }
+            .instrument(span),
+        );
+        self.job_ids.insert(handle.id(), job_id.clone());
+        self.registry
+            .lock()
+            .unwrap()
+            .insert(job_id.clone(), now_secs());
+        self.handles.insert(job_id, handle);
+    }
+
+    /// Wartet auf den nächsten beendeten Job. Abgebrochene Jobs gelten als fehlgeschlagen.
+    /// Liefert `None`, wenn kein Job mehr läuft.
+    pub async fn join_next(&mut self) -> Option<(String, JobOutcome)> {
+        let (id, outcome) = match self.tasks.join_next_with_id().await? {
+            Ok((id, outcome)) => (id, outcome),
+            Err(e) if e.is_cancelled() => (
+                e.id(),
+                JobOutcome::failed(FailureReason::Cancelled, "Job abgebrochen"),
+            ),
+            Err(e) => (
+                e.id(),
+                JobOutcome::failed(FailureReason::Unspecified, e.to_string()),
+            ),
+        };
+        let job_id = self.job_ids.remove(&id).unwrap_or_default();
+        self.handles.remove(&job_id);
+        self.registry.lock().unwrap().remove(&job_id);
+        Some((job_id, outcome))
+    }
+
+    /// Bricht einen Job ab; das Ergebnis kommt danach über [`RunningJobs::join_next`].
+    pub fn cancel(&mut self, job_id: &str) -> bool {
+        match self.handles.get(job_id) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&mut self) {
+        for handle in self.handles.values() {
+            handle.abort();
+        }
+    }
+}
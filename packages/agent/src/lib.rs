@@ -1,4 +1,16 @@
+pub mod backoff;
 pub mod config;
+pub mod credentials;
+pub mod drain;
+pub mod error;
+pub mod executor;
 pub mod grpc_client;
 pub mod health_server;
+pub mod jobs;
+pub mod logging;
+pub mod otel;
+pub mod outbox;
+pub mod protocol;
 pub mod runner;
+pub mod status;
+pub mod telemetry;
@@ -0,0 +1,31 @@
+use crate::config::{LogFormat, LoggingConfig, OtelConfig};
+use crate::error::Result;
+use crate::otel;
+use std::io::IsTerminal;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Richtet die Log-Ausgabe ein. Im JSON-Format enthält jede Zeile die Felder
+/// der aktiven Spans, etwa `trace_id` der Session und `job_id`. Mit einem
+/// OTLP-Endpunkt werden zusätzlich die Spans der Jobs exportiert.
+pub fn init(logging: &LoggingConfig, otel: &OtelConfig) -> Result<()> {
+    // Das Level wurde beim Laden der Konfiguration bereits geprüft
+    let filter = EnvFilter::new(&logging.level);
+    let fmt_layer = match logging.format {
+        // Farben nur im Terminal, nicht in umgeleiteten Logdateien
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .with_ansi(std::io::stdout().is_terminal())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(fmt_layer.with_filter(filter))
+        .with(otel::layer(otel)?)
+        .init();
+    Ok(())
+}
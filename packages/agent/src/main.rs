@@ -1,25 +1,95 @@
-use agent::config::load_config;
+use agent::config::{load_config, ConfigArgs};
+use agent::drain::Drain;
 use agent::grpc_client::run_client_loop;
 use agent::health_server::run_health_server;
+use agent::logging;
+use agent::otel;
+use agent::status::AgentState;
+use clap::Parser;
 use futures_util::future::TryFutureExt;
+use tracing::{error, info, warn};
+
+/// Wartet auf Ctrl+C bzw. SIGTERM.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = load_config()?;
+    let config = match load_config(&ConfigArgs::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = logging::init(&config.logging, &config.otel) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(path) = &config.config_file {
+        info!(path = %path.display(), "Config-Datei geladen");
+    }
+    info!(
+        agent_id = %config.agent_id,
+        hostname = %config.hostname,
+        server_url = %config.server_url,
+        labels = %config.labels.join(", "),
+        concurrency = config.concurrency,
+        "Konfiguration geladen"
+    );
+
+    // Erstes Signal: keine neuen Jobs, laufende beenden. Zweites Signal: sofort beenden.
+    let drain = Drain::new();
+    tokio::spawn({
+        let drain = drain.clone();
+        async move {
+            wait_for_signal().await;
+            info!("Signal empfangen, beende laufende Jobs und fahre herunter...");
+            drain.stop();
+            wait_for_signal().await;
+            warn!("Zweites Signal empfangen, breche sofort ab.");
+            std::process::exit(130);
+        }
+    });
 
-    let health_server_future = run_health_server().map_err(|e| {
-        eprintln!("Health Server crashed: {}", e);
+    let state = AgentState::new(&config, drain.clone());
+    let health_server_future = run_health_server(config.health_port, state.clone()).map_err(|e| {
+        error!("Health Server crashed: {}", e);
         e
     });
 
-    let client_future = run_client_loop(config.clone()).map_err(|e| {
-        eprintln!("gRPC Client loop crashed: {}", e);
+    let client_future = run_client_loop(config.clone(), drain, state).map_err(|e| {
+        error!("gRPC Client loop crashed: {}", e);
         e
     });
 
-    println!("start grpc client and health server...");
-    tokio::try_join!(health_server_future, client_future)?;
+    info!("start grpc client and health server...");
+    // Der Health-Server läuft endlos, der Client endet nach dem Drain
+    tokio::select! {
+        result = health_server_future => result?,
+        result = client_future => result?,
+    }
 
-    println!("Worker shut down.");
+    let _ = tokio::task::spawn_blocking(otel::shutdown).await;
+    info!("Worker shut down.");
     Ok(())
 }
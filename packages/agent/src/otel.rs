@@ -0,0 +1,95 @@
+//! Export der Job-Ausführung als OTLP-Spans. Der Span `execute` eines Jobs und
+//! seine Kinder (`checkout`, `step`) hängen am Wurzel-Span `job`, den der
+//! Server beim Abschluss des Jobs exportiert.
+//!
+//! Trace- und Span-ID der Wurzel leiten beide Seiten gleich aus der Job-ID ab,
+//! siehe `server::otel`.
+
+use crate::config::OtelConfig;
+use crate::error::Result;
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use tracing::{Level, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Layer für den Export, `None` ohne konfigurierten Collector. Nur die Spans
+/// der Jobs werden exportiert, nicht die der Sessions.
+pub fn layer<S>(config: &OtelConfig) -> Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(None);
+    };
+    let tracer =
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(sdktrace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", config.service_name.clone()),
+            ])))
+            .install_batch(runtime::Tokio)?;
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_location(false)
+            .with_threads(false)
+            .with_tracked_inactivity(false)
+            .with_filter(Targets::new().with_target("agent::jobs", Level::INFO)),
+    ))
+}
+
+/// Exportiert die noch gepufferten Spans. Blockiert, daher nicht auf einem
+/// Runtime-Thread aufrufen.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Hängt den Span eines Jobs unter den Wurzel-Span, den der Server für den Job
+/// exportiert. Jobs ohne UUID als ID bekommen einen eigenen Trace.
+pub fn attach_to_job_trace(span: &tracing::Span, job_id: &str) {
+    let Some(trace_id) = job_trace_id(job_id) else {
+        return;
+    };
+    let parent = SpanContext::new(
+        trace_id,
+        job_root_span_id(trace_id),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    span.set_parent(Context::new().with_remote_span_context(parent));
+}
+
+/// Die 128 Bit der Job-UUID, wie auf dem Server.
+fn job_trace_id(job_id: &str) -> Option<TraceId> {
+    let hex: String = job_id.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    TraceId::from_hex(&hex)
+        .ok()
+        .filter(|id| *id != TraceId::INVALID)
+}
+
+/// Die beiden Hälften der Trace-ID per XOR verknüpft, wie auf dem Server.
+fn job_root_span_id(trace_id: TraceId) -> SpanId {
+    let bytes = trace_id.to_bytes();
+    let mut id = [0u8; 8];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = bytes[i] ^ bytes[i + 8];
+    }
+    if id == [0; 8] {
+        id[7] = 1;
+    }
+    SpanId::from_bytes(id)
+}
@@ -0,0 +1,71 @@
+use crate::runner::{AgentRequest, Payload};
+use std::collections::VecDeque;
+use tracing::warn;
+
+/// So viele unbestätigte Nachrichten hält der Agent höchstens vor.
+const CAPACITY: usize = 10_000;
+
+/// Logs und Ergebnisse, die der Server noch nicht bestätigt hat. Jede Nachricht
+/// bekommt eine fortlaufende Sequenznummer; nach einem Reconnect sendet der
+/// Agent alle Nachrichten nach der letzten Bestätigung erneut.
+#[derive(Default)]
+pub struct Outbox {
+    last_seq: u64,
+    unacked: VecDeque<AgentRequest>,
+}
+
+impl Outbox {
+    /// Nummeriert die Nachricht und behält eine Kopie bis zur Bestätigung.
+    pub fn push(&mut self, mut request: AgentRequest) -> AgentRequest {
+        self.last_seq += 1;
+        request.seq = self.last_seq;
+        if self.unacked.len() >= CAPACITY {
+            // Ergebnisse sind wichtiger als Logs, daher zuerst das älteste Log verwerfen
+            let index = self
+                .unacked
+                .iter()
+                .position(|request| matches!(request.payload, Some(Payload::Log(_))))
+                .unwrap_or(0);
+            self.unacked.remove(index);
+            warn!(
+                "Mehr als {} unbestätigte Nachrichten, verwerfe die älteste.",
+                CAPACITY
+            );
+        }
+        self.unacked.push_back(request.clone());
+        request
+    }
+
+    /// Der Server hat alle Nachrichten bis einschließlich `seq` verarbeitet.
+    pub fn ack(&mut self, seq: u64) {
+        while self
+            .unacked
+            .front()
+            .is_some_and(|request| request.seq <= seq)
+        {
+            self.unacked.pop_front();
+        }
+    }
+
+    /// Ohne fortgesetzte Session weiß der Server nicht, welche Logs er schon hat.
+    /// Ergebnisse bleiben erhalten, der Server übernimmt jedes nur einmal.
+    pub fn discard_logs(&mut self) {
+        self.unacked
+            .retain(|request| !matches!(request.payload, Some(Payload::Log(_))));
+    }
+
+    /// Die Nachrichten, die nach einem Reconnect erneut gesendet werden.
+    pub fn unacked(&self) -> impl Iterator<Item = &AgentRequest> {
+        self.unacked.iter()
+    }
+
+    /// Jobs, deren Ergebnis der Server noch nicht bestätigt hat.
+    pub fn unconfirmed_job_ids(&self) -> impl Iterator<Item = &str> {
+        self.unacked
+            .iter()
+            .filter_map(|request| match &request.payload {
+                Some(Payload::Result(result)) => Some(result.job_id.as_str()),
+                _ => None,
+            })
+    }
+}
@@ -0,0 +1,13 @@
+//! Protokollversionen, die dieser Agent spricht, siehe `proto/runner.proto`.
+
+/// Die höchste Version, die der Agent spricht.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Der Agent wartet nach der Registrierung auf das RegisterAck aus Version 2.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+/// Ab dieser Version setzt der Server Sessions nach einem Reconnect fort und
+/// bestätigt Logs und Ergebnisse mit einem Ack.
+pub const SESSION_RESUME_VERSION: u32 = 3;
+/// Metadata-Key, unter dem der Agent die Trace-ID einer Session mitschickt.
+/// Server und Agent loggen sie im Span der Session, so lassen sich die Logs
+/// eines Jobs auf beiden Seiten zusammenführen.
+pub const TRACE_ID_METADATA: &str = "x-trace-id";
@@ -2,3 +2,4 @@ tonic::include_proto!("runner");
 
 pub use agent_request::Payload;
 pub use runner_service_client::RunnerServiceClient;
+pub use server_command::Payload as CommandPayload;
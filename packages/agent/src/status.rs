@@ -0,0 +1,131 @@
+use crate::backoff::{Backoff, ConnectionPhase, ConnectionStatus, ConnectionTracker};
+use crate::config::AgentConfig;
+use crate::drain::{Drain, DrainMode};
+use crate::jobs::JobRegistry;
+use crate::telemetry::AGENT_VERSION;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Die Session seit der letzten Registrierung.
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionStatus {
+    /// Leer, wenn der Server keine Sessions fortsetzen kann.
+    pub session_id: String,
+    pub protocol_version: u32,
+    pub resumed: bool,
+    pub registered_at: u64,
+    /// Der Server bestätigt Heartbeats erst ab Protokollversion 3.
+    pub last_heartbeat_ack_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RunningJob {
+    pub job_id: String,
+    pub started_at: u64,
+}
+
+/// Antwort von `GET /status`.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub agent_id: String,
+    pub version: &'static str,
+    pub started_at: u64,
+    pub ready: bool,
+    pub drain_mode: DrainMode,
+    pub connection: ConnectionStatus,
+    pub session: Option<SessionStatus>,
+    pub jobs: Vec<RunningJob>,
+}
+
+/// Zustand des Agents, den der gRPC-Client pflegt und der Health-Server ausgibt.
+#[derive(Clone)]
+pub struct AgentState {
+    connection: ConnectionTracker,
+    session: Arc<Mutex<Option<SessionStatus>>>,
+    jobs: JobRegistry,
+    drain: Drain,
+    agent_id: String,
+    started_at: u64,
+}
+
+impl AgentState {
+    pub fn new(config: &AgentConfig, drain: Drain) -> Self {
+        AgentState {
+            connection: ConnectionTracker::new(&config.reconnect),
+            session: Arc::default(),
+            jobs: JobRegistry::default(),
+            drain,
+            agent_id: config.agent_id.clone(),
+            started_at: unix_secs(SystemTime::now()),
+        }
+    }
+
+    /// Die laufenden Jobs; der gRPC-Client trägt sie hier ein.
+    pub fn jobs(&self) -> JobRegistry {
+        self.jobs.clone()
+    }
+
+    /// Bereit, sobald der Agent beim Server registriert ist.
+    pub fn is_ready(&self) -> bool {
+        self.connection.status().phase == ConnectionPhase::Connected
+    }
+
+    pub fn report(&self) -> StatusReport {
+        let connection = self.connection.status();
+        let jobs = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(job_id, started_at)| RunningJob {
+                job_id: job_id.clone(),
+                started_at: *started_at,
+            })
+            .collect();
+        StatusReport {
+            agent_id: self.agent_id.clone(),
+            version: AGENT_VERSION,
+            started_at: self.started_at,
+            ready: connection.phase == ConnectionPhase::Connected,
+            drain_mode: self.drain.mode(),
+            connection,
+            session: self.session.lock().unwrap().clone(),
+            jobs,
+        }
+    }
+
+    pub fn connecting(&self) {
+        self.connection.connecting();
+    }
+
+    pub fn connected(&self, session_id: &str, protocol_version: u32, resumed: bool) {
+        self.connection.connected();
+        *self.session.lock().unwrap() = Some(SessionStatus {
+            session_id: session_id.to_string(),
+            protocol_version,
+            resumed,
+            registered_at: unix_secs(SystemTime::now()),
+            last_heartbeat_ack_at: None,
+        });
+    }
+
+    pub fn heartbeat_acked(&self) {
+        if let Some(session) = &mut *self.session.lock().unwrap() {
+            session.last_heartbeat_ack_at = Some(unix_secs(SystemTime::now()));
+        }
+    }
+
+    /// Die Session ist beendet, der nächste Versuch folgt nach `delay`. `error`
+    /// ist der Grund, falls die Session mit einem Fehler endete.
+    pub fn waiting(&self, backoff: &Backoff, delay: Duration, error: Option<String>) {
+        *self.session.lock().unwrap() = None;
+        self.connection.waiting(backoff, delay, error);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::runner::AgentTelemetry;
+use std::path::{Path, PathBuf};
+use sysinfo::{Disks, System};
+
+pub const AGENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Sammelt die Ressourcen des Agents für die Heartbeats. Die CPU-Auslastung
+/// ergibt sich aus der Differenz zweier Messungen, der erste Wert ist daher 0.
+pub struct TelemetryCollector {
+    system: System,
+    disks: Disks,
+    workdir: PathBuf,
+}
+
+/// Existiert das Arbeitsverzeichnis noch nicht, zählt das nächste existierende Elternverzeichnis.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    absolute
+        .ancestors()
+        .find_map(|dir| dir.canonicalize().ok())
+        .unwrap_or(absolute)
+}
+
+impl TelemetryCollector {
+    pub fn new(workdir: &Path) -> Self {
+        TelemetryCollector {
+            system: System::new(),
+            disks: Disks::new_with_refreshed_list(),
+            workdir: workdir.to_path_buf(),
+        }
+    }
+
+    /// Liefert (gesamt, frei) des Datenträgers, auf dem das Arbeitsverzeichnis liegt.
+    fn workdir_disk(&mut self) -> (u64, u64) {
+        self.disks.refresh_list();
+        let workdir = existing_ancestor(&self.workdir);
+        self.disks
+            .list()
+            .iter()
+            .filter(|disk| workdir.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len())
+            .map(|disk| (disk.total_space(), disk.available_space()))
+            .unwrap_or_default()
+    }
+
+    pub fn collect(&mut self, running_jobs: usize) -> AgentTelemetry {
+        self.system.refresh_cpu_usage();
+        self.system.refresh_memory();
+        let (disk_total_bytes, disk_free_bytes) = self.workdir_disk();
+
+        AgentTelemetry {
+            cpu_usage: self.system.global_cpu_usage() as f64,
+            load_average: System::load_average().one,
+            memory_total_bytes: self.system.total_memory(),
+            memory_available_bytes: self.system.available_memory(),
+            disk_total_bytes,
+            disk_free_bytes,
+            running_jobs: running_jobs as u32,
+            agent_version: AGENT_VERSION.to_string(),
+        }
+    }
+}
@@ -7,9 +7,14 @@ import { runInAction } from 'mobx';
 // Interfaces (no change)
 interface Agent {
   id: string;
-  status: 'online' | 'offline' | 'busy' | 'unknown';
+  status: 'online' | 'offline' | 'busy' | 'incompatible' | 'unknown';
   hostname?: string;
   last_heartbeat?: number;
+  protocol_version?: number;
+  agent_version?: string;
+  incompatible_reason?: string | null;
+  labels?: string[];
+  concurrency?: number;
 }
 interface WsInitialState {
     type: 'InitialState';
@@ -28,7 +33,8 @@ type WsServerMessage = WsInitialState | WsAgentUpdate | WsStatsUpdate;
 
 
 export const Dashboard = observer(() => {
-  const { agentStore } = useStore();
+  const { agentStore, authStore } = useStore();
+  const [tokenInput, setTokenInput] = useState('');
   // --- KORREKTUR 1: Fehlende Hooks wieder hinzufügen ---
   const [isConnected, setIsConnected] = useState(false);
   const ws = useRef<WebSocket | null>(null);
@@ -36,13 +42,18 @@ export const Dashboard = observer(() => {
 
   // --- KORREKTUR 2: WebSocket-Logik in useEffect kapseln ---
   useEffect(() => {
+    // Ohne Token lehnt der Server das Upgrade ab
+    if (!authStore.token) return;
+    let closed = false;
+    let reconnectTimer: ReturnType<typeof setTimeout> | undefined;
+
     // Definiere die Funktion innerhalb des Effects
     function connectWebSocket() {
       console.log("Connecting to WebSocket...");
       const wsHost = window.location.host;
       const wsUrl = `wss://71635-3000.2.codesphere.com/server/api/ws`;
 
-      ws.current = new WebSocket(wsUrl);
+      ws.current = new WebSocket(authStore.withAccessToken(wsUrl));
 
       ws.current.onopen = () => {
         console.log("WebSocket connected!");
@@ -53,8 +64,9 @@ export const Dashboard = observer(() => {
         console.log("WebSocket disconnected:", event.code, event.reason);
         setIsConnected(false);
         ws.current = null;
+        if (closed) return;
         // Versuche nach 5 Sekunden erneut zu verbinden
-        setTimeout(connectWebSocket, 5000);
+        reconnectTimer = setTimeout(connectWebSocket, 5000);
       };
 
       ws.current.onerror = (error) => {
@@ -71,7 +83,7 @@ export const Dashboard = observer(() => {
                 console.log("Processing Initial State:", message.agents.length, "agents");
                 agentStore.agents.clear();
                 message.agents.forEach(agent => {
-                    const validStatus = ['online', 'offline', 'busy'].includes(agent.status)
+                    const validStatus = ['online', 'offline', 'busy', 'incompatible'].includes(agent.status)
                         ? agent.status as Agent['status'] : 'unknown';
                     agentStore.agents.set(agent.id, { ...agent, status: validStatus });
                 });
@@ -94,20 +106,42 @@ export const Dashboard = observer(() => {
       };
     }
 
-    // Starte die Verbindung, wenn die Komponente geladen wird
+    // Starte die Verbindung, wenn die Komponente geladen wird; die
+    // Agent-Liste prüft dabei auch den Token (401 verwirft ihn)
+    agentStore.fetchInitialAgents();
     connectWebSocket();
 
     // Cleanup-Funktion: Wird ausgeführt, wenn die Komponente unmountet wird
+    // oder sich der Token ändert
     return () => {
       console.log("Closing WebSocket connection...");
+      closed = true;
+      clearTimeout(reconnectTimer);
       ws.current?.close();
     };
-  }, [agentStore]); // Abhängigkeit vom Store
+  }, [agentStore, authStore, authStore.token]); // Abhängigkeit vom Store und Token
   // --- ENDE KORREKTUR 2 ---
 
+  if (!authStore.token) {
+    return (
+      <div>
+        <h2>Dashboard</h2>
+        <form onSubmit={(event) => { event.preventDefault(); authStore.setToken(tokenInput); setTokenInput(''); }}>
+          <label>
+            API token:{' '}
+            <input type="password" value={tokenInput} onChange={(event) => setTokenInput(event.target.value)} />
+          </label>
+          <button type="submit">Sign in</button>
+        </form>
+        {agentStore.error && <p style={{ color: 'red' }}>Error loading agents: {agentStore.error}</p>}
+      </div>
+    );
+  }
+
   return (
     <div>
       <h2>Dashboard</h2>
+      <button onClick={() => authStore.clearToken()}>Sign out</button>
       <p>WebSocket Status: {isConnected ? 'Connected' : 'Disconnected'}</p>
       <p>Online Agents: {agentStore.onlineAgents.length}</p>
       <p>Offline Agents: {agentStore.offlineAgents.length}</p>
@@ -123,6 +157,7 @@ export const Dashboard = observer(() => {
             <Link to="/agents/$agentId" params={{ agentId: agent.id }}>
               {agent.id} ({agent.hostname || '...'})
             </Link>
+            {agent.labels && agent.labels.length > 0 && ` [${agent.labels.join(', ')}]`}
           </li>
         ))}
       </ul>
@@ -136,6 +171,20 @@ export const Dashboard = observer(() => {
           </li>
         ))}
       </ul>
+
+      {agentStore.incompatibleAgents.length > 0 && (
+        <>
+          <h3>Incompatible Agents</h3>
+          <ul>
+            {agentStore.incompatibleAgents.map((agent) => (
+              <li key={agent.id} style={{ color: 'darkorange' }}>
+                {agent.id} ({agent.hostname || '...'}, agent {agent.agent_version || '?'}, protocol {agent.protocol_version ?? '?'})
+                {' - '}{agent.incompatible_reason || 'Incompatible'}
+              </li>
+            ))}
+          </ul>
+        </>
+      )}
     </div>
   );
 });
@@ -1,10 +1,60 @@
-import { makeAutoObservable } from "mobx";
+import { makeAutoObservable, runInAction } from "mobx";
 
 interface Agent {
   id: string;
-  status: 'online' | 'offline' | 'busy' | 'unknown'; 
+  status: 'online' | 'offline' | 'busy' | 'incompatible' | 'unknown'; 
   hostname?: string; 
   last_heartbeat?: number; 
+  protocol_version?: number;
+  agent_version?: string;
+  incompatible_reason?: string | null;
+  labels?: string[];
+  concurrency?: number;
+}
+
+const KNOWN_STATUSES = ['online', 'offline', 'busy', 'incompatible'];
+
+const TOKEN_STORAGE_KEY = 'deliversphere.apiToken';
+
+// API-Token des Benutzers; alle /api-Routen verlangen ihn
+class AuthStore {
+  token: string | null = localStorage.getItem(TOKEN_STORAGE_KEY);
+
+  constructor() {
+    makeAutoObservable(this);
+  }
+
+  setToken(token: string) {
+    this.token = token.trim() || null;
+    if (this.token) {
+      localStorage.setItem(TOKEN_STORAGE_KEY, this.token);
+    } else {
+      localStorage.removeItem(TOKEN_STORAGE_KEY);
+    }
+  }
+
+  // Bei 401: Token verwerfen, damit das Dashboard erneut danach fragt
+  clearToken() {
+    this.token = null;
+    localStorage.removeItem(TOKEN_STORAGE_KEY);
+  }
+
+  // fetch mit `Authorization: Bearer <token>`
+  async fetch(path: string, init: RequestInit = {}): Promise<Response> {
+    const headers = new Headers(init.headers);
+    if (this.token) headers.set('Authorization', `Bearer ${this.token}`);
+    const response = await fetch(path, { ...init, headers });
+    if (response.status === 401) this.clearToken();
+    return response;
+  }
+
+  // Browser können beim WebSocket-Upgrade keine Header setzen,
+  // der Server akzeptiert dort `?access_token=<token>`
+  withAccessToken(url: string): string {
+    if (!this.token) return url;
+    const separator = url.includes('?') ? '&' : '?';
+    return `${url}${separator}access_token=${encodeURIComponent(this.token)}`;
+  }
 }
 
 class AgentStore {
@@ -13,27 +63,26 @@ class AgentStore {
   isLoading = false;
   error: string | null = null;
 
-  constructor() {
+  private auth: AuthStore;
+
+  constructor(auth: AuthStore) {
+    this.auth = auth;
     makeAutoObservable(this);
   }
 
   // Action to update or add an agent based on SSE data
-  updateAgent(agentData: { id: string; status: string }) {
+  updateAgent(agentData: Omit<Agent, 'status'> & { status: string }) {
     const existingAgent = this.agents.get(agentData.id);
-    const validStatus = ['online', 'offline', 'busy'].includes(agentData.status)
+    const validStatus = KNOWN_STATUSES.includes(agentData.status)
         ? agentData.status as Agent['status']
         : 'unknown';
 
-    if (existingAgent) {
-      // Update existing agent status
-      existingAgent.status = validStatus;
-    } else {
-      // Add new agent
-      this.agents.set(agentData.id, {
-        id: agentData.id,
-        status: validStatus,
-      });
-    }
+    // Update existing agent or add a new one
+    this.agents.set(agentData.id, {
+      ...existingAgent,
+      ...agentData,
+      status: validStatus,
+    });
   }
 
 	async fetchInitialAgents() {
@@ -42,13 +91,13 @@ class AgentStore {
     this.isLoading = true;
     this.error = null;
     try {
-      const response = await fetch('/api/agents');
+      const response = await this.auth.fetch('/api/agents');
       if (!response.ok) throw new Error(`HTTP error! status: ${response.status}`);
       const data: Agent[] = await response.json();
       runInAction(() => {
         this.agents.clear(); 
         data.forEach(agent => {
-             const validStatus = ['online', 'offline', 'busy'].includes(agent.status)
+             const validStatus = KNOWN_STATUSES.includes(agent.status)
                 ? agent.status as Agent['status']
                 : 'unknown';
              this.agents.set(agent.id, { ...agent, status: validStatus });
@@ -80,6 +129,11 @@ class AgentStore {
      return Array.from(this.agents.values()).filter(agent => agent.status === 'offline');
   }
 
+  // Agents, die der Server wegen ihrer Protokollversion abgelehnt hat
+  get incompatibleAgents(): Agent[] {
+     return Array.from(this.agents.values()).filter(agent => agent.status === 'incompatible');
+  }
+
    get agentCount() {
      return this.agents.size;
    }
@@ -90,7 +144,7 @@ class AgentStore {
     this.error = null;
     try {
       // Assumes API returns Agent[]
-      const response = await fetch('/api/agents'); // Example REST endpoint
+      const response = await this.auth.fetch('/api/agents'); // Example REST endpoint
       if (!response.ok) throw new Error(`HTTP error! status: ${response.status}`);
       const data: Agent[] = await response.json();
       runInAction(() => {
@@ -110,9 +164,11 @@ class AgentStore {
 
 // RootStore remains the same
 export class RootStore {
+  authStore: AuthStore;
   agentStore: AgentStore;
   constructor() {
-    this.agentStore = new AgentStore();
+    this.authStore = new AuthStore();
+    this.agentStore = new AgentStore(this.authStore);
     makeAutoObservable(this);
   }
 }
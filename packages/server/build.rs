@@ -1,4 +1,6 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Neue Migrationen sollen das Binary neu bauen, sie werden per sqlx::migrate! eingebettet
+    println!("cargo:rerun-if-changed=migrations");
     tonic_build::configure().compile(&["../../proto/runner.proto"], &["../../proto"])?;
     Ok(())
 }
@@ -0,0 +1,4 @@
+ALTER TABLE jobs DROP COLUMN pipeline_id;
+ALTER TABLE jobs DROP COLUMN project_id;
+DROP TABLE pipelines;
+DROP TABLE projects;
@@ -0,0 +1,19 @@
+-- Einmalige Tokens, mit denen sich neue Agents registrieren können
+CREATE TABLE enrollment_tokens (
+    id TEXT PRIMARY KEY NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by TEXT,
+    created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW())::BIGINT),
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT,
+    used_by_agent_id TEXT,
+    FOREIGN KEY(created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- Dauerhafte Credentials der Agents, gespeichert wird nur der SHA-256-Hash
+CREATE TABLE agent_credentials (
+    agent_id TEXT PRIMARY KEY NOT NULL,
+    credential_hash TEXT NOT NULL UNIQUE,
+    created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM NOW())::BIGINT),
+    revoked_at BIGINT
+);
@@ -0,0 +1,2 @@
+DROP INDEX idx_jobs_created_at;
+ALTER TABLE jobs DROP COLUMN pinned;
@@ -0,0 +1,4 @@
+-- Angepinnte Jobs werden von den Aufbewahrungsregeln nie gelöscht
+ALTER TABLE jobs ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_jobs_created_at ON jobs(created_at);
@@ -0,0 +1,4 @@
+-- Zeitpunkte, zu denen ein Agent den Job übernommen bzw. abgeschlossen hat,
+-- NULL solange das nicht passiert ist
+ALTER TABLE jobs ADD COLUMN started_at BIGINT;
+ALTER TABLE jobs ADD COLUMN finished_at BIGINT;
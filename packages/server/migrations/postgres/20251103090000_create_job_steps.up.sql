@@ -0,0 +1,20 @@
+-- Ein Eintrag je Befehl eines Jobs, Zeitpunkte in Millisekunden
+CREATE TABLE job_steps (
+    job_id TEXT NOT NULL,
+    step_index BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    -- 'pending', 'running', 'success', 'failed', 'skipped', 'timed_out', 'cancelled'
+    status TEXT NOT NULL DEFAULT 'pending',
+    exit_code BIGINT,
+    signal BIGINT,
+    started_at_ms BIGINT,
+    finished_at_ms BIGINT,
+    duration_ms BIGINT,
+    PRIMARY KEY (job_id, step_index),
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
+
+-- Warum ein Job fehlgeschlagen ist:
+-- 'command_failed', 'checkout_failed', 'timeout', 'agent_lost', 'cancelled'
+ALTER TABLE jobs ADD COLUMN failure_reason TEXT;
+ALTER TABLE jobs ADD COLUMN failure_message TEXT;
@@ -0,0 +1,16 @@
+-- Index der Log-Chunks eines Jobs. Die Chunks selbst liegen als Dateien im
+-- Log-Verzeichnis des Jobs, nach Abschluss des Jobs gzip-komprimiert.
+CREATE TABLE job_log_chunks (
+    job_id TEXT NOT NULL,
+    seq BIGINT NOT NULL,
+    -- NULL für Ausgaben außerhalb eines Schritts, z.B. beim Checkout
+    step_index BIGINT,
+    -- Position des Chunks im gesamten, unkomprimierten Log
+    byte_offset BIGINT NOT NULL,
+    byte_length BIGINT NOT NULL,
+    line_offset BIGINT NOT NULL,
+    line_count BIGINT NOT NULL,
+    compressed BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (job_id, seq),
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN log_indexed;
+DROP TABLE job_log_lines;
@@ -0,0 +1,18 @@
+-- Die Zeilen abgeschlossener Jobs für die Suche, befüllt vom Log-Indexer
+CREATE TABLE job_log_lines (
+    id BIGSERIAL PRIMARY KEY,
+    job_id TEXT NOT NULL,
+    -- NULL für Ausgaben außerhalb eines Schritts
+    step_index BIGINT,
+    line_no BIGINT NOT NULL,
+    content TEXT NOT NULL,
+    -- 'simple' ohne Stemming, Logs sind keine natürliche Sprache
+    search TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED,
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX idx_job_log_lines_job_line ON job_log_lines(job_id, line_no);
+CREATE INDEX idx_job_log_lines_search ON job_log_lines USING GIN (search);
+
+-- Ob die Logs des Jobs im Suchindex stehen
+ALTER TABLE jobs ADD COLUMN log_indexed BOOLEAN NOT NULL DEFAULT FALSE;
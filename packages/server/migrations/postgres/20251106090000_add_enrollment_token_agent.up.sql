@@ -0,0 +1,3 @@
+-- Token für das erneute Enrollment eines bestehenden Agents, nur für diese
+-- Agent-ID gültig. Tokens ohne Agent-ID registrieren nur neue Agents.
+ALTER TABLE enrollment_tokens ADD COLUMN agent_id TEXT;
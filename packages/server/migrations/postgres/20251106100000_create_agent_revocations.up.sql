@@ -0,0 +1,9 @@
+-- Widerrufene Agents, auch solche, die sich nur per Client-Zertifikat anmelden
+-- und kein Credential haben. Erneutes Enrollment hebt den Widerruf auf.
+CREATE TABLE agent_revocations (
+    agent_id TEXT PRIMARY KEY NOT NULL,
+    revoked_at BIGINT NOT NULL
+);
+
+INSERT INTO agent_revocations (agent_id, revoked_at)
+SELECT agent_id, revoked_at FROM agent_credentials WHERE revoked_at IS NOT NULL;
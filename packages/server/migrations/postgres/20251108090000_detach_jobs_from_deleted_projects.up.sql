@@ -0,0 +1,9 @@
+-- Jobs überdauern ihr Projekt und ihre Pipeline
+ALTER TABLE jobs
+    DROP CONSTRAINT jobs_project_id_fkey,
+    ADD CONSTRAINT jobs_project_id_fkey
+        FOREIGN KEY (project_id) REFERENCES projects(id) ON DELETE SET NULL;
+ALTER TABLE jobs
+    DROP CONSTRAINT jobs_pipeline_id_fkey,
+    ADD CONSTRAINT jobs_pipeline_id_fkey
+        FOREIGN KEY (pipeline_id) REFERENCES pipelines(id) ON DELETE SET NULL;
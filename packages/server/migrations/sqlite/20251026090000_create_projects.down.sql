@@ -0,0 +1,19 @@
+-- SQLite kann Spalten mit Foreign Key nicht entfernen, daher wird jobs neu aufgebaut
+CREATE TABLE jobs_without_projects (
+    id TEXT PRIMARY KEY,
+    agent_id TEXT,
+    status TEXT NOT NULL DEFAULT 'pending',
+    repository_url TEXT NOT NULL,
+    commands TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY(agent_id) REFERENCES agents(id)
+);
+
+INSERT INTO jobs_without_projects (id, agent_id, status, repository_url, commands, created_at)
+SELECT id, agent_id, status, repository_url, commands, created_at FROM jobs;
+
+DROP TABLE jobs;
+ALTER TABLE jobs_without_projects RENAME TO jobs;
+
+DROP TABLE pipelines;
+DROP TABLE projects;
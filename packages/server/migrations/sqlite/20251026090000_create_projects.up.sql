@@ -0,0 +1,31 @@
+-- Projekte bündeln Repository-Einstellungen, Pipelines und Jobs
+CREATE TABLE projects (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    repository_url TEXT NOT NULL,
+    default_branch TEXT NOT NULL DEFAULT 'main',
+    -- Pfad zur Pipeline-Definition im Repository
+    pipeline_path TEXT NOT NULL DEFAULT '.deliversphere.yml',
+    webhook_secret TEXT,
+    settings TEXT NOT NULL DEFAULT '{}', -- Als JSON-Objekt gespeichert
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- Ein Pipeline-Lauf eines Projekts, besteht aus einem oder mehreren Jobs
+CREATE TABLE pipelines (
+    id TEXT PRIMARY KEY NOT NULL,
+    project_id TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    pipeline_path TEXT NOT NULL,
+    -- 'pending', 'running', 'success', 'failed'
+    status TEXT NOT NULL DEFAULT 'pending',
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE
+);
+
+ALTER TABLE jobs ADD COLUMN project_id TEXT REFERENCES projects(id);
+ALTER TABLE jobs ADD COLUMN pipeline_id TEXT REFERENCES pipelines(id);
+
+CREATE INDEX idx_pipelines_project_id ON pipelines(project_id);
+CREATE INDEX idx_jobs_project_id ON jobs(project_id);
+CREATE INDEX idx_jobs_pipeline_id ON jobs(pipeline_id);
@@ -0,0 +1,17 @@
+-- Zeitreihe der Ressourcen, die Agents mit jedem Heartbeat melden
+CREATE TABLE agent_metrics (
+    agent_id TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL,
+    cpu_usage REAL NOT NULL,
+    load_average REAL NOT NULL,
+    memory_total_bytes INTEGER NOT NULL,
+    memory_available_bytes INTEGER NOT NULL,
+    disk_total_bytes INTEGER NOT NULL,
+    disk_free_bytes INTEGER NOT NULL,
+    running_jobs INTEGER NOT NULL,
+    agent_version TEXT NOT NULL,
+    PRIMARY KEY (agent_id, recorded_at),
+    FOREIGN KEY(agent_id) REFERENCES agents(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_agent_metrics_recorded_at ON agent_metrics(recorded_at);
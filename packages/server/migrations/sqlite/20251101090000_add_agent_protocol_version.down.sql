@@ -0,0 +1,3 @@
+ALTER TABLE agents DROP COLUMN incompatible_reason;
+ALTER TABLE agents DROP COLUMN agent_version;
+ALTER TABLE agents DROP COLUMN protocol_version;
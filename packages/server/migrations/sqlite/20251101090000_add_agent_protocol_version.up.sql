@@ -0,0 +1,6 @@
+-- Ausgehandelte Protokollversion und Build-Version des Agents,
+-- 0 bzw. leer bis zur ersten Registrierung mit Versionsaushandlung
+ALTER TABLE agents ADD COLUMN protocol_version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE agents ADD COLUMN agent_version TEXT NOT NULL DEFAULT '';
+-- Grund, warum der Server den Agent abgelehnt hat (Status 'incompatible')
+ALTER TABLE agents ADD COLUMN incompatible_reason TEXT;
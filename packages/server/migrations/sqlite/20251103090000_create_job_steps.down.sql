@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN failure_message;
+ALTER TABLE jobs DROP COLUMN failure_reason;
+DROP TABLE job_steps;
@@ -0,0 +1,20 @@
+-- Ein Eintrag je Befehl eines Jobs, Zeitpunkte in Millisekunden
+CREATE TABLE job_steps (
+    job_id TEXT NOT NULL,
+    step_index INTEGER NOT NULL,
+    command TEXT NOT NULL,
+    -- 'pending', 'running', 'success', 'failed', 'skipped', 'timed_out', 'cancelled'
+    status TEXT NOT NULL DEFAULT 'pending',
+    exit_code INTEGER,
+    signal INTEGER,
+    started_at_ms INTEGER,
+    finished_at_ms INTEGER,
+    duration_ms INTEGER,
+    PRIMARY KEY (job_id, step_index),
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
+
+-- Warum ein Job fehlgeschlagen ist:
+-- 'command_failed', 'checkout_failed', 'timeout', 'agent_lost', 'cancelled'
+ALTER TABLE jobs ADD COLUMN failure_reason TEXT;
+ALTER TABLE jobs ADD COLUMN failure_message TEXT;
@@ -0,0 +1,16 @@
+-- Index der Log-Chunks eines Jobs. Die Chunks selbst liegen als Dateien im
+-- Log-Verzeichnis des Jobs, nach Abschluss des Jobs gzip-komprimiert.
+CREATE TABLE job_log_chunks (
+    job_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    -- NULL für Ausgaben außerhalb eines Schritts, z.B. beim Checkout
+    step_index INTEGER,
+    -- Position des Chunks im gesamten, unkomprimierten Log
+    byte_offset INTEGER NOT NULL,
+    byte_length INTEGER NOT NULL,
+    line_offset INTEGER NOT NULL,
+    line_count INTEGER NOT NULL,
+    compressed BOOLEAN NOT NULL DEFAULT 0,
+    PRIMARY KEY (job_id, seq),
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+ALTER TABLE jobs DROP COLUMN log_indexed;
+DROP TRIGGER job_log_lines_ad;
+DROP TRIGGER job_log_lines_ai;
+DROP TABLE job_log_search;
+DROP TABLE job_log_lines;
@@ -0,0 +1,30 @@
+-- Die Zeilen abgeschlossener Jobs für die Suche, befüllt vom Log-Indexer
+CREATE TABLE job_log_lines (
+    id INTEGER PRIMARY KEY,
+    job_id TEXT NOT NULL,
+    -- NULL für Ausgaben außerhalb eines Schritts
+    step_index INTEGER,
+    line_no INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    FOREIGN KEY(job_id) REFERENCES jobs(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX idx_job_log_lines_job_line ON job_log_lines(job_id, line_no);
+
+-- Volltextindex über job_log_lines, per Trigger synchron gehalten
+CREATE VIRTUAL TABLE job_log_search USING fts5(
+    content,
+    content = 'job_log_lines',
+    content_rowid = 'id'
+);
+
+CREATE TRIGGER job_log_lines_ai AFTER INSERT ON job_log_lines BEGIN
+    INSERT INTO job_log_search(rowid, content) VALUES (new.id, new.content);
+END;
+
+CREATE TRIGGER job_log_lines_ad AFTER DELETE ON job_log_lines BEGIN
+    INSERT INTO job_log_search(job_log_search, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+
+-- Ob die Logs des Jobs im Suchindex stehen
+ALTER TABLE jobs ADD COLUMN log_indexed BOOLEAN NOT NULL DEFAULT 0;
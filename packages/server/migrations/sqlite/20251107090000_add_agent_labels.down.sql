@@ -0,0 +1,2 @@
+ALTER TABLE agents DROP COLUMN concurrency;
+ALTER TABLE agents DROP COLUMN labels;
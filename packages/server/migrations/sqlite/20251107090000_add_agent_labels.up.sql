@@ -0,0 +1,3 @@
+-- Labels des Agents als JSON-Array und wie viele Jobs er gleichzeitig ausführt
+ALTER TABLE agents ADD COLUMN labels TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE agents ADD COLUMN concurrency INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+DROP TRIGGER pipelines_detach_jobs;
+DROP TRIGGER projects_detach_jobs;
@@ -0,0 +1,14 @@
+-- Jobs überdauern ihr Projekt und ihre Pipeline (wie ON DELETE SET NULL).
+-- SQLite kann den Fremdschlüssel nicht nachträglich ändern, daher per Trigger;
+-- er läuft vor der Prüfung des Fremdschlüssels und vor dem Löschen der Pipelines.
+CREATE TRIGGER projects_detach_jobs BEFORE DELETE ON projects
+BEGIN
+    UPDATE jobs SET project_id = NULL, pipeline_id = NULL
+    WHERE project_id = OLD.id
+       OR pipeline_id IN (SELECT id FROM pipelines WHERE project_id = OLD.id);
+END;
+
+CREATE TRIGGER pipelines_detach_jobs BEFORE DELETE ON pipelines
+BEGIN
+    UPDATE jobs SET pipeline_id = NULL WHERE pipeline_id = OLD.id;
+END;
@@ -0,0 +1,226 @@
+use crate::auth::{generate_secret, hash_token};
+use crate::db::DbPool;
+use crate::{unix_timestamp, with_db, AppError, LiveAgentMap, Result};
+use tonic::{metadata::MetadataMap, Status};
+use tracing::info;
+use uuid::Uuid;
+
+const ENROLLMENT_TOKEN_PREFIX: &str = "dse_";
+const AGENT_CREDENTIAL_PREFIX: &str = "dsa_";
+
+/// Legt einen einmalig verwendbaren Enrollment-Token an und gibt ihn im Klartext zurück.
+/// Mit `agent_id` gilt er nur für diesen Agent und darf dessen Credential ersetzen.
+pub async fn create_enrollment_token(
+    db_pool: &DbPool,
+    created_by: &str,
+    expires_at: i64,
+    agent_id: Option<&str>,
+) -> Result<(String, String)> {
+    let token = generate_secret(ENROLLMENT_TOKEN_PREFIX);
+    let token_id = Uuid::new_v4().to_string();
+    with_db!(db_pool, |pool| {
+        sqlx::query(
+            "INSERT INTO enrollment_tokens (id, token_hash, created_by, expires_at, agent_id) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&token_id)
+        .bind(hash_token(&token))
+        .bind(created_by)
+        .bind(expires_at)
+        .bind(agent_id)
+        .execute(pool)
+        .await?;
+    });
+    Ok((token_id, token))
+}
+
+/// Löst einen Enrollment-Token ein und stellt ein neues Credential für den Agent aus.
+/// Einen bereits bekannten Agent kann nur ein Token neu registrieren, den ein
+/// Admin für genau diese Agent-ID ausgestellt hat; sein altes Credential wird
+/// dabei ersetzt und ein Widerruf aufgehoben.
+pub async fn enroll_agent(
+    db_pool: &DbPool,
+    enrollment_token: &str,
+    agent_id: &str,
+) -> Result<String> {
+    let now = unix_timestamp();
+    let credential = generate_secret(AGENT_CREDENTIAL_PREFIX);
+
+    with_db!(db_pool, |pool| {
+        let mut tx = pool.begin().await?;
+
+        // Nur ein Aufrufer kann den Token einlösen, auch bei gleichzeitigen Requests
+        let redeemed: Option<Option<String>> = sqlx::query_scalar(
+            r#"
+            UPDATE enrollment_tokens SET used_at = $1, used_by_agent_id = $2
+            WHERE token_hash = $3 AND used_at IS NULL AND expires_at > $1
+                AND (agent_id IS NULL OR agent_id = $2)
+            RETURNING agent_id
+            "#,
+        )
+        .bind(now)
+        .bind(agent_id)
+        .bind(hash_token(enrollment_token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(reenroll) = redeemed.map(|bound| bound.is_some()) else {
+            return Err(AppError::Unauthorized(
+                "invalid, expired or already used enrollment token".to_string(),
+            ));
+        };
+
+        if reenroll {
+            sqlx::query(
+                r#"
+                INSERT INTO agent_credentials (agent_id, credential_hash, created_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT(agent_id) DO UPDATE SET
+                    credential_hash = excluded.credential_hash, created_at = excluded.created_at, revoked_at = NULL
+                "#,
+            )
+            .bind(agent_id)
+            .bind(hash_token(&credential))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM agent_revocations WHERE agent_id = $1")
+                .bind(agent_id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            // Ohne Bindung an den Agent könnte jeder Token-Inhaber die
+            // Identität eines bestehenden Agents übernehmen. Der Token bleibt
+            // dabei unverbraucht, weil die Transaktion verworfen wird.
+            let known: bool = sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM agent_credentials WHERE agent_id = $1) \
+                 OR EXISTS (SELECT 1 FROM agents WHERE id = $1)",
+            )
+            .bind(agent_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if known {
+                return Err(AppError::Forbidden(format!(
+                    "agent '{}' is already enrolled; re-enrollment requires a token issued for this agent",
+                    agent_id
+                )));
+            }
+            sqlx::query(
+                "INSERT INTO agent_credentials (agent_id, credential_hash, created_at) VALUES ($1, $2, $3)",
+            )
+            .bind(agent_id)
+            .bind(hash_token(&credential))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+    });
+    Ok(credential)
+}
+
+/// Liest `authorization: Bearer <credential>` aus den gRPC-Metadaten und
+/// liefert die Agent-ID, zu der das Credential gehört.
+pub async fn authenticate_agent(db_pool: &DbPool, metadata: &MetadataMap) -> Result<String> {
+    let credential = metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Unauthorized("missing agent credential".to_string()))?;
+
+    with_db!(db_pool, |pool| {
+        sqlx::query_scalar(
+            "SELECT agent_id FROM agent_credentials WHERE credential_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(hash_token(credential.trim()))
+        .fetch_optional(pool)
+        .await?
+    })
+    .ok_or_else(|| AppError::Unauthorized("invalid or revoked agent credential".to_string()))
+}
+
+/// Bestimmt die Agent-ID eines Streams. Mit Client-Zertifikat (mTLS) ist der
+/// Common Name die Identität; ein zusätzlich gesendetes Credential muss dazu
+/// passen. Ohne Zertifikat ist das Credential Pflicht.
+pub async fn resolve_agent_identity(
+    db_pool: &DbPool,
+    metadata: &MetadataMap,
+    certificate_identity: Option<String>,
+) -> Result<String> {
+    let Some(cert_agent_id) = certificate_identity else {
+        return authenticate_agent(db_pool, metadata).await;
+    };
+
+    if metadata.contains_key("authorization") {
+        let agent_id = authenticate_agent(db_pool, metadata).await?;
+        if agent_id != cert_agent_id {
+            return Err(AppError::Forbidden(format!(
+                "credential of agent '{}' does not match certificate subject '{}'",
+                agent_id, cert_agent_id
+            )));
+        }
+        return Ok(agent_id);
+    }
+
+    // Ein Widerruf sperrt den Agent auch für die Anmeldung per Zertifikat
+    let revoked: Option<i64> = with_db!(db_pool, |pool| {
+        sqlx::query_scalar("SELECT revoked_at FROM agent_revocations WHERE agent_id = $1")
+            .bind(&cert_agent_id)
+            .fetch_optional(pool)
+            .await?
+    });
+    if revoked.is_some() {
+        return Err(AppError::Unauthorized(format!(
+            "agent '{}' has been revoked",
+            cert_agent_id
+        )));
+    }
+    Ok(cert_agent_id)
+}
+
+/// Widerruft einen Agent samt Credential und beendet seinen laufenden Stream.
+/// Agents ohne Credential (nur mTLS) werden über den Widerruf selbst gesperrt.
+pub async fn revoke_agent_credential(
+    db_pool: &DbPool,
+    live_agents: &LiveAgentMap,
+    agent_id: &str,
+) -> Result<()> {
+    let now = unix_timestamp();
+    let rows_affected = with_db!(db_pool, |pool| {
+        let mut tx = pool.begin().await?;
+        let revoked = sqlx::query(
+            r#"
+            INSERT INTO agent_revocations (agent_id, revoked_at)
+            SELECT $1, $2
+            WHERE EXISTS (SELECT 1 FROM agents WHERE id = $1)
+                OR EXISTS (SELECT 1 FROM agent_credentials WHERE agent_id = $1)
+            ON CONFLICT(agent_id) DO NOTHING
+            "#,
+        )
+        .bind(agent_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        sqlx::query(
+            "UPDATE agent_credentials SET revoked_at = $1 WHERE agent_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(now)
+        .bind(agent_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        revoked
+    });
+    if rows_affected == 0 {
+        return Err(AppError::NotFound(format!("active agent '{}'", agent_id)));
+    }
+
+    if let Some((_, agent)) = live_agents.remove(agent_id) {
+        let _ = agent
+            .tx
+            .send(Err(Status::unauthenticated("agent credential revoked")))
+            .await;
+        info!("Stream von Agent '{}' nach Widerruf beendet.", agent_id);
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::db::DbPool;
+use crate::grpc_server::fetch_agent;
+use crate::grpc_server::runner::AgentTelemetry;
+use crate::models::AgentMetrics;
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, AppError, Result};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Ohne `since` liefert der Endpunkt die Messpunkte der letzten Stunde.
+const DEFAULT_WINDOW_SECS: i64 = 60 * 60;
+const DEFAULT_LIMIT: i64 = 360;
+const MAX_LIMIT: i64 = 10_000;
+
+/// Query-Parameter für `GET /api/agents/{id}/metrics`, z.B. `?since=1730000000&limit=100`.
+#[derive(Debug, Default, Deserialize)]
+pub struct MetricsFilter {
+    pub since: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Speichert die Telemetrie eines Heartbeats. Der Zeitpunkt kommt vom Server,
+/// damit abweichende Uhren der Agents die Zeitreihe nicht verschieben.
+pub async fn record(
+    db_pool: &DbPool,
+    agent_id: &str,
+    telemetry: &AgentTelemetry,
+) -> Result<AgentMetrics> {
+    let metrics = AgentMetrics {
+        agent_id: agent_id.to_string(),
+        recorded_at: unix_timestamp(),
+        cpu_usage: telemetry.cpu_usage,
+        load_average: telemetry.load_average,
+        memory_total_bytes: telemetry.memory_total_bytes as i64,
+        memory_available_bytes: telemetry.memory_available_bytes as i64,
+        disk_total_bytes: telemetry.disk_total_bytes as i64,
+        disk_free_bytes: telemetry.disk_free_bytes as i64,
+        running_jobs: telemetry.running_jobs as i64,
+        agent_version: telemetry.agent_version.clone(),
+    };
+    // Mehrere Heartbeats in derselben Sekunde überschreiben sich
+    with_db!(db_pool, |pool| {
+        sqlx::query(
+            r#"
+            INSERT INTO agent_metrics (
+                agent_id, recorded_at, cpu_usage, load_average, memory_total_bytes,
+                memory_available_bytes, disk_total_bytes, disk_free_bytes, running_jobs, agent_version
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (agent_id, recorded_at) DO UPDATE SET
+                cpu_usage = excluded.cpu_usage,
+                load_average = excluded.load_average,
+                memory_total_bytes = excluded.memory_total_bytes,
+                memory_available_bytes = excluded.memory_available_bytes,
+                disk_total_bytes = excluded.disk_total_bytes,
+                disk_free_bytes = excluded.disk_free_bytes,
+                running_jobs = excluded.running_jobs,
+                agent_version = excluded.agent_version
+            "#,
+        )
+        .bind(&metrics.agent_id)
+        .bind(metrics.recorded_at)
+        .bind(metrics.cpu_usage)
+        .bind(metrics.load_average)
+        .bind(metrics.memory_total_bytes)
+        .bind(metrics.memory_available_bytes)
+        .bind(metrics.disk_total_bytes)
+        .bind(metrics.disk_free_bytes)
+        .bind(metrics.running_jobs)
+        .bind(&metrics.agent_version)
+        .execute(pool)
+        .await?;
+    });
+    Ok(metrics)
+}
+
+/// Der jeweils neueste Messpunkt jedes Agents.
+pub async fn latest(db_pool: &DbPool) -> Result<Vec<AgentMetrics>> {
+    let metrics = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, AgentMetrics>(
+            r#"
+            SELECT m.* FROM agent_metrics m
+            JOIN (
+                SELECT agent_id, MAX(recorded_at) AS recorded_at
+                FROM agent_metrics GROUP BY agent_id
+            ) newest ON newest.agent_id = m.agent_id AND newest.recorded_at = m.recorded_at
+            ORDER BY m.agent_id
+            "#,
+        )
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(metrics)
+}
+
+/// Löscht Messpunkte, die älter als `max_age` sind. Liefert die Anzahl gelöschter Zeilen.
+pub async fn prune(db_pool: &DbPool, max_age: Duration) -> Result<u64> {
+    let cutoff = unix_timestamp() - max_age.as_secs() as i64;
+    let deleted = with_db!(db_pool, |pool| {
+        sqlx::query("DELETE FROM agent_metrics WHERE recorded_at < $1")
+            .bind(cutoff)
+            .execute(pool)
+            .await?
+            .rows_affected()
+    });
+    Ok(deleted)
+}
+
+/// Messpunkte eines Agents, neueste zuerst.
+pub async fn list_agent_metrics(
+    State(state): State<AppState>,
+    Path(agent_id): Path<String>,
+    Query(filter): Query<MetricsFilter>,
+) -> Result<Json<Vec<AgentMetrics>>> {
+    fetch_agent(&state.db_pool, &agent_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("agent '{}'", agent_id)))?;
+
+    let since = filter
+        .since
+        .unwrap_or_else(|| unix_timestamp() - DEFAULT_WINDOW_SECS);
+    let limit = filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let metrics = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, AgentMetrics>(
+            "SELECT * FROM agent_metrics WHERE agent_id = $1 AND recorded_at >= $2 \
+             ORDER BY recorded_at DESC LIMIT $3",
+        )
+        .bind(&agent_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(Json(metrics))
+}
@@ -0,0 +1,163 @@
+use crate::agent_auth;
+use crate::auth::{AuthUser, Scope};
+use crate::grpc_server::runner::{server_command::Payload as CommandPayload, Drain, ServerCommand};
+use crate::grpc_server::{broadcast_ws_message, fetch_agent};
+use crate::models::Agent;
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, AppError, Result, WsServerMessage};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+const DEFAULT_ENROLLMENT_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateEnrollmentToken {
+    pub expires_in_hours: Option<i64>,
+    /// Erlaubt das erneute Enrollment dieses bereits bekannten Agents,
+    /// z.B. nachdem sein Credential verloren ging oder widerrufen wurde.
+    pub agent_id: Option<String>,
+}
+
+/// Der Token wird nur in dieser Antwort im Klartext ausgegeben.
+#[derive(Debug, Serialize)]
+pub struct CreatedEnrollmentToken {
+    pub id: String,
+    pub token: String,
+    pub expires_at: i64,
+    pub agent_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DrainAgent {
+    pub reason: Option<String>,
+}
+
+pub async fn list_agents(State(state): State<AppState>) -> Result<Json<Vec<Agent>>> {
+    let agents = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, Agent>("SELECT * FROM agents ORDER BY id")
+            .fetch_all(pool)
+            .await?
+    });
+    Ok(Json(agents))
+}
+
+pub async fn create_enrollment_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    input: Option<Json<CreateEnrollmentToken>>,
+) -> Result<(StatusCode, Json<CreatedEnrollmentToken>)> {
+    auth.require_scope(Scope::Admin)?;
+
+    let Json(input) = input.unwrap_or_default();
+    let hours = input
+        .expires_in_hours
+        .unwrap_or(DEFAULT_ENROLLMENT_TTL_HOURS);
+    if hours <= 0 {
+        return Err(AppError::BadRequest(
+            "'expires_in_hours' must be positive".to_string(),
+        ));
+    }
+
+    let agent_id = input.agent_id.filter(|id| !id.trim().is_empty());
+
+    let expires_at = unix_timestamp() + hours * 60 * 60;
+    let (id, token) = agent_auth::create_enrollment_token(
+        &state.db_pool,
+        &auth.user_id,
+        expires_at,
+        agent_id.as_deref(),
+    )
+    .await?;
+    match &agent_id {
+        Some(agent_id) => info!(
+            "Enrollment-Token {} für Agent '{}' von '{}' erstellt, gültig bis {}.",
+            id, agent_id, auth.username, expires_at
+        ),
+        None => info!(
+            "Enrollment-Token {} von '{}' erstellt, gültig bis {}.",
+            id, auth.username, expires_at
+        ),
+    }
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedEnrollmentToken {
+            id,
+            token,
+            expires_at,
+            agent_id,
+        }),
+    ))
+}
+
+pub async fn revoke_agent_credential(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+) -> Result<StatusCode> {
+    auth.require_scope(Scope::Admin)?;
+    agent_auth::revoke_agent_credential(&state.db_pool, &state.live_agents, &agent_id).await?;
+    info!(
+        "Credential von Agent '{}' durch '{}' widerrufen.",
+        agent_id, auth.username
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Der Agent nimmt danach keine neuen Jobs mehr an und beendet die laufenden.
+/// Er meldet sich selbst als `draining`, der Status wird aber sofort gesetzt.
+pub async fn drain_agent(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(agent_id): Path<String>,
+    input: Option<Json<DrainAgent>>,
+) -> Result<(StatusCode, Json<Agent>)> {
+    auth.require_scope(Scope::Admin)?;
+    fetch_agent(&state.db_pool, &agent_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("agent '{}'", agent_id)))?;
+    let tx = state
+        .live_agents
+        .get(&agent_id)
+        .map(|entry| entry.value().tx.clone())
+        .ok_or_else(|| AppError::BadRequest(format!("agent '{}' is not connected", agent_id)))?;
+
+    let Json(input) = input.unwrap_or_default();
+    let reason = input
+        .reason
+        .unwrap_or_else(|| format!("requested by '{}'", auth.username));
+    let command = ServerCommand {
+        payload: Some(CommandPayload::Drain(Drain { reason })),
+    };
+    tx.send(Ok(command))
+        .await
+        .map_err(|e| AppError::ChannelSend(e.to_string()))?;
+
+    // Wie beim Drain durch den Agent selbst übersteht er so Heartbeats nach einer Lücke
+    state.liveness.drain(&agent_id);
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query("UPDATE agents SET status = 'draining' WHERE id = $1")
+            .bind(&agent_id)
+            .execute(pool)
+            .await?;
+    });
+    let agent = fetch_agent(&state.db_pool, &agent_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("agent '{}'", agent_id)))?;
+    broadcast_ws_message(
+        &state.ws_clients,
+        &WsServerMessage::AgentUpdate {
+            agent: agent.clone(),
+        },
+    )
+    .await;
+    info!(
+        "Agent '{}' wird von '{}' gedrained.",
+        agent_id, auth.username
+    );
+    Ok((StatusCode::ACCEPTED, Json(agent)))
+}
@@ -0,0 +1,324 @@
+//! ANSI-Escape-Sequenzen in Log-Zeilen: entfernen für Klartext oder
+//! Farben und Textattribute (SGR) in HTML mit Inline-Styles umsetzen.
+//! Andere Steuersequenzen werden verworfen, ein `\r` mitten in der Zeile
+//! überschreibt wie im Terminal den bisherigen Text (Fortschrittsanzeigen).
+
+use std::fmt::Write;
+
+/// Die 16 Grundfarben, wie xterm sie darstellt.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x31, 0x31),
+    (0x0d, 0xbc, 0x79),
+    (0xe5, 0xe5, 0x10),
+    (0x24, 0x72, 0xc8),
+    (0xbc, 0x3f, 0xbc),
+    (0x11, 0xa8, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x66, 0x66, 0x66),
+    (0xf1, 0x4c, 0x4c),
+    (0x23, 0xd1, 0x8b),
+    (0xf5, 0xf5, 0x43),
+    (0x3b, 0x8e, 0xea),
+    (0xd6, 0x70, 0xd6),
+    (0x29, 0xb8, 0xdb),
+    (0xff, 0xff, 0xff),
+];
+
+type Color = (u8, u8, u8);
+
+/// Farbe `index` der 256-Farben-Palette.
+fn palette_256(index: u16) -> Color {
+    match index {
+        0..=15 => PALETTE[index as usize],
+        16..=231 => {
+            let level = |value: u16| match value {
+                0 => 0,
+                _ => (55 + value * 40) as u8,
+            };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let gray = (8 + (index.min(255) - 232) * 10) as u8;
+            (gray, gray, gray)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+}
+
+impl Style {
+    /// Liest eine erweiterte Farbe (`5;n` oder `2;r;g;b`) aus den folgenden Parametern.
+    fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+        match params.next()? {
+            5 => Some(palette_256(params.next()?)),
+            2 => {
+                let mut component = || params.next().map(|value| value.min(255) as u8);
+                Some((component()?, component()?, component()?))
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                9 => self.strike = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                29 => self.strike = false,
+                30..=37 => self.fg = Some(PALETTE[(param - 30) as usize]),
+                38 => self.fg = Style::extended_color(&mut params),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(PALETTE[(param - 40) as usize]),
+                48 => self.bg = Style::extended_color(&mut params),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(PALETTE[(param - 90 + 8) as usize]),
+                100..=107 => self.bg = Some(PALETTE[(param - 100 + 8) as usize]),
+                _ => {}
+            }
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = String::new();
+        if let Some((r, g, b)) = self.fg {
+            let _ = write!(css, "color:#{:02x}{:02x}{:02x};", r, g, b);
+        }
+        if let Some((r, g, b)) = self.bg {
+            let _ = write!(css, "background-color:#{:02x}{:02x}{:02x};", r, g, b);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.7;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        match (self.underline, self.strike) {
+            (true, true) => css.push_str("text-decoration:underline line-through;"),
+            (true, false) => css.push_str("text-decoration:underline;"),
+            (false, true) => css.push_str("text-decoration:line-through;"),
+            (false, false) => {}
+        }
+        css.pop();
+        css
+    }
+}
+
+/// Zerlegt eine Zeile in Text mit dem jeweils geltenden Stil, ausgehend von
+/// `style`. Danach steht in `style` der Stil am Zeilenende.
+fn segments(line: &str, style: &mut Style) -> Vec<(Style, String)> {
+    let mut segments: Vec<(Style, String)> = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    let mut params = String::new();
+                    let mut last = None;
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            last = Some(c);
+                            break;
+                        }
+                        params.push(c);
+                    }
+                    if last == Some('m') {
+                        style.apply(&parse_params(&params));
+                    }
+                }
+                // OSC, z.B. Hyperlinks: endet mit BEL oder ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Zeichensatzwahl wie ESC ( B
+                Some('(' | ')') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\r' => segments.clear(),
+            '\t' => push_char(&mut segments, *style, c),
+            c if c.is_control() => {}
+            c => push_char(&mut segments, *style, c),
+        }
+    }
+    segments
+}
+
+fn push_char(segments: &mut Vec<(Style, String)>, style: Style, c: char) {
+    match segments.last_mut() {
+        Some((last, text)) if *last == style => text.push(c),
+        _ => segments.push((style, c.to_string())),
+    }
+}
+
+/// Parameter einer SGR-Sequenz, leere zählen als 0. Unterparameter mit `:`
+/// (z.B. `38:2::255:0:0`) werden wie durch `;` getrennte behandelt.
+fn parse_params(params: &str) -> Vec<u16> {
+    let mut values = Vec::new();
+    for param in params.split(';') {
+        if param.contains(':') {
+            values.extend(
+                param
+                    .split(':')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.parse().unwrap_or(0)),
+            );
+        } else {
+            values.push(param.parse().unwrap_or(0));
+        }
+    }
+    values
+}
+
+/// Die Zeile ohne Escape-Sequenzen.
+pub fn strip(line: &str) -> String {
+    segments(line, &mut Style::default())
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect()
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Setzt die Zeilen eines Logs nacheinander in HTML um. Wie im Terminal gilt
+/// ein Stil bis zur nächsten SGR-Sequenz, auch über das Zeilenende hinaus.
+#[derive(Debug, Default)]
+pub struct HtmlRenderer {
+    style: Style,
+}
+
+impl HtmlRenderer {
+    /// Die Zeile als HTML, gestaltete Abschnitte als `<span style="...">`.
+    pub fn line(&mut self, line: &str) -> String {
+        let mut html = String::new();
+        for (style, text) in segments(line, &mut self.style) {
+            if style == Style::default() {
+                escape_html(&text, &mut html);
+            } else {
+                let _ = write!(html, "<span style=\"{}\">", style.css());
+                escape_html(&text, &mut html);
+                html.push_str("</span>");
+            }
+        }
+        html
+    }
+
+    /// Übernimmt nur den Stil einer Zeile, die nicht ausgegeben wird.
+    pub fn skip(&mut self, line: &str) {
+        segments(line, &mut self.style);
+    }
+}
+
+/// Eine einzelne Zeile als HTML, ohne Stil vorangehender Zeilen.
+pub fn to_html(line: &str) -> String {
+    HtmlRenderer::default().line(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_removes_escape_sequences() {
+        assert_eq!(strip("\x1b[1;31merror\x1b[0m: failed"), "error: failed");
+        // OSC-Hyperlink und Zeichensatzwahl
+        assert_eq!(
+            strip("\x1b]8;;https://example.com\x07docs\x1b]8;;\x1b\\ \x1b(Bok"),
+            "docs ok"
+        );
+        assert_eq!(strip("\x1b[2Kprogress 50%\rprogress 100%"), "progress 100%");
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            to_html("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+        assert_eq!(
+            to_html("\x1b[1m<b>\x1b[22m"),
+            "<span style=\"font-weight:bold\">&lt;b&gt;</span>"
+        );
+    }
+
+    #[test]
+    fn extended_colors() {
+        // 256 Farben: Würfel und Graustufen
+        assert_eq!(
+            to_html("\x1b[38;5;196mred\x1b[48;5;244mgray"),
+            "<span style=\"color:#ff0000\">red</span>\
+             <span style=\"color:#ff0000;background-color:#808080\">gray</span>"
+        );
+        // Truecolor, auch mit Unterparametern
+        assert_eq!(
+            to_html("\x1b[38;2;10;20;30mrgb"),
+            "<span style=\"color:#0a141e\">rgb</span>"
+        );
+        assert_eq!(
+            to_html("\x1b[38:2::255:128:0mrgb"),
+            "<span style=\"color:#ff8000\">rgb</span>"
+        );
+    }
+
+    #[test]
+    fn style_carries_across_lines() {
+        let mut renderer = HtmlRenderer::default();
+        assert_eq!(
+            renderer.line("\x1b[31mfirst"),
+            "<span style=\"color:#cd3131\">first</span>"
+        );
+        assert_eq!(
+            renderer.line("second"),
+            "<span style=\"color:#cd3131\">second</span>"
+        );
+        assert_eq!(renderer.line("\x1b[0mthird"), "third");
+        // Auch der Stil übersprungener Zeilen gilt weiter
+        renderer.skip("\x1b[1mhidden");
+        assert_eq!(
+            renderer.line("shown"),
+            "<span style=\"font-weight:bold\">shown</span>"
+        );
+        assert_eq!(to_html("second"), "second");
+    }
+}
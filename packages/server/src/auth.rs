@@ -0,0 +1,261 @@
+use crate::db::DbPool;
+use crate::models::ApiToken;
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, AppError, Result};
+use axum::{
+    extract::{FromRequestParts, Query, Request, State},
+    http::{header, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, FromRow};
+use tracing::info;
+use uuid::Uuid;
+
+const TOKEN_PREFIX: &str = "dsp_";
+
+/// Berechtigungen eines API-Tokens. Höhere Scopes schließen niedrigere ein:
+/// `admin` ⊃ `write` ⊃ `read`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+/// Der authentifizierte Aufrufer eines Requests.
+/// Wird von [`require_auth`] in die Request-Extensions gelegt.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub username: String,
+    pub is_admin: bool,
+    pub token_id: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthUser {
+    pub fn has_scope(&self, required: Scope) -> bool {
+        self.scopes.iter().any(|scope| *scope >= required)
+    }
+
+    pub fn require_scope(&self, required: Scope) -> Result<()> {
+        if self.has_scope(required) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "token lacks the '{}' scope",
+                scope_name(required)
+            )))
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or_else(|| AppError::Unauthorized("missing credentials".to_string()))
+    }
+}
+
+fn scope_name(scope: Scope) -> &'static str {
+    match scope {
+        Scope::Read => "read",
+        Scope::Write => "write",
+        Scope::Admin => "admin",
+    }
+}
+
+/// Erzeugt ein zufälliges Secret (256 Bit, hex-kodiert) mit erkennbarem Präfix.
+pub fn generate_secret(prefix: &str) -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", prefix, hex::encode(bytes))
+}
+
+pub fn generate_token() -> String {
+    generate_secret(TOKEN_PREFIX)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Legt einen neuen Token an und gibt ihn einmalig im Klartext zurück.
+pub async fn issue_token(
+    db_pool: &DbPool,
+    user_id: &str,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<i64>,
+) -> Result<(ApiToken, String)> {
+    let token = generate_token();
+    let token_id = Uuid::new_v4().to_string();
+
+    let api_token = with_db!(db_pool, |pool| {
+        sqlx::query(
+            r#"
+            INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(&token_id)
+        .bind(user_id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(Json(scopes))
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+
+        sqlx::query_as::<_, ApiToken>("SELECT * FROM api_tokens WHERE id = $1")
+            .bind(&token_id)
+            .fetch_one(pool)
+            .await?
+    });
+    Ok((api_token, token))
+}
+
+#[derive(FromRow)]
+struct TokenOwnerRow {
+    token_id: String,
+    #[sqlx(json)]
+    scopes: Vec<Scope>,
+    expires_at: Option<i64>,
+    user_id: String,
+    username: String,
+    is_admin: bool,
+}
+
+/// Prüft einen Klartext-Token gegen die Datenbank.
+pub async fn authenticate(db_pool: &DbPool, token: &str) -> Result<AuthUser> {
+    let row = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, TokenOwnerRow>(
+            r#"
+            SELECT t.id AS token_id, t.scopes, t.expires_at,
+                   u.id AS user_id, u.username, u.is_admin
+            FROM api_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE t.token_hash = $1 AND t.revoked_at IS NULL
+            "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(pool)
+        .await?
+    })
+    .ok_or_else(|| AppError::Unauthorized("invalid or revoked token".to_string()))?;
+
+    let now = unix_timestamp();
+    if row.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::Unauthorized("token expired".to_string()));
+    }
+
+    with_db!(db_pool, |pool| {
+        sqlx::query("UPDATE api_tokens SET last_used_at = $1 WHERE id = $2")
+            .bind(now)
+            .bind(&row.token_id)
+            .execute(pool)
+            .await?;
+    });
+
+    Ok(AuthUser {
+        user_id: row.user_id,
+        username: row.username,
+        is_admin: row.is_admin,
+        token_id: row.token_id,
+        scopes: row.scopes,
+    })
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+fn is_websocket_upgrade(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Liest den Token aus `Authorization: Bearer <token>`.
+/// Browser können beim WebSocket-Upgrade keine Header setzen, dort ist
+/// zusätzlich `?access_token=<token>` erlaubt.
+fn extract_token(request: &Request) -> Option<String> {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+
+    if bearer.is_some() || !is_websocket_upgrade(request) {
+        return bearer;
+    }
+
+    Query::<TokenQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.access_token)
+}
+
+/// Middleware für alle `/api/*`-Routen: authentifiziert den Aufrufer und
+/// verlangt `read` für lesende und `write` für schreibende Requests.
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let token = extract_token(&request)
+        .ok_or_else(|| AppError::Unauthorized("missing bearer token".to_string()))?;
+    let auth_user = authenticate(&state.db_pool, &token).await?;
+
+    let required = if request.method().is_safe() {
+        Scope::Read
+    } else {
+        Scope::Write
+    };
+    auth_user.require_scope(required)?;
+
+    request.extensions_mut().insert(auth_user);
+    Ok(next.run(request).await)
+}
+
+/// Legt beim ersten Start einen Admin-Benutzer samt Token an,
+/// damit die API überhaupt benutzt werden kann.
+pub async fn bootstrap_admin(db_pool: &DbPool) -> Result<()> {
+    let user_count: i64 = with_db!(db_pool, |pool| {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(pool)
+            .await?
+    });
+    if user_count > 0 {
+        return Ok(());
+    }
+
+    let user_id = Uuid::new_v4().to_string();
+    with_db!(db_pool, |pool| {
+        sqlx::query("INSERT INTO users (id, username, is_admin) VALUES ($1, 'admin', $2)")
+            .bind(&user_id)
+            .bind(true)
+            .execute(pool)
+            .await?;
+    });
+    let (_, token) = issue_token(db_pool, &user_id, "bootstrap", &[Scope::Admin], None).await?;
+
+    info!("Kein Benutzer vorhanden, Admin-Benutzer 'admin' wurde angelegt.");
+    // Direkt auf stderr statt über tracing, damit das Token nicht in Log-Dateien
+    // oder beim OTLP-Collector landet
+    eprintln!("Admin-Token (wird nur einmal angezeigt): {}", token);
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use crate::auth::{AuthUser, Scope};
+use crate::db::DbPool;
+use crate::{with_db, AppError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Rolle eines Benutzers innerhalb eines Projekts. Höhere Rollen schließen
+/// die Rechte der niedrigeren ein.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Developer,
+    Maintainer,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Developer => "developer",
+            Role::Maintainer => "maintainer",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "viewer" => Ok(Role::Viewer),
+            "developer" => Ok(Role::Developer),
+            "maintainer" => Ok(Role::Maintainer),
+            "admin" => Ok(Role::Admin),
+            other => Err(AppError::BadRequest(format!("unknown role '{}'", other))),
+        }
+    }
+}
+
+/// Alles, was ein Benutzer in einem Projekt tun kann.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    ViewProject,
+    TriggerJob,
+    RerunJob,
+    CancelJob,
+    PinJob,
+    EditProject,
+    ManageMembers,
+    DeleteProject,
+}
+
+impl Action {
+    /// Mindestrolle im Projekt für diese Aktion.
+    pub fn required_role(&self) -> Role {
+        match self {
+            Action::ViewProject => Role::Viewer,
+            Action::TriggerJob | Action::RerunJob | Action::CancelJob => Role::Developer,
+            Action::PinJob | Action::EditProject | Action::ManageMembers => Role::Maintainer,
+            Action::DeleteProject => Role::Admin,
+        }
+    }
+
+    /// Mindest-Scope des verwendeten API-Tokens für diese Aktion.
+    pub fn required_scope(&self) -> Scope {
+        match self {
+            Action::ViewProject => Scope::Read,
+            _ => Scope::Write,
+        }
+    }
+}
+
+/// Die zentrale Berechtigungsprüfung: Der Token muss den nötigen Scope haben
+/// und der Benutzer muss globaler Admin sein oder im Projekt mindestens die
+/// für die Aktion nötige Rolle besitzen.
+pub fn authorize(user: &AuthUser, role: Option<Role>, action: Action) -> Result<()> {
+    user.require_scope(action.required_scope())?;
+
+    if user.is_admin {
+        return Ok(());
+    }
+    match role {
+        Some(role) if role >= action.required_role() => Ok(()),
+        Some(role) => Err(AppError::Forbidden(format!(
+            "role '{}' may not perform {:?}",
+            role, action
+        ))),
+        None => Err(AppError::Forbidden(
+            "not a member of this project".to_string(),
+        )),
+    }
+}
+
+pub async fn project_role(
+    db_pool: &DbPool,
+    user_id: &str,
+    project_id: &str,
+) -> Result<Option<Role>> {
+    let role: Option<String> = with_db!(db_pool, |pool| {
+        sqlx::query_scalar(
+            "SELECT role FROM project_members WHERE project_id = $1 AND user_id = $2",
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+    });
+    role.map(|role| role.parse()).transpose()
+}
+
+/// Die Rollen aller Mitglieder eines Projekts, für Prüfungen über viele Benutzer.
+pub async fn project_roles(db_pool: &DbPool, project_id: &str) -> Result<HashMap<String, Role>> {
+    let rows: Vec<(String, String)> = with_db!(db_pool, |pool| {
+        sqlx::query_as("SELECT user_id, role FROM project_members WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_all(pool)
+            .await?
+    });
+    rows.into_iter()
+        .map(|(user_id, role)| Ok((user_id, role.parse()?)))
+        .collect()
+}
+
+/// Lädt die Rolle des Benutzers im Projekt und prüft die Aktion.
+pub async fn authorize_project(
+    db_pool: &DbPool,
+    user: &AuthUser,
+    project_id: &str,
+    action: Action,
+) -> Result<()> {
+    let role = if user.is_admin {
+        None
+    } else {
+        project_role(db_pool, &user.user_id, project_id).await?
+    };
+    authorize(user, role, action)
+}
+
+/// Jobs ohne Projekt gehören niemandem und sind nur für globale Admins sichtbar.
+pub async fn authorize_job(
+    db_pool: &DbPool,
+    user: &AuthUser,
+    project_id: Option<&str>,
+    action: Action,
+) -> Result<()> {
+    match project_id {
+        Some(project_id) => authorize_project(db_pool, user, project_id, action).await,
+        None => authorize(user, None, action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [Option<Role>; 5] = [
+        None,
+        Some(Role::Viewer),
+        Some(Role::Developer),
+        Some(Role::Maintainer),
+        Some(Role::Admin),
+    ];
+
+    const ACTIONS: [Action; 8] = [
+        Action::ViewProject,
+        Action::TriggerJob,
+        Action::RerunJob,
+        Action::CancelJob,
+        Action::PinJob,
+        Action::EditProject,
+        Action::ManageMembers,
+        Action::DeleteProject,
+    ];
+
+    fn user(is_admin: bool, scopes: &[Scope]) -> AuthUser {
+        AuthUser {
+            user_id: "user".to_string(),
+            username: "user".to_string(),
+            is_admin,
+            token_id: "token".to_string(),
+            scopes: scopes.to_vec(),
+        }
+    }
+
+    /// Erlaubte Aktionen je Rolle, Zeilen wie `ROLES`, Spalten wie `ACTIONS`:
+    /// View, Trigger, Rerun, Cancel, Pin, Edit, Members, Delete.
+    const MATRIX: [[bool; 8]; 5] = [
+        [false, false, false, false, false, false, false, false],
+        [true, false, false, false, false, false, false, false],
+        [true, true, true, true, false, false, false, false],
+        [true, true, true, true, true, true, true, false],
+        [true, true, true, true, true, true, true, true],
+    ];
+
+    #[test]
+    fn project_roles_follow_the_matrix() {
+        let member = user(false, &[Scope::Write]);
+        for (role, allowed) in ROLES.into_iter().zip(MATRIX) {
+            for (action, allowed) in ACTIONS.into_iter().zip(allowed) {
+                let result = authorize(&member, role, action);
+                assert_eq!(result.is_ok(), allowed, "{:?} / {:?}", role, action);
+                if let Err(e) = result {
+                    assert!(matches!(e, AppError::Forbidden(_)), "{:?}", e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn global_admin_needs_no_membership() {
+        let admin = user(true, &[Scope::Admin]);
+        for action in ACTIONS {
+            assert!(authorize(&admin, None, action).is_ok(), "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn read_scope_only_allows_viewing() {
+        for is_admin in [false, true] {
+            let reader = user(is_admin, &[Scope::Read]);
+            for action in ACTIONS {
+                assert_eq!(
+                    authorize(&reader, Some(Role::Admin), action).is_ok(),
+                    action == Action::ViewProject,
+                    "admin={} / {:?}",
+                    is_admin,
+                    action
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,912 @@
+use crate::db::DbBackend;
+use crate::tls::GrpcTlsSettings;
+use crate::{AppError, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_CONFIG_FILE: &str = "deliversphere.toml";
+const DEFAULT_HTTP_ADDR: &str = "[::]:3000";
+const DEFAULT_GRPC_ADDR: &str = "[::]:3001";
+const DEFAULT_MAX_CONNECTIONS: u32 = 5;
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 10;
+const DEFAULT_HEARTBEAT_FLUSH_INTERVAL_SECS: u64 = 15;
+const DEFAULT_AGENT_OFFLINE_AFTER_SECS: u64 = 60;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_DATA_DIR: &str = "data";
+const DEFAULT_RETENTION_INTERVAL_SECS: u64 = 60 * 60;
+const DEFAULT_AGENT_METRICS_MAX_AGE_HOURS: u64 = 7 * 24;
+const MB: u64 = 1024 * 1024;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_OTEL_SERVICE_NAME: &str = "deliversphere-server";
+
+/// Die vollständige, geprüfte Konfiguration des Servers.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub http_addr: SocketAddr,
+    pub grpc_addr: SocketAddr,
+    pub database: DatabaseConfig,
+    /// Heartbeat-Intervall für Agents, die bei der Registrierung keines wünschen.
+    pub heartbeat_interval: Duration,
+    /// Wie oft die gesammelten Heartbeats in die Datenbank geschrieben werden.
+    pub heartbeat_flush_interval: Duration,
+    /// Nach dieser Zeit ohne Heartbeat gilt ein Agent als offline.
+    pub agent_offline_after: Duration,
+    /// So lange wartet der Server beim Herunterfahren auf offene Verbindungen.
+    pub shutdown_timeout: Duration,
+    pub data_dir: PathBuf,
+    pub log_dir: PathBuf,
+    pub tls: Option<GrpcTlsSettings>,
+    pub retention: RetentionConfig,
+    pub scheduling: SchedulingConfig,
+    pub features: Features,
+    pub logging: LoggingConfig,
+    pub otel: OtelConfig,
+    /// Die gelesene Config-Datei, falls vorhanden.
+    pub config_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+}
+
+/// Aufbewahrungsregeln für abgeschlossene Jobs samt Logs und Artefakten.
+/// Ohne gesetztes Limit wird nichts gelöscht.
+#[derive(Clone, Debug)]
+pub struct RetentionConfig {
+    /// Wie oft der Hintergrund-Task die Regeln anwendet.
+    pub interval: Duration,
+    pub max_age: Option<Duration>,
+    pub max_jobs_per_project: Option<u64>,
+    /// Obergrenze für Logs und Artefakte aller Jobs zusammen.
+    pub max_storage_bytes: Option<u64>,
+    /// Der letzte erfolgreiche Job jedes Projekts bleibt immer erhalten.
+    pub keep_last_successful: bool,
+    /// Ältere Messpunkte der Agents löscht der Agent-Health-Check.
+    pub agent_metrics_max_age: Duration,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some()
+            || self.max_jobs_per_project.is_some()
+            || self.max_storage_bytes.is_some()
+    }
+}
+
+/// Mindestwerte aus dem letzten Heartbeat, damit ein Agent Jobs bekommt.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchedulingConfig {
+    pub min_free_disk_bytes: Option<u64>,
+    pub min_available_memory_bytes: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Features {
+    /// Legt beim ersten Start einen Admin-Benutzer samt Token an.
+    pub bootstrap_admin: bool,
+    /// Stellt `/api/ws` für Live-Updates bereit.
+    pub websocket: bool,
+    /// Stellt `/metrics` für Prometheus bereit, ohne Authentifizierung.
+    pub metrics: bool,
+}
+
+/// Ausgabeformat der Server-Logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Lesbare Zeilen für die Konsole.
+    #[default]
+    Pretty,
+    /// Ein JSON-Objekt pro Zeile, samt Feldern der aktiven Spans.
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected 'pretty' or 'json'".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Filter im Format von `RUST_LOG`, z.B. `info,server::grpc_server=debug`.
+    pub level: String,
+}
+
+/// Export der Job-Traces per OTLP (gRPC), siehe [`crate::otel`].
+#[derive(Clone, Debug)]
+pub struct OtelConfig {
+    /// Adresse des Collectors, z.B. `http://localhost:4317`. Ohne Angabe kein Export.
+    pub endpoint: Option<String>,
+    pub service_name: String,
+}
+
+/// Überschreibungen per CLI-Flag, sie haben Vorrang vor Env-Vars und Config-Datei.
+#[derive(Debug, Default)]
+pub struct ConfigOverrides {
+    pub http_addr: Option<String>,
+    pub grpc_addr: Option<String>,
+    pub database_url: Option<String>,
+}
+
+/// Eine Ebene der Konfiguration. Spätere Ebenen überschreiben frühere:
+/// Defaults < Config-Datei < Env-Vars < CLI-Flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigLayer {
+    #[serde(default)]
+    server: ListenLayer,
+    #[serde(default)]
+    database: DatabaseLayer,
+    #[serde(default)]
+    timing: TimingLayer,
+    #[serde(default)]
+    storage: StorageLayer,
+    #[serde(default)]
+    tls: TlsLayer,
+    #[serde(default)]
+    retention: RetentionLayer,
+    #[serde(default)]
+    scheduling: SchedulingLayer,
+    #[serde(default)]
+    features: FeaturesLayer,
+    #[serde(default)]
+    logging: LoggingLayer,
+    #[serde(default)]
+    otel: OtelLayer,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenLayer {
+    http_addr: Option<String>,
+    grpc_addr: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DatabaseLayer {
+    url: Option<String>,
+    max_connections: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimingLayer {
+    heartbeat_interval_secs: Option<u64>,
+    /// `sweep_interval_secs` stammt aus der Zeit vor dem Liveness-Tracker.
+    #[serde(alias = "sweep_interval_secs")]
+    heartbeat_flush_interval_secs: Option<u64>,
+    agent_offline_after_secs: Option<u64>,
+    shutdown_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StorageLayer {
+    data_dir: Option<PathBuf>,
+    /// Default: `<data_dir>/logs`
+    log_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TlsLayer {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetentionLayer {
+    interval_secs: Option<u64>,
+    max_age_days: Option<u64>,
+    max_jobs_per_project: Option<u64>,
+    max_storage_mb: Option<u64>,
+    keep_last_successful: Option<bool>,
+    agent_metrics_max_age_hours: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchedulingLayer {
+    min_free_disk_mb: Option<u64>,
+    min_available_memory_mb: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeaturesLayer {
+    bootstrap_admin: Option<bool>,
+    websocket: Option<bool>,
+    metrics: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoggingLayer {
+    format: Option<LogFormat>,
+    level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OtelLayer {
+    endpoint: Option<String>,
+    service_name: Option<String>,
+}
+
+impl ConfigLayer {
+    fn merge(self, over: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            server: ListenLayer {
+                http_addr: over.server.http_addr.or(self.server.http_addr),
+                grpc_addr: over.server.grpc_addr.or(self.server.grpc_addr),
+            },
+            database: DatabaseLayer {
+                url: over.database.url.or(self.database.url),
+                max_connections: over
+                    .database
+                    .max_connections
+                    .or(self.database.max_connections),
+            },
+            timing: TimingLayer {
+                heartbeat_interval_secs: over
+                    .timing
+                    .heartbeat_interval_secs
+                    .or(self.timing.heartbeat_interval_secs),
+                heartbeat_flush_interval_secs: over
+                    .timing
+                    .heartbeat_flush_interval_secs
+                    .or(self.timing.heartbeat_flush_interval_secs),
+                agent_offline_after_secs: over
+                    .timing
+                    .agent_offline_after_secs
+                    .or(self.timing.agent_offline_after_secs),
+                shutdown_timeout_secs: over
+                    .timing
+                    .shutdown_timeout_secs
+                    .or(self.timing.shutdown_timeout_secs),
+            },
+            storage: StorageLayer {
+                data_dir: over.storage.data_dir.or(self.storage.data_dir),
+                log_dir: over.storage.log_dir.or(self.storage.log_dir),
+            },
+            tls: TlsLayer {
+                cert: over.tls.cert.or(self.tls.cert),
+                key: over.tls.key.or(self.tls.key),
+                client_ca: over.tls.client_ca.or(self.tls.client_ca),
+            },
+            retention: RetentionLayer {
+                interval_secs: over
+                    .retention
+                    .interval_secs
+                    .or(self.retention.interval_secs),
+                max_age_days: over.retention.max_age_days.or(self.retention.max_age_days),
+                max_jobs_per_project: over
+                    .retention
+                    .max_jobs_per_project
+                    .or(self.retention.max_jobs_per_project),
+                max_storage_mb: over
+                    .retention
+                    .max_storage_mb
+                    .or(self.retention.max_storage_mb),
+                keep_last_successful: over
+                    .retention
+                    .keep_last_successful
+                    .or(self.retention.keep_last_successful),
+                agent_metrics_max_age_hours: over
+                    .retention
+                    .agent_metrics_max_age_hours
+                    .or(self.retention.agent_metrics_max_age_hours),
+            },
+            scheduling: SchedulingLayer {
+                min_free_disk_mb: over
+                    .scheduling
+                    .min_free_disk_mb
+                    .or(self.scheduling.min_free_disk_mb),
+                min_available_memory_mb: over
+                    .scheduling
+                    .min_available_memory_mb
+                    .or(self.scheduling.min_available_memory_mb),
+            },
+            features: FeaturesLayer {
+                bootstrap_admin: over
+                    .features
+                    .bootstrap_admin
+                    .or(self.features.bootstrap_admin),
+                websocket: over.features.websocket.or(self.features.websocket),
+                metrics: over.features.metrics.or(self.features.metrics),
+            },
+            logging: LoggingLayer {
+                format: over.logging.format.or(self.logging.format),
+                level: over.logging.level.or(self.logging.level),
+            },
+            otel: OtelLayer {
+                endpoint: over.otel.endpoint.or(self.otel.endpoint),
+                service_name: over.otel.service_name.or(self.otel.service_name),
+            },
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<ConfigLayer> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            AppError::Config(format!(
+                "config file {} could not be read: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        toml::from_str(&content).map_err(|e| {
+            AppError::Config(format!("config file {} is invalid: {}", path.display(), e))
+        })
+    }
+
+    fn from_env() -> Result<ConfigLayer> {
+        Ok(ConfigLayer {
+            server: ListenLayer {
+                http_addr: non_empty_env("SERVER_HTTP_ADDR"),
+                grpc_addr: non_empty_env("SERVER_GRPC_ADDR"),
+            },
+            database: DatabaseLayer {
+                url: non_empty_env("DATABASE_URL"),
+                max_connections: parse_env("DATABASE_MAX_CONNECTIONS")?,
+            },
+            timing: TimingLayer {
+                heartbeat_interval_secs: parse_env("SERVER_HEARTBEAT_INTERVAL_SECS")?,
+                // SERVER_SWEEP_INTERVAL_SECS stammt aus der Zeit vor dem Liveness-Tracker
+                heartbeat_flush_interval_secs: match parse_env(
+                    "SERVER_HEARTBEAT_FLUSH_INTERVAL_SECS",
+                )? {
+                    Some(secs) => Some(secs),
+                    None => parse_env("SERVER_SWEEP_INTERVAL_SECS")?,
+                },
+                agent_offline_after_secs: parse_env("SERVER_AGENT_OFFLINE_AFTER_SECS")?,
+                shutdown_timeout_secs: parse_env("SERVER_SHUTDOWN_TIMEOUT_SECS")?,
+            },
+            storage: StorageLayer {
+                data_dir: non_empty_env("SERVER_DATA_DIR").map(PathBuf::from),
+                log_dir: non_empty_env("SERVER_LOG_DIR").map(PathBuf::from),
+            },
+            tls: TlsLayer {
+                cert: non_empty_env("GRPC_TLS_CERT").map(PathBuf::from),
+                key: non_empty_env("GRPC_TLS_KEY").map(PathBuf::from),
+                client_ca: non_empty_env("GRPC_TLS_CLIENT_CA").map(PathBuf::from),
+            },
+            retention: RetentionLayer {
+                interval_secs: parse_env("SERVER_RETENTION_INTERVAL_SECS")?,
+                max_age_days: parse_env("SERVER_RETENTION_MAX_AGE_DAYS")?,
+                max_jobs_per_project: parse_env("SERVER_RETENTION_MAX_JOBS_PER_PROJECT")?,
+                max_storage_mb: parse_env("SERVER_RETENTION_MAX_STORAGE_MB")?,
+                keep_last_successful: parse_env("SERVER_RETENTION_KEEP_LAST_SUCCESSFUL")?,
+                agent_metrics_max_age_hours: parse_env(
+                    "SERVER_RETENTION_AGENT_METRICS_MAX_AGE_HOURS",
+                )?,
+            },
+            scheduling: SchedulingLayer {
+                min_free_disk_mb: parse_env("SERVER_SCHEDULING_MIN_FREE_DISK_MB")?,
+                min_available_memory_mb: parse_env("SERVER_SCHEDULING_MIN_AVAILABLE_MEMORY_MB")?,
+            },
+            features: FeaturesLayer {
+                bootstrap_admin: parse_env("SERVER_FEATURE_BOOTSTRAP_ADMIN")?,
+                websocket: parse_env("SERVER_FEATURE_WEBSOCKET")?,
+                metrics: parse_env("SERVER_FEATURE_METRICS")?,
+            },
+            logging: LoggingLayer {
+                format: parse_env("SERVER_LOG_FORMAT")?,
+                level: non_empty_env("SERVER_LOG_LEVEL"),
+            },
+            otel: OtelLayer {
+                endpoint: non_empty_env("SERVER_OTEL_ENDPOINT"),
+                service_name: non_empty_env("SERVER_OTEL_SERVICE_NAME"),
+            },
+        })
+    }
+
+    fn from_overrides(overrides: &ConfigOverrides) -> ConfigLayer {
+        ConfigLayer {
+            server: ListenLayer {
+                http_addr: overrides.http_addr.clone(),
+                grpc_addr: overrides.grpc_addr.clone(),
+            },
+            database: DatabaseLayer {
+                url: overrides.database_url.clone(),
+                max_connections: None,
+            },
+            ..ConfigLayer::default()
+        }
+    }
+}
+
+fn invalid(key: &str, message: impl std::fmt::Display) -> AppError {
+    AppError::Config(format!("invalid value for '{}': {}", key, message))
+}
+
+fn non_empty_env(var: &str) -> Option<String> {
+    env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_env<T: std::str::FromStr>(var: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    non_empty_env(var)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|e| invalid(var, format!("'{}': {}", value, e)))
+        })
+        .transpose()
+}
+
+fn parse_addr(key: &str, value: Option<String>, default: &str) -> Result<SocketAddr> {
+    let value = value.unwrap_or_else(|| default.to_string());
+    value
+        .parse()
+        .map_err(|e| invalid(key, format!("'{}': {}", value, e)))
+}
+
+fn positive_secs(key: &str, value: Option<u64>, default: u64) -> Result<Duration> {
+    match value.unwrap_or(default) {
+        0 => Err(invalid(key, "must be at least 1")),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+fn positive_limit(key: &str, value: Option<u64>) -> Result<Option<u64>> {
+    match value {
+        Some(0) => Err(invalid(key, "must be at least 1 (omit it for no limit)")),
+        value => Ok(value),
+    }
+}
+
+/// Rechnet einen Wert in eine kleinere Einheit um (Tage in Sekunden, MB in Bytes).
+fn scaled(key: &str, value: u64, factor: u64) -> Result<u64> {
+    value
+        .checked_mul(factor)
+        .ok_or_else(|| invalid(key, format!("{} is too large", value)))
+}
+
+fn existing_file(key: &str, path: PathBuf) -> Result<PathBuf> {
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(invalid(key, format!("{} is not a file", path.display())))
+    }
+}
+
+fn directory(key: &str, path: PathBuf) -> Result<PathBuf> {
+    if path.exists() && !path.is_dir() {
+        return Err(invalid(
+            key,
+            format!("{} is not a directory", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+fn resolve(layer: ConfigLayer) -> Result<ServerConfig> {
+    let http_addr = parse_addr(
+        "server.http_addr",
+        layer.server.http_addr,
+        DEFAULT_HTTP_ADDR,
+    )?;
+    let grpc_addr = parse_addr(
+        "server.grpc_addr",
+        layer.server.grpc_addr,
+        DEFAULT_GRPC_ADDR,
+    )?;
+    if http_addr == grpc_addr {
+        return Err(invalid(
+            "server.grpc_addr",
+            "must differ from server.http_addr",
+        ));
+    }
+
+    let url = layer.database.url.ok_or_else(|| {
+        invalid(
+            "database.url",
+            "not set (config file, DATABASE_URL or --database-url)",
+        )
+    })?;
+    if DbBackend::from_url(&url).is_none() {
+        return Err(invalid(
+            "database.url",
+            "must start with sqlite: or postgres://",
+        ));
+    }
+    let max_connections = layer
+        .database
+        .max_connections
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+    if max_connections == 0 {
+        return Err(invalid("database.max_connections", "must be at least 1"));
+    }
+
+    let heartbeat_interval = positive_secs(
+        "timing.heartbeat_interval_secs",
+        layer.timing.heartbeat_interval_secs,
+        DEFAULT_HEARTBEAT_INTERVAL_SECS,
+    )?;
+    let heartbeat_flush_interval = positive_secs(
+        "timing.heartbeat_flush_interval_secs",
+        layer.timing.heartbeat_flush_interval_secs,
+        DEFAULT_HEARTBEAT_FLUSH_INTERVAL_SECS,
+    )?;
+    let agent_offline_after = positive_secs(
+        "timing.agent_offline_after_secs",
+        layer.timing.agent_offline_after_secs,
+        DEFAULT_AGENT_OFFLINE_AFTER_SECS,
+    )?;
+    if heartbeat_interval
+        .checked_mul(2)
+        .is_none_or(|twice| twice > agent_offline_after)
+    {
+        return Err(invalid(
+            "timing.heartbeat_interval_secs",
+            "must be at most half of timing.agent_offline_after_secs",
+        ));
+    }
+    let shutdown_timeout = positive_secs(
+        "timing.shutdown_timeout_secs",
+        layer.timing.shutdown_timeout_secs,
+        DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+    )?;
+
+    let data_dir = directory(
+        "storage.data_dir",
+        layer
+            .storage
+            .data_dir
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR)),
+    )?;
+    let log_dir = directory(
+        "storage.log_dir",
+        layer
+            .storage
+            .log_dir
+            .unwrap_or_else(|| data_dir.join("logs")),
+    )?;
+
+    let tls = match (layer.tls.cert, layer.tls.key) {
+        (Some(cert), Some(key)) => Some(GrpcTlsSettings {
+            cert: existing_file("tls.cert", cert)?,
+            key: existing_file("tls.key", key)?,
+            client_ca: layer
+                .tls
+                .client_ca
+                .map(|ca| existing_file("tls.client_ca", ca))
+                .transpose()?,
+        }),
+        (None, None) if layer.tls.client_ca.is_none() => None,
+        (None, None) => return Err(invalid("tls.client_ca", "requires tls.cert and tls.key")),
+        (Some(_), None) => return Err(invalid("tls.key", "must be set together with tls.cert")),
+        (None, Some(_)) => return Err(invalid("tls.cert", "must be set together with tls.key")),
+    };
+
+    // positive_secs prüft hier nur auf >= 1, der Wert sind Stunden
+    let agent_metrics_max_age_hours = positive_secs(
+        "retention.agent_metrics_max_age_hours",
+        layer.retention.agent_metrics_max_age_hours,
+        DEFAULT_AGENT_METRICS_MAX_AGE_HOURS,
+    )?
+    .as_secs();
+    let retention = RetentionConfig {
+        interval: positive_secs(
+            "retention.interval_secs",
+            layer.retention.interval_secs,
+            DEFAULT_RETENTION_INTERVAL_SECS,
+        )?,
+        max_age: positive_limit("retention.max_age_days", layer.retention.max_age_days)?
+            .map(|days| scaled("retention.max_age_days", days, 24 * 60 * 60))
+            .transpose()?
+            .map(Duration::from_secs),
+        max_jobs_per_project: positive_limit(
+            "retention.max_jobs_per_project",
+            layer.retention.max_jobs_per_project,
+        )?,
+        max_storage_bytes: positive_limit(
+            "retention.max_storage_mb",
+            layer.retention.max_storage_mb,
+        )?
+        .map(|mb| scaled("retention.max_storage_mb", mb, MB))
+        .transpose()?,
+        keep_last_successful: layer.retention.keep_last_successful.unwrap_or(true),
+        agent_metrics_max_age: Duration::from_secs(scaled(
+            "retention.agent_metrics_max_age_hours",
+            agent_metrics_max_age_hours,
+            60 * 60,
+        )?),
+    };
+    let scheduling = SchedulingConfig {
+        min_free_disk_bytes: layer
+            .scheduling
+            .min_free_disk_mb
+            .map(|mb| scaled("scheduling.min_free_disk_mb", mb, MB))
+            .transpose()?,
+        min_available_memory_bytes: layer
+            .scheduling
+            .min_available_memory_mb
+            .map(|mb| scaled("scheduling.min_available_memory_mb", mb, MB))
+            .transpose()?,
+    };
+
+    let log_level = layer
+        .logging
+        .level
+        .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+    if let Err(e) = EnvFilter::try_new(&log_level) {
+        return Err(invalid("logging.level", format!("'{}': {}", log_level, e)));
+    }
+
+    if let Some(endpoint) = &layer.otel.endpoint {
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            return Err(invalid(
+                "otel.endpoint",
+                format!("'{}' must start with http:// or https://", endpoint),
+            ));
+        }
+    }
+
+    Ok(ServerConfig {
+        http_addr,
+        grpc_addr,
+        database: DatabaseConfig {
+            url,
+            max_connections,
+        },
+        heartbeat_interval,
+        heartbeat_flush_interval,
+        agent_offline_after,
+        shutdown_timeout,
+        data_dir,
+        log_dir,
+        tls,
+        retention,
+        scheduling,
+        features: Features {
+            bootstrap_admin: layer.features.bootstrap_admin.unwrap_or(true),
+            websocket: layer.features.websocket.unwrap_or(true),
+            metrics: layer.features.metrics.unwrap_or(true),
+        },
+        logging: LoggingConfig {
+            format: layer.logging.format.unwrap_or_default(),
+            level: log_level,
+        },
+        otel: OtelConfig {
+            endpoint: layer.otel.endpoint,
+            service_name: layer
+                .otel
+                .service_name
+                .unwrap_or_else(|| DEFAULT_OTEL_SERVICE_NAME.to_string()),
+        },
+        config_file: None,
+    })
+}
+
+/// Lädt die Konfiguration aus Config-Datei, Env-Vars (inkl. `.env`) und CLI-Flags.
+/// Eine explizit angegebene Datei muss existieren, `deliversphere.toml` ist optional.
+pub fn load_config(
+    config_file: Option<&Path>,
+    overrides: &ConfigOverrides,
+) -> Result<ServerConfig> {
+    dotenvy::dotenv().ok();
+
+    let config_file = config_file
+        .map(Path::to_path_buf)
+        .or_else(|| non_empty_env("SERVER_CONFIG").map(PathBuf::from))
+        .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+
+    let file_layer = match &config_file {
+        Some(path) => ConfigLayer::from_file(path)?,
+        None => ConfigLayer::default(),
+    };
+    let mut config = resolve(
+        file_layer
+            .merge(ConfigLayer::from_env()?)
+            .merge(ConfigLayer::from_overrides(overrides)),
+    )?;
+    config.config_file = config_file;
+    Ok(config)
+}
+
+impl ServerConfig {
+    /// Verzeichnis mit den Logs eines Jobs.
+    pub fn job_log_dir(&self, job_id: &str) -> PathBuf {
+        self.log_dir.join(job_id)
+    }
+
+    /// Verzeichnis mit den Artefakten eines Jobs.
+    pub fn job_artifact_dir(&self, job_id: &str) -> PathBuf {
+        self.data_dir.join("artifacts").join(job_id)
+    }
+
+    /// Die Datenbank-URL ohne Passwort, für Log-Ausgaben.
+    pub fn redacted_database_url(&self) -> String {
+        match (self.database.url.find("://"), self.database.url.rfind('@')) {
+            (Some(scheme_end), Some(at)) if at > scheme_end => format!(
+                "{}://***{}",
+                &self.database.url[..scheme_end],
+                &self.database.url[at..]
+            ),
+            _ => self.database.url.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer() -> ConfigLayer {
+        ConfigLayer {
+            database: DatabaseLayer {
+                url: Some("sqlite:test.db".to_string()),
+                max_connections: None,
+            },
+            ..ConfigLayer::default()
+        }
+    }
+
+    fn error(layer: ConfigLayer) -> String {
+        match resolve(layer) {
+            Err(AppError::Config(message)) => message,
+            other => panic!("kein Konfigurationsfehler: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cli_overrides_env_overrides_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("deliversphere.toml");
+        fs::write(
+            &path,
+            r#"
+            [server]
+            http_addr = "127.0.0.1:4000"
+            grpc_addr = "127.0.0.1:4001"
+
+            [database]
+            url = "sqlite:file.db"
+            max_connections = 7
+
+            [retention]
+            max_storage_mb = 10
+            "#,
+        )
+        .unwrap();
+        // Der einzige Test, der Env-Vars setzt
+        env::set_var("SERVER_GRPC_ADDR", "127.0.0.1:5001");
+        env::set_var("DATABASE_URL", "sqlite:env.db");
+        env::set_var("SERVER_RETENTION_MAX_STORAGE_MB", "20");
+        let overrides = ConfigOverrides {
+            database_url: Some("sqlite:cli.db".to_string()),
+            ..ConfigOverrides::default()
+        };
+        let config = load_config(Some(&path), &overrides);
+
+        env::set_var("SERVER_RETENTION_MAX_AGE_DAYS", u64::MAX.to_string());
+        let invalid = load_config(Some(&path), &overrides);
+        for var in [
+            "SERVER_GRPC_ADDR",
+            "DATABASE_URL",
+            "SERVER_RETENTION_MAX_STORAGE_MB",
+            "SERVER_RETENTION_MAX_AGE_DAYS",
+        ] {
+            env::remove_var(var);
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.http_addr.to_string(), "127.0.0.1:4000");
+        assert_eq!(config.grpc_addr.to_string(), "127.0.0.1:5001");
+        assert_eq!(config.database.url, "sqlite:cli.db");
+        assert_eq!(config.database.max_connections, 7);
+        assert_eq!(config.retention.max_storage_bytes, Some(20 * MB));
+        assert_eq!(config.config_file, Some(path));
+        assert!(
+            matches!(&invalid, Err(AppError::Config(message)) if message.contains("'retention.max_age_days'")),
+            "{:?}",
+            invalid
+        );
+    }
+
+    #[test]
+    fn defaults_apply_without_settings() {
+        let config = resolve(layer()).unwrap();
+        assert_eq!(config.http_addr.to_string(), DEFAULT_HTTP_ADDR);
+        assert_eq!(config.log_dir, PathBuf::from(DEFAULT_DATA_DIR).join("logs"));
+        assert!(!config.retention.is_enabled());
+        assert_eq!(
+            config.retention.agent_metrics_max_age,
+            Duration::from_secs(DEFAULT_AGENT_METRICS_MAX_AGE_HOURS * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn values_too_large_name_the_key() {
+        let huge = Some(u64::MAX);
+        let cases = [
+            (
+                ConfigLayer {
+                    retention: RetentionLayer {
+                        max_age_days: huge,
+                        ..RetentionLayer::default()
+                    },
+                    ..layer()
+                },
+                "retention.max_age_days",
+            ),
+            (
+                ConfigLayer {
+                    retention: RetentionLayer {
+                        max_storage_mb: huge,
+                        ..RetentionLayer::default()
+                    },
+                    ..layer()
+                },
+                "retention.max_storage_mb",
+            ),
+            (
+                ConfigLayer {
+                    retention: RetentionLayer {
+                        agent_metrics_max_age_hours: huge,
+                        ..RetentionLayer::default()
+                    },
+                    ..layer()
+                },
+                "retention.agent_metrics_max_age_hours",
+            ),
+            (
+                ConfigLayer {
+                    scheduling: SchedulingLayer {
+                        min_free_disk_mb: huge,
+                        min_available_memory_mb: None,
+                    },
+                    ..layer()
+                },
+                "scheduling.min_free_disk_mb",
+            ),
+            (
+                ConfigLayer {
+                    scheduling: SchedulingLayer {
+                        min_free_disk_mb: None,
+                        min_available_memory_mb: huge,
+                    },
+                    ..layer()
+                },
+                "scheduling.min_available_memory_mb",
+            ),
+            (
+                ConfigLayer {
+                    timing: TimingLayer {
+                        heartbeat_interval_secs: huge,
+                        agent_offline_after_secs: huge,
+                        ..TimingLayer::default()
+                    },
+                    ..layer()
+                },
+                "timing.heartbeat_interval_secs",
+            ),
+        ];
+        for (layer, key) in cases {
+            let message = error(layer);
+            assert!(message.contains(&format!("'{}'", key)), "{}", message);
+        }
+    }
+}
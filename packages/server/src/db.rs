@@ -1,19 +1,154 @@
+use crate::config::DatabaseConfig;
 use crate::{AppError, Result};
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
-use std::env;
+use sqlx::migrate::Migrator;
+use sqlx::{
+    postgres::PgPoolOptions, sqlite::SqlitePoolOptions, PgPool, Postgres, QueryBuilder, Sqlite,
+    SqlitePool,
+};
+use tracing::info;
 
-pub type DbPool = Pool<Sqlite>;
+/// Verbindungspool für das per `DATABASE_URL` gewählte Backend.
+///
+/// Queries werden mit [`with_db!`](crate::with_db) für beide Backends
+/// ausgeführt und verwenden `$1, $2, ...` als Platzhalter, das verstehen
+/// SQLite und Postgres gleichermaßen. Was sich darüber hinaus unterscheidet,
+/// steckt in [`Dialect`].
+#[derive(Clone, Debug)]
+pub enum DbPool {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbBackend {
+    Sqlite,
+    Postgres,
+}
+
+impl DbBackend {
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("sqlite:") {
+            Some(DbBackend::Sqlite)
+        } else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Some(DbBackend::Postgres)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DbBackend::Sqlite => "sqlite",
+            DbBackend::Postgres => "postgres",
+        }
+    }
+}
+
+impl DbPool {
+    /// Wartet auf laufende Queries und schließt alle Verbindungen.
+    pub async fn close(&self) {
+        crate::with_db!(self, |pool| pool.close().await)
+    }
+
+    pub fn backend(&self) -> DbBackend {
+        match self {
+            DbPool::Sqlite(_) => DbBackend::Sqlite,
+            DbPool::Postgres(_) => DbBackend::Postgres,
+        }
+    }
+}
+
+/// SQL, das sich zwischen den Backends unterscheidet. Innerhalb von
+/// [`with_db!`](crate::with_db) wählt der Typ des Pools die Implementierung,
+/// z.B. `Dialect::push_log_match(&mut sql, terms)`.
+pub trait Dialect: sqlx::Database {
+    /// Die eingebetteten Migrationen des Backends.
+    fn migrator() -> &'static Migrator;
+
+    /// Schränkt die Log-Zeilen `l` (`job_log_lines`) auf solche ein, die alle
+    /// Begriffe aus `terms` enthalten; beginnt mit `JOIN` bzw. `WHERE`.
+    fn push_log_match(sql: &mut QueryBuilder<'_, Self>, terms: &str);
+}
+
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Macht aus der Eingabe eine FTS5-Abfrage, in der jeder Begriff als Phrase
+/// vorkommen muss. Operatoren und Spaltenfilter der Eingabe wirken so nicht.
+fn fts5_query(input: &str) -> String {
+    input
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Dialect for Sqlite {
+    fn migrator() -> &'static Migrator {
+        &SQLITE_MIGRATOR
+    }
+
+    fn push_log_match(sql: &mut QueryBuilder<'_, Self>, terms: &str) {
+        sql.push("JOIN job_log_search s ON s.rowid = l.id WHERE job_log_search MATCH ")
+            .push_bind(fts5_query(terms));
+    }
+}
+
+impl Dialect for Postgres {
+    fn migrator() -> &'static Migrator {
+        &POSTGRES_MIGRATOR
+    }
+
+    fn push_log_match(sql: &mut QueryBuilder<'_, Self>, terms: &str) {
+        sql.push("WHERE l.search @@ plainto_tsquery('simple', ")
+            .push_bind(terms.to_string())
+            .push(")");
+    }
+}
+
+/// Führt `body` mit dem konkreten Pool des Backends aus. Der Block wird für
+/// jedes Backend einzeln kompiliert und muss in beiden Fällen denselben Typ
+/// liefern, z.B. `.execute(pool).await?.rows_affected()` statt des
+/// backend-spezifischen `QueryResult`.
+#[macro_export]
+macro_rules! with_db {
+    ($pool:expr, |$db:ident| $body:expr) => {
+        match $pool {
+            $crate::db::DbPool::Sqlite($db) => {
+                let _timer = $crate::metrics::db_query_timer("sqlite");
+                $body
+            }
+            $crate::db::DbPool::Postgres($db) => {
+                let _timer = $crate::metrics::db_query_timer("postgres");
+                $body
+            }
+        }
+    };
+}
 
-pub async fn init_pool() -> Result<DbPool> {
-    dotenvy::dotenv().ok();
-    let db_url = env::var("DATABASE_URL")
-        .map_err(|_| AppError::MissingEnvVar("DATABASE_URL".to_string()))?;
+pub async fn init_pool(config: &DatabaseConfig) -> Result<DbPool> {
+    let backend = DbBackend::from_url(&config.url).ok_or_else(|| {
+        AppError::Config("DATABASE_URL must start with sqlite: or postgres://".to_string())
+    })?;
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
-        .await?;
+    let pool = match backend {
+        DbBackend::Sqlite => DbPool::Sqlite(
+            SqlitePoolOptions::new()
+                .max_connections(config.max_connections)
+                .connect(&config.url)
+                .await?,
+        ),
+        DbBackend::Postgres => DbPool::Postgres(
+            PgPoolOptions::new()
+                .max_connections(config.max_connections)
+                .connect(&config.url)
+                .await?,
+        ),
+    };
 
-    println!("Datenbank-Pool erfolgreich initialisiert.");
+    info!(
+        "Datenbank-Pool erfolgreich initialisiert ({}).",
+        backend.name()
+    );
     Ok(pool)
 }
@@ -1,3 +1,8 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde_json;
 use sqlx;
 use std::env;
@@ -5,6 +10,7 @@ use std::io;
 use thiserror::Error;
 use tokio;
 use tonic;
+use tracing::error;
 use uuid;
 
 #[derive(Error, Debug)]
@@ -15,6 +21,9 @@ pub enum AppError {
     #[error("Configuration error: {0}")]
     ConfigVar(#[from] env::VarError),
 
+    #[error("Configuration error: {0}")]
+    Config(String),
+
     #[error("Invalid Server URL: {0}")]
     InvalidServerUrl(#[from] tonic::transport::Error),
 
@@ -24,8 +33,11 @@ pub enum AppError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
-    #[error("gRPC error: {0}")]
-    GrpcStatus(#[from] tonic::Status),
+    #[error("Migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+
+    #[error("Database schema error: {0}")]
+    Schema(String),
 
     #[error("Network error binding server: {0}")]
     ServerBind(io::Error),
@@ -44,6 +56,91 @@ pub enum AppError {
 
     #[error("Other I/O error: {0}")]
     Io(io::Error),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("Metrics error: {0}")]
+    Metrics(#[from] prometheus::Error),
+}
+
+impl AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::BadRequest(_) | AppError::MessageParse(_) | AppError::Uuid(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(sqlx::Error::Database(e))
+                if e.is_unique_violation() || e.is_foreign_key_violation() =>
+            {
+                StatusCode::CONFLICT
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Meldung für den API-Client, ohne interne Details (z.B. SQL-Fehler).
+    fn public_message(&self) -> String {
+        match self {
+            AppError::Database(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                "Resource already exists".to_string()
+            }
+            AppError::Database(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+                "Resource is still referenced by other resources".to_string()
+            }
+            AppError::Database(sqlx::Error::RowNotFound) => "Not found".to_string(),
+            AppError::Unavailable(_) => self.to_string(),
+            _ if self.status_code().is_server_error() => "Internal server error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("Request failed: {}", self);
+        }
+        let message = self.public_message();
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
+
+impl From<AppError> for tonic::Status {
+    fn from(error: AppError) -> Self {
+        match error {
+            AppError::Unauthorized(message) => tonic::Status::unauthenticated(message),
+            AppError::Forbidden(message) => tonic::Status::permission_denied(message),
+            AppError::NotFound(message) => tonic::Status::not_found(message),
+            AppError::BadRequest(message) => tonic::Status::invalid_argument(message),
+            AppError::Unavailable(message) => tonic::Status::unavailable(message),
+            other => {
+                error!("gRPC request failed: {}", other);
+                tonic::Status::internal("internal server error")
+            }
+        }
+    }
+}
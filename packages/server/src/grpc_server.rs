@@ -2,30 +2,58 @@ pub mod runner {
     tonic::include_proto!("runner");
 }
 
-use crate::{db::DbPool, models::Agent, LiveAgentMap, WsClientMap, WsServerMessage};
+use crate::{
+    agent_auth, agent_metrics,
+    db::DbPool,
+    job_logs::LogStore,
+    jobs,
+    liveness::{Liveness, StreamEnd},
+    metrics,
+    models::Agent,
+    protocol::{self, Feature},
+    shutdown::Shutdown,
+    tls, unix_timestamp, with_db, AppError, LiveAgent, LiveAgentMap, WsClientMap, WsServerMessage,
+};
 
+use sqlx::types::Json as JsonColumn;
 use std::pin::Pin;
 use std::time::SystemTime;
 use tokio::sync::mpsc;
 use tokio_stream::{Stream, StreamExt};
-use tonic::{Request, Response, Status, Streaming};
+use tonic::{metadata::MetadataMap, Request, Response, Status, Streaming};
 
 pub use runner::runner_service_server::RunnerServiceServer;
 use runner::{
-    agent_request::Payload, runner_service_server::RunnerService, AgentRequest, ServerCommand,
+    agent_request::Payload, runner_service_server::RunnerService,
+    server_command::Payload as CommandPayload, Ack, AgentRequest, CancelJob, EnrollRequest,
+    EnrollResponse, RegisterAck, ServerCommand, SessionSettings,
 };
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 pub struct MyRunnerService {
     pub db_pool: DbPool,
     pub live_agents: LiveAgentMap,
     pub ws_clients: WsClientMap,
+    pub logs: LogStore,
+    pub liveness: Liveness,
+    pub shutdown: Shutdown,
+}
+
+impl MyRunnerService {
+    /// Während des Herunterfahrens werden keine neuen Agents angenommen.
+    fn reject_during_shutdown(&self) -> crate::Result<()> {
+        if self.shutdown.is_triggered() {
+            return Err(AppError::Unavailable("server is shutting down".to_string()));
+        }
+        Ok(())
+    }
 }
 
-async fn broadcast_ws_message(clients: &WsClientMap, message: &WsServerMessage) {
+pub async fn broadcast_ws_message(clients: &WsClientMap, message: &WsServerMessage) {
     for entry in clients.iter() {
-        let tx = entry.value();
+        let tx = &entry.value().tx;
         if tx.send(message.clone()).is_err() {
-            println!(
+            debug!(
                 "Failed to send WS message to client {}, will be cleaned up on next disconnect.",
                 entry.key()
             );
@@ -33,6 +61,81 @@ async fn broadcast_ws_message(clients: &WsClientMap, message: &WsServerMessage)
     }
 }
 
+/// Trace-ID der Session aus den Metadaten des Agents. Ältere Agents senden
+/// keine, ungültige Werte werden ersetzt, damit sie nicht ungeprüft ins Log gelangen.
+fn session_trace_id(metadata: &MetadataMap) -> String {
+    metadata
+        .get(protocol::TRACE_ID_METADATA)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= 64
+                && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string())
+}
+
+pub async fn fetch_agent(db_pool: &DbPool, agent_id: &str) -> sqlx::Result<Option<Agent>> {
+    with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, Agent>("SELECT * FROM agents WHERE id = $1")
+            .bind(agent_id)
+            .fetch_optional(pool)
+            .await
+    })
+}
+
+/// Gleicht die Jobs ab, die der Agent bei der Registrierung meldet: Laufende
+/// Jobs des Agents, die er nicht mehr kennt, schlagen fehl; die übrigen laufen
+/// weiter. Jobs, die der Server schon abgeschlossen hat, bricht der Agent ab.
+async fn reconcile_jobs(
+    db_pool: &DbPool,
+    ws_clients: &WsClientMap,
+    logs: &LogStore,
+    tx: &mpsc::Sender<Result<ServerCommand, Status>>,
+    agent_id: &str,
+    reported: &[String],
+) {
+    match jobs::fail_agent_jobs(db_pool, ws_clients, logs, agent_id, reported).await {
+        Ok(failed) if !failed.is_empty() => info!(
+            "{} Job(s) von Agent '{}' nach dem Reconnect verloren: {:?}",
+            failed.len(),
+            agent_id,
+            failed
+        ),
+        Ok(_) => {}
+        Err(e) => error!(
+            "Konnte Jobs von Agent '{}' nicht abgleichen: {}",
+            agent_id, e
+        ),
+    }
+    if reported.is_empty() {
+        return;
+    }
+    let running = match jobs::running_agent_jobs(db_pool, agent_id).await {
+        Ok(running) => running,
+        Err(e) => {
+            error!(
+                "Konnte Jobs von Agent '{}' nicht abgleichen: {}",
+                agent_id, e
+            );
+            return;
+        }
+    };
+    for job_id in reported {
+        if running.contains(job_id) {
+            info!("Job {} läuft auf Agent '{}' weiter.", job_id, agent_id);
+            continue;
+        }
+        let cancel = ServerCommand {
+            payload: Some(CommandPayload::Cancel(CancelJob {
+                job_id: job_id.clone(),
+            })),
+        };
+        let _ = tx.send(Ok(cancel)).await;
+    }
+}
+
 #[tonic::async_trait]
 impl RunnerService for MyRunnerService {
     type CommunicateStream =
@@ -42,6 +145,19 @@ impl RunnerService for MyRunnerService {
         &self,
         request_stream: Request<Streaming<AgentRequest>>,
     ) -> Result<Response<Self::CommunicateStream>, Status> {
+        self.reject_during_shutdown()?;
+        // Zertifikat bzw. Credential legen fest, als welcher Agent sich der Stream registrieren darf
+        let certificate_identity = request_stream
+            .peer_certs()
+            .and_then(|certs| tls::peer_identity(&certs));
+        let authenticated_agent_id = agent_auth::resolve_agent_identity(
+            &self.db_pool,
+            request_stream.metadata(),
+            certificate_identity,
+        )
+        .await?;
+        let trace_id = session_trace_id(request_stream.metadata());
+        let span = info_span!("agent_session", agent_id = %authenticated_agent_id, %trace_id);
         let mut inbound = request_stream.into_inner();
         let (tx, rx) = mpsc::channel(128);
 
@@ -51,67 +167,181 @@ impl RunnerService for MyRunnerService {
         let db_pool = self.db_pool.clone();
         let live_agents = self.live_agents.clone();
         let ws_clients = self.ws_clients.clone();
+        let liveness = self.liveness.clone();
+        let logs = self.logs.clone();
 
         tokio::spawn(async move {
+            let _stream_guard = metrics::GrpcStreamGuard::open();
             let agent_id: Option<String>;
+            let stream: u64;
+            let resumable: bool;
 
             if let Some(Ok(first_msg)) = inbound.next().await {
                 if let Some(Payload::Register(reg)) = first_msg.payload {
-                    println!("Agent '{}' registriert sich...", &reg.agent_id);
+                    if reg.agent_id != authenticated_agent_id {
+                        warn!(
+                            "Agent '{}' versuchte sich als '{}' zu registrieren, abgelehnt.",
+                            authenticated_agent_id, reg.agent_id
+                        );
+                        let _ = tx
+                            .send(Err(Status::permission_denied(
+                                "credential does not belong to this agent_id",
+                            )))
+                            .await;
+                        return;
+                    }
+                    info!(
+                        "Agent '{}' registriert sich (Protokoll {}, Version '{}')...",
+                        &reg.agent_id, reg.protocol_version, reg.agent_version
+                    );
                     agent_id = Some(reg.agent_id.clone());
 
+                    let negotiated =
+                        protocol::negotiate(reg.protocol_version, reg.min_protocol_version);
+                    let (status, protocol_version, incompatible_reason) = match &negotiated {
+                        Ok(version) => ("online", *version, None),
+                        Err(reason) => ("incompatible", reg.protocol_version, Some(reason)),
+                    };
+
                     let now = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .unwrap_or_default()
                         .as_secs() as i64;
 
-                    let query = sqlx::query(
-                        r#"
-                        INSERT INTO agents (id, hostname, status, last_heartbeat)
-                        VALUES (?, ?, 'online', ?)
-                        ON CONFLICT(id) DO UPDATE SET
-                            hostname = excluded.hostname, status = 'online', last_heartbeat = excluded.last_heartbeat
-                        "#,
-                    )
-                    .bind(&reg.agent_id)
-                    .bind(&reg.hostname)
-                    .bind(now)
-                    .execute(&db_pool)
-                    .await;
+                    let query = with_db!(&db_pool, |pool| {
+                        sqlx::query(
+                            r#"
+                            INSERT INTO agents (
+                                id, hostname, status, last_heartbeat,
+                                protocol_version, agent_version, incompatible_reason,
+                                labels, concurrency
+                            )
+                            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                            ON CONFLICT(id) DO UPDATE SET
+                                hostname = excluded.hostname, status = excluded.status,
+                                last_heartbeat = excluded.last_heartbeat,
+                                protocol_version = excluded.protocol_version,
+                                agent_version = excluded.agent_version,
+                                incompatible_reason = excluded.incompatible_reason,
+                                labels = excluded.labels, concurrency = excluded.concurrency
+                            "#,
+                        )
+                        .bind(&reg.agent_id)
+                        .bind(&reg.hostname)
+                        .bind(status)
+                        .bind(now)
+                        .bind(protocol_version as i64)
+                        .bind(&reg.agent_version)
+                        .bind(incompatible_reason)
+                        .bind(JsonColumn(&reg.labels))
+                        .bind(reg.concurrency.max(1) as i64)
+                        .execute(pool)
+                        .await
+                        .map(|_| ())
+                    });
 
                     if let Err(e) = query {
-                        eprintln!("DB-Fehler bei Agent-Registrierung: {}", e);
+                        error!("DB-Fehler bei Agent-Registrierung: {}", e);
                         return;
                     }
 
-                    let agent_result =
-                        sqlx::query_as::<_, Agent>("SELECT * FROM agents WHERE id = ?")
-                            .bind(&reg.agent_id)
-                            .fetch_optional(&db_pool)
-                            .await;
+                    let agent_result = fetch_agent(&db_pool, &reg.agent_id).await;
 
                     if let Ok(Some(agent)) = agent_result {
                         let update_msg = WsServerMessage::AgentUpdate { agent };
-                        println!("Broadcasting WS AgentUpdate: {:?}", update_msg);
+                        debug!("Broadcasting WS AgentUpdate: {:?}", update_msg);
                         broadcast_ws_message(&ws_clients, &update_msg).await;
                     } else {
-                        eprintln!(
+                        error!(
                             "Konnte Agent {} nach Registrierung nicht aus DB laden.",
                             reg.agent_id
                         );
                     }
 
-                    live_agents.insert(reg.agent_id, tx.clone());
-                    println!(
-                        "Agent '{}' ist jetzt online (Registrierung abgeschlossen).",
-                        agent_id.as_ref().unwrap()
+                    let protocol_version = match negotiated {
+                        Ok(version) => version,
+                        Err(reason) => {
+                            warn!("Agent '{}' abgelehnt: {}", reg.agent_id, reason);
+                            let ack = RegisterAck {
+                                accepted: false,
+                                protocol_version: protocol::CURRENT_VERSION,
+                                settings: None,
+                                rejection_reason: reason,
+                                ..RegisterAck::default()
+                            };
+                            let _ = tx
+                                .send(Ok(ServerCommand {
+                                    payload: Some(CommandPayload::RegisterAck(ack)),
+                                }))
+                                .await;
+                            // Der Stream endet ohne Fehlerstatus, tonic würde das
+                            // gepufferte Ack sonst zugunsten des Status verwerfen
+                            return;
+                        }
+                    };
+
+                    // Agents vor Version 3 können eine Session nicht fortsetzen
+                    resumable = Feature::SessionResume.supported_by(protocol_version);
+                    let heartbeat_interval =
+                        liveness.negotiate_interval(reg.heartbeat_interval_secs);
+                    let registration = liveness.register(
+                        &reg.agent_id,
+                        if resumable {
+                            &reg.resume_session_id
+                        } else {
+                            ""
+                        },
+                        heartbeat_interval,
+                    );
+                    stream = registration.stream;
+
+                    // Agents vor Version 2 kennen kein RegisterAck und behalten ihr Intervall
+                    if Feature::RegisterAck.supported_by(protocol_version) {
+                        let ack = RegisterAck {
+                            accepted: true,
+                            protocol_version,
+                            settings: Some(SessionSettings {
+                                heartbeat_interval_secs: heartbeat_interval.as_secs() as u32,
+                                offline_after_secs: liveness.offline_after().as_secs() as u32,
+                            }),
+                            rejection_reason: String::new(),
+                            session_id: if resumable {
+                                registration.session_id.clone()
+                            } else {
+                                String::new()
+                            },
+                            resumed: registration.resumed,
+                            last_seq: registration.last_seq,
+                        };
+                        let command = ServerCommand {
+                            payload: Some(CommandPayload::RegisterAck(ack)),
+                        };
+                        // Bricht der Stream hier ab, endet gleich auch die Schleife unten
+                        let _ = tx.send(Ok(command)).await;
+                    }
+                    live_agents.insert(
+                        reg.agent_id.clone(),
+                        LiveAgent {
+                            tx: tx.clone(),
+                            protocol_version,
+                        },
+                    );
+                    reconcile_jobs(&db_pool, &ws_clients, &logs, &tx, &reg.agent_id, &reg.job_ids).await;
+                    info!(
+                        "Agent '{}' ist jetzt online (Registrierung abgeschlossen{}).",
+                        reg.agent_id,
+                        if registration.resumed {
+                            ", Session fortgesetzt"
+                        } else {
+                            ""
+                        }
                     );
                 } else {
-                    eprintln!("Fehler: Erste Nachricht war nicht 'RegisterAgent'");
+                    warn!("Fehler: Erste Nachricht war nicht 'RegisterAgent'");
                     return;
                 }
             } else {
-                eprintln!("Agent hat Verbindung vor Registrierung getrennt");
+                warn!("Agent hat Verbindung vor Registrierung getrennt");
                 return;
             }
 
@@ -122,42 +352,231 @@ impl RunnerService for MyRunnerService {
 
             while let Some(result) = inbound.next().await {
                 if let Ok(request) = result {
-                    if let Some(Payload::Heartbeat(_)) = request.payload {
-                        let now = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs() as i64;
-                        let _ = sqlx::query("UPDATE agents SET last_heartbeat = ? WHERE id = ?")
-                            .bind(now)
-                            .bind(&current_agent_id)
-                            .execute(&db_pool)
-                            .await;
+                    // Nach einem Resume erneut gesendete Nachrichten nur einmal verarbeiten
+                    let seq = request.seq;
+                    if !liveness.accept(&current_agent_id, stream, seq) {
+                        continue;
+                    }
+                    let processed = match request.payload {
+                        Some(Payload::Heartbeat(heartbeat)) => {
+                            // Der Zeitpunkt wird gesammelt vom Heartbeat-Task gespeichert
+                            if resumable {
+                                if let Some(seq) = liveness.last_seq(&current_agent_id, stream) {
+                                    let ack = ServerCommand {
+                                        payload: Some(CommandPayload::Ack(Ack { seq })),
+                                    };
+                                    let _ = tx.try_send(Ok(ack));
+                                }
+                            }
+                            if liveness.heartbeat(&current_agent_id, stream) {
+                                // Ein Agent im Drain bleibt auch nach der Lücke im Drain
+                                let status = liveness.live_status(&current_agent_id, stream);
+                                info!(
+                                    "Agent '{}' sendet wieder Heartbeats, markiere als {}.",
+                                    current_agent_id, status
+                                );
+                                let _ = with_db!(&db_pool, |pool| {
+                                    sqlx::query(
+                                        "UPDATE agents SET status = $1, last_heartbeat = $2 WHERE id = $3",
+                                    )
+                                    .bind(status)
+                                    .bind(unix_timestamp())
+                                    .bind(&current_agent_id)
+                                    .execute(pool)
+                                    .await
+                                    .map(|_| ())
+                                });
+                                if let Ok(Some(agent)) =
+                                    fetch_agent(&db_pool, &current_agent_id).await
+                                {
+                                    broadcast_ws_message(
+                                        &ws_clients,
+                                        &WsServerMessage::AgentUpdate { agent },
+                                    )
+                                    .await;
+                                }
+                            }
+                            if let Some(telemetry) = heartbeat.telemetry {
+                                match agent_metrics::record(&db_pool, &current_agent_id, &telemetry)
+                                    .await
+                                {
+                                    Ok(metrics) => {
+                                        broadcast_ws_message(
+                                            &ws_clients,
+                                            &WsServerMessage::AgentMetrics { metrics },
+                                        )
+                                        .await
+                                    }
+                                    Err(e) => error!(
+                                        "Konnte Telemetrie von Agent {} nicht speichern: {}",
+                                        current_agent_id, e
+                                    ),
+                                }
+                            }
+                            true
+                        }
+                        Some(Payload::Result(result)) => {
+                            let span = info_span!("job", job_id = %result.job_id);
+                            async {
+                                match jobs::finish_job(
+                                    &db_pool,
+                                    &ws_clients,
+                                    &logs,
+                                    &current_agent_id,
+                                    &result.job_id,
+                                    &jobs::JobOutcome::from_result(&result),
+                                )
+                                .await
+                                {
+                                    Ok(Some(job)) => {
+                                        info!("Job beendet: {}.", job.status);
+                                        true
+                                    }
+                                    Ok(None) => true,
+                                    Err(e) => {
+                                        error!("Konnte Ergebnis nicht speichern: {}", e);
+                                        false
+                                    }
+                                }
+                            }
+                            .instrument(span)
+                            .await
+                        }
+                        Some(Payload::Log(log)) => {
+                            match logs.append(&current_agent_id, &log).await {
+                                Ok(()) => true,
+                                Err(e) => {
+                                    error!(job_id = %log.job_id, "Konnte Log nicht speichern: {}", e);
+                                    false
+                                }
+                            }
+                        }
+                        Some(Payload::Step(step)) => {
+                            let span = info_span!("job", job_id = %step.job_id);
+                            async {
+                                match jobs::record_step(&db_pool, &ws_clients, &current_agent_id, &step)
+                                    .await
+                                {
+                                    Ok(_) => true,
+                                    Err(e) => {
+                                        error!("Konnte Schritt {} nicht speichern: {}", step.index, e);
+                                        false
+                                    }
+                                }
+                            }
+                            .instrument(span)
+                            .await
+                        }
+                        Some(Payload::Draining(draining)) => {
+                            liveness.mark_draining(&current_agent_id, stream, draining.stopping);
+                            info!(
+                                "Agent '{}' nimmt keine neuen Jobs mehr an ({}).",
+                                current_agent_id, draining.reason
+                            );
+                            let _ = with_db!(&db_pool, |pool| {
+                                sqlx::query("UPDATE agents SET status = 'draining' WHERE id = $1")
+                                    .bind(&current_agent_id)
+                                    .execute(pool)
+                                    .await
+                                    .map(|_| ())
+                            });
+                            if let Ok(Some(agent)) = fetch_agent(&db_pool, &current_agent_id).await
+                            {
+                                broadcast_ws_message(
+                                    &ws_clients,
+                                    &WsServerMessage::AgentUpdate { agent },
+                                )
+                                .await;
+                            }
+                            true
+                        }
+                        _ => true,
+                    };
+                    // Erst die verarbeitete Nachricht gilt dem Agent gegenüber als bestätigt
+                    if processed {
+                        liveness.processed(&current_agent_id, stream, seq);
+                    } else if seq != 0 {
+                        // Spätere Nachrichten nicht verarbeiten, sonst kämen sie nach dem
+                        // Resume doppelt an; der Agent sendet ab dieser Nachricht erneut
+                        warn!(
+                            "Nachricht {} von Agent '{}' nicht verarbeitet, beende den Stream.",
+                            seq, current_agent_id
+                        );
+                        break;
                     }
                 } else {
-                    eprintln!("Fehler beim Empfangen von Agent '{}'", current_agent_id);
+                    warn!("Fehler beim Empfangen von Agent '{}'", current_agent_id);
                     break;
                 }
             }
 
-            println!("Agent '{}' hat die Verbindung getrennt.", current_agent_id);
+            info!("Agent '{}' hat die Verbindung getrennt.", current_agent_id);
+            let last_heartbeat = match liveness.disconnect(&current_agent_id, stream, resumable) {
+                // Hat sich der Agent inzwischen neu verbunden, gehört der Eintrag der neuen Session
+                StreamEnd::Superseded => return,
+                StreamEnd::Detached => {
+                    live_agents.remove(&current_agent_id);
+                    info!(
+                        "Session von Agent '{}' kann bis zur Offline-Frist fortgesetzt werden.",
+                        current_agent_id
+                    );
+                    return;
+                }
+                StreamEnd::Closed { last_heartbeat } => last_heartbeat,
+            };
             live_agents.remove(&current_agent_id);
-            let _ = sqlx::query("UPDATE agents SET status = 'offline' WHERE id = ?")
+            let _ = with_db!(&db_pool, |pool| {
+                sqlx::query(
+                    "UPDATE agents SET status = 'offline', last_heartbeat = $1 WHERE id = $2",
+                )
+                .bind(last_heartbeat)
                 .bind(&current_agent_id)
-                .execute(&db_pool)
-                .await;
+                .execute(pool)
+                .await
+                .map(|_| ())
+            });
 
-            let agent_result = sqlx::query_as::<_, Agent>("SELECT * FROM agents WHERE id = ?")
-                .bind(&current_agent_id)
-                .fetch_optional(&db_pool)
-                .await;
+            let agent_result = fetch_agent(&db_pool, &current_agent_id).await;
 
             if let Ok(Some(agent)) = agent_result {
                 let update_msg = WsServerMessage::AgentUpdate { agent };
-                println!("Broadcasting WS AgentUpdate (disconnect): {:?}", update_msg);
+                debug!("Broadcasting WS AgentUpdate (disconnect): {:?}", update_msg);
                 broadcast_ws_message(&ws_clients, &update_msg).await;
             }
-        });
+            if let Err(e) =
+                jobs::fail_agent_jobs(&db_pool, &ws_clients, &logs, &current_agent_id, &[]).await
+            {
+                error!(
+                    "Konnte Jobs von Agent '{}' nicht abschließen: {}",
+                    current_agent_id, e
+                );
+            }
+        }.instrument(span));
 
         Ok(response)
     }
+
+    async fn enroll(
+        &self,
+        request: Request<EnrollRequest>,
+    ) -> Result<Response<EnrollResponse>, Status> {
+        self.reject_during_shutdown()?;
+        let enroll = request.into_inner();
+        if enroll.agent_id.trim().is_empty() {
+            return Err(Status::invalid_argument("agent_id must not be empty"));
+        }
+
+        let credential =
+            agent_auth::enroll_agent(&self.db_pool, &enroll.enrollment_token, &enroll.agent_id)
+                .await?;
+        info!(
+            "Agent '{}' ({}) hat sich mit einem Enrollment-Token angemeldet.",
+            enroll.agent_id, enroll.hostname
+        );
+
+        Ok(Response::new(EnrollResponse {
+            agent_id: enroll.agent_id,
+            credential,
+        }))
+    }
 }
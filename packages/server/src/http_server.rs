@@ -1,100 +1,211 @@
+use crate::auth::{self, AuthUser};
+use crate::authz;
 use crate::db::DbPool;
 use crate::state::AppState;
-use crate::{models::Agent, WsClientMap, WsClientMessage, WsServerMessage};
+use crate::{
+    agent_metrics, agents, job_logs, jobs, log_search, metrics, projects, retention, users,
+};
+use crate::{
+    models::{Agent, Job},
+    with_db, WsClient, WsClientMap, WsClientMessage, WsClientTx, WsServerMessage,
+};
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
         State,
     },
+    middleware,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{delete, get, post, put},
     Router,
 };
 use futures_util::{sink::SinkExt, stream::StreamExt};
 use serde_json;
-use sqlx;
+use std::collections::HashMap;
 use tokio::sync::mpsc;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
 async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(app_state): State<AppState>,
+    auth: AuthUser,
 ) -> impl IntoResponse {
-    println!("New WebSocket connection attempt");
-    ws.on_upgrade(move |socket| handle_socket(socket, app_state.ws_clients, app_state.db_pool))
+    info!("New WebSocket connection attempt by '{}'", auth.username);
+    ws.on_upgrade(move |socket| {
+        let client_id = Uuid::new_v4();
+        let span = info_span!("ws_client", %client_id, user = %auth.username);
+        handle_socket(socket, app_state, auth, client_id).instrument(span)
+    })
+}
+
+/// Sendet eine Nachricht an alle Clients, deren Benutzer das Projekt sehen darf.
+/// Die Mitglieder werden einmal pro Nachricht geladen, nicht pro Client.
+pub async fn broadcast_project_message(
+    db_pool: &DbPool,
+    ws_clients: &WsClientMap,
+    project_id: Option<&str>,
+    message: WsServerMessage,
+) {
+    let roles = match project_id {
+        Some(project_id) => match authz::project_roles(db_pool, project_id).await {
+            Ok(roles) => roles,
+            Err(e) => {
+                // Ohne Mitgliederliste erhalten nur globale Admins die Nachricht.
+                error!("Failed to load members of project {}: {}", project_id, e);
+                HashMap::new()
+            }
+        },
+        None => HashMap::new(),
+    };
+
+    for entry in ws_clients.iter() {
+        let (client_id, client) = (entry.key(), entry.value());
+        let role = roles.get(&client.user.user_id).copied();
+        let allowed = authz::authorize(&client.user, role, authz::Action::ViewProject).is_ok();
+        if allowed && client.tx.send(message.clone()).is_err() {
+            debug!(
+                "Failed to send WS message to client {}, will be cleaned up on next disconnect.",
+                client_id
+            );
+        }
+    }
+}
+
+/// Sendet ein Job-Update an alle Clients, deren Benutzer das Projekt des Jobs sehen darf.
+pub async fn broadcast_job_update(db_pool: &DbPool, ws_clients: &WsClientMap, job: &Job) {
+    let message = WsServerMessage::JobUpdate { job: job.clone() };
+    broadcast_project_message(db_pool, ws_clients, job.project_id.as_deref(), message).await;
+}
+
+async fn handle_client_message(
+    app_state: &AppState,
+    auth: &AuthUser,
+    tx: &WsClientTx,
+    client_msg: WsClientMessage,
+) {
+    match client_msg {
+        WsClientMessage::RequestRerun { job_id } => {
+            match jobs::create_rerun(app_state, auth, &job_id).await {
+                Ok(job) => {
+                    broadcast_job_update(&app_state.db_pool, &app_state.ws_clients, &job).await
+                }
+                Err(e) => {
+                    let _ = tx.send(WsServerMessage::Error {
+                        message: format!("Rerun of job {} failed: {}", job_id, e),
+                    });
+                }
+            }
+        }
+    }
 }
 
-async fn handle_socket(socket: WebSocket, clients: WsClientMap, db_pool: DbPool) {
-    let client_id = Uuid::new_v4();
-    println!("WebSocket client connected: {}", client_id);
+async fn handle_socket(socket: WebSocket, app_state: AppState, auth: AuthUser, client_id: Uuid) {
+    let clients = app_state.ws_clients.clone();
+    let db_pool = app_state.db_pool.clone();
+    info!(
+        "WebSocket client connected: {} (user '{}')",
+        client_id, auth.username
+    );
 
     let (mut sender, mut receiver) = socket.split();
 
     let (tx, mut rx) = mpsc::unbounded_channel::<WsServerMessage>();
 
-    clients.insert(client_id, tx);
+    clients.insert(
+        client_id,
+        WsClient {
+            tx: tx.clone(),
+            user: auth.clone(),
+        },
+    );
 
-    let initial_agents = sqlx::query_as::<_, Agent>("SELECT * FROM agents")
-        .fetch_all(&db_pool)
-        .await
-        .unwrap_or_else(|e| {
-            eprintln!("DB Error fetching initial state for {}: {}", client_id, e);
-            vec![]
-        });
+    let initial_agents = with_db!(&db_pool, |pool| {
+        sqlx::query_as::<_, Agent>("SELECT * FROM agents")
+            .fetch_all(pool)
+            .await
+    })
+    .unwrap_or_else(|e| {
+        error!("DB Error fetching initial state for {}: {}", client_id, e);
+        vec![]
+    });
 
     let initial_msg = WsServerMessage::InitialState {
         agents: initial_agents,
     };
     if let Ok(json_msg) = serde_json::to_string(&initial_msg) {
         if sender.send(Message::Text(json_msg.into())).await.is_err() {
-            eprintln!("Failed to send initial state to {}", client_id);
+            warn!("Failed to send initial state to {}", client_id);
             clients.remove(&client_id);
-            println!("WebSocket client disconnected early: {}", client_id);
+            debug!("WebSocket client disconnected early: {}", client_id);
             return;
         }
-        println!("Sent initial state to {}", client_id);
+        debug!("Sent initial state to {}", client_id);
     }
 
-    let send_task = tokio::spawn(async move {
-        while let Some(msg_to_send) = rx.recv().await {
-            if let Ok(json_msg) = serde_json::to_string(&msg_to_send) {
-                if sender.send(Message::Text(json_msg.into())).await.is_err() {
-                    println!("Send failed for {}, breaking send task.", client_id);
-                    break;
+    let shutdown = app_state.shutdown.clone();
+    let send_task = tokio::spawn(
+        async move {
+            loop {
+                let msg_to_send = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break,
+                    },
+                    _ = shutdown.wait() => {
+                        // Der Client soll den Shutdown nicht als Verbindungsabbruch sehen
+                        let close = Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server is shutting down".into(),
+                        }));
+                        let _ = sender.send(close).await;
+                        break;
+                    }
+                };
+                if let Ok(json_msg) = serde_json::to_string(&msg_to_send) {
+                    if sender.send(Message::Text(json_msg.into())).await.is_err() {
+                        debug!("Send failed for {}, breaking send task.", client_id);
+                        break;
+                    }
                 }
             }
         }
-    });
+        .in_current_span(),
+    );
 
-    let recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            match msg {
-                Message::Text(text) => {
-                    println!("Received text from {}: {}", client_id, text);
-                    if let Ok(client_msg) = serde_json::from_str::<WsClientMessage>(&text) {
-                        println!("Parsed client message: {:?}", client_msg);
-                    } else {
-                        eprintln!("Failed to parse client message from {}", client_id);
+    let recv_task = tokio::spawn(
+        async move {
+            while let Some(Ok(msg)) = receiver.next().await {
+                match msg {
+                    Message::Text(text) => {
+                        debug!("Received text from {}: {}", client_id, text);
+                        if let Ok(client_msg) = serde_json::from_str::<WsClientMessage>(&text) {
+                            debug!("Parsed client message: {:?}", client_msg);
+                            handle_client_message(&app_state, &auth, &tx, client_msg).await;
+                        } else {
+                            warn!("Failed to parse client message from {}", client_id);
+                        }
+                    }
+                    Message::Binary(_) => {
+                        debug!("Received binary data (unhandled) from {}", client_id);
+                    }
+                    Message::Ping(_) | Message::Pong(_) => {}
+                    Message::Close(_) => {
+                        debug!("Client {} sent close frame.", client_id);
+                        break;
                     }
-                }
-                Message::Binary(_) => {
-                    println!("Received binary data (unhandled) from {}", client_id);
-                }
-                Message::Ping(_) | Message::Pong(_) => {}
-                Message::Close(_) => {
-                    println!("Client {} sent close frame.", client_id);
-                    break;
                 }
             }
         }
-    });
+        .in_current_span(),
+    );
 
     tokio::select! {
         _ = send_task => { /* Send task finished, probably an error */ },
         _ = recv_task => { /* Receive task finished, client closed or error */ },
     }
 
-    println!("WebSocket client disconnected: {}", client_id);
+    info!("WebSocket client disconnected: {}", client_id);
     clients.remove(&client_id);
 }
 
@@ -103,8 +214,76 @@ async fn health_check_handler() -> Html<&'static str> {
 }
 
 pub fn create_router(app_state: AppState) -> Router {
-    Router::new()
-        .route("/", get(health_check_handler))
-        .route("/api/ws", get(websocket_handler))
-        .with_state(app_state)
+    let mut api_routes = Router::new();
+    if app_state.config.features.websocket {
+        api_routes = api_routes.route("/api/ws", get(websocket_handler));
+    }
+    let api_routes = api_routes
+        .route("/api/auth/me", get(users::current_user))
+        .route("/api/agents", get(agents::list_agents))
+        .route(
+            "/api/agents/enrollment-tokens",
+            post(agents::create_enrollment_token),
+        )
+        .route("/api/agents/{id}/drain", post(agents::drain_agent))
+        .route(
+            "/api/agents/{id}/metrics",
+            get(agent_metrics::list_agent_metrics),
+        )
+        .route(
+            "/api/agents/{id}/credential",
+            delete(agents::revoke_agent_credential),
+        )
+        .route(
+            "/api/tokens",
+            get(users::list_tokens).post(users::create_token),
+        )
+        .route("/api/tokens/{id}", delete(users::revoke_token))
+        .route(
+            "/api/users",
+            get(users::list_users).post(users::create_user),
+        )
+        .route("/api/users/{id}/tokens", post(users::create_user_token))
+        .route(
+            "/api/projects",
+            get(projects::list_projects).post(projects::create_project),
+        )
+        .route(
+            "/api/projects/{id}",
+            get(projects::get_project)
+                .put(projects::update_project)
+                .delete(projects::delete_project),
+        )
+        .route("/api/projects/{id}/jobs", get(projects::list_project_jobs))
+        .route("/api/projects/{id}/members", get(projects::list_members))
+        .route(
+            "/api/projects/{id}/members/{user_id}",
+            put(projects::set_member_role).delete(projects::remove_member),
+        )
+        .route(
+            "/api/projects/{id}/pipelines",
+            get(projects::list_project_pipelines),
+        )
+        .route("/api/jobs", get(jobs::list_jobs))
+        .route("/api/jobs/{id}", get(jobs::get_job))
+        .route("/api/jobs/{id}/steps", get(jobs::list_job_steps))
+        .route("/api/jobs/{id}/log", get(job_logs::get_job_log))
+        .route("/api/search/logs", get(log_search::search_logs))
+        .route("/api/jobs/{id}/rerun", post(jobs::rerun_job))
+        .route(
+            "/api/jobs/{id}/pin",
+            put(jobs::pin_job).delete(jobs::unpin_job),
+        )
+        .route("/api/retention/preview", get(retention::preview_retention))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::require_auth,
+        ));
+
+    let mut router = Router::new().route("/", get(health_check_handler));
+    // Prometheus scrapt ohne Token, daher außerhalb der API-Routen
+    if app_state.config.features.metrics {
+        router = router.route("/metrics", get(metrics::metrics_handler));
+    }
+    router.merge(api_routes).with_state(app_state)
 }
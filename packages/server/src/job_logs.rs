@@ -0,0 +1,1036 @@
+//! Speicherung der Job-Logs. Die Ausgabe eines Jobs wird in Chunks mit
+//! fortlaufender Nummer abgelegt, je Chunk höchstens ein Schritt. Die Chunks
+//! liegen als Dateien im Log-Verzeichnis des Jobs, der Index (Position im
+//! gesamten Log in Bytes und Zeilen) steht in `job_log_chunks`. Nach Abschluss
+//! des Jobs werden die Chunks einzeln mit gzip komprimiert, Bereiche lassen
+//! sich so weiterhin lesen, ohne das ganze Log zu entpacken. Neben jedem
+//! Chunk liegt eine `.meta`-Datei mit Zeitpunkt und Stream jeder Zeile, die
+//! Log-Datei selbst bleibt die unveränderte Ausgabe samt ANSI-Sequenzen.
+
+use crate::ansi;
+use crate::auth::AuthUser;
+use crate::authz::{self, Action};
+use crate::config::ServerConfig;
+use crate::db::DbPool;
+use crate::grpc_server::runner::{LogMessage, LogStream};
+use crate::jobs;
+use crate::state::AppState;
+use crate::{with_db, AppError, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use dashmap::DashMap;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{debug, error};
+
+/// Ab dieser Größe beginnt ein neuer Chunk.
+const CHUNK_MAX_BYTES: i64 = 256 * 1024;
+/// Zeilen, die ein Follower zurückliegen darf, bevor sein Stream endet.
+const FOLLOW_BUFFER: usize = 1024;
+/// Je Zeile in der `.meta`-Datei: Zeitpunkt in Millisekunden (u64, little
+/// endian) und Stream (u8, Wert von `LogStream`).
+const META_RECORD_BYTES: usize = 9;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct LogChunk {
+    pub seq: i64,
+    pub step_index: Option<i64>,
+    pub byte_offset: i64,
+    pub byte_length: i64,
+    pub line_offset: i64,
+    pub line_count: i64,
+    pub compressed: bool,
+}
+
+/// Zeitpunkt und Stream einer Zeile. Fehlen sie (Logs älterer Agents oder
+/// nach einem Absturz), sind beide 0 bzw. `Unspecified`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineMeta {
+    pub timestamp_ms: u64,
+    pub stream: LogStream,
+}
+
+impl LineMeta {
+    fn encode(&self) -> [u8; META_RECORD_BYTES] {
+        let mut record = [0; META_RECORD_BYTES];
+        record[..8].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        record[8] = self.stream as u8;
+        record
+    }
+
+    fn decode(record: &[u8]) -> Self {
+        let timestamp: [u8; 8] = record[..8].try_into().expect("record has 9 bytes");
+        LineMeta {
+            timestamp_ms: u64::from_le_bytes(timestamp),
+            stream: LogStream::try_from(i32::from(record[8])).unwrap_or_default(),
+        }
+    }
+}
+
+fn stream_name(stream: LogStream) -> Option<&'static str> {
+    match stream {
+        LogStream::Unspecified => None,
+        LogStream::Stdout => Some("stdout"),
+        LogStream::Stderr => Some("stderr"),
+    }
+}
+
+/// Eine Zeile, wie sie Follower per Server-Sent Event bekommen.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Zeilennummer im gesamten Log, ab 0.
+    pub line: i64,
+    pub step_index: Option<i64>,
+    /// `stdout` oder `stderr`, fehlt bei Meldungen des Agents.
+    pub stream: Option<&'static str>,
+    pub timestamp_ms: Option<u64>,
+    pub output: String,
+}
+
+impl LogLine {
+    fn new(line: i64, step_index: Option<i64>, meta: LineMeta, output: String) -> Self {
+        LogLine {
+            line,
+            step_index,
+            stream: stream_name(meta.stream),
+            timestamp_ms: (meta.timestamp_ms > 0).then_some(meta.timestamp_ms),
+            output,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LogEvent {
+    Line(LogLine),
+    /// Der Job ist abgeschlossen, es kommen keine Zeilen mehr.
+    End,
+}
+
+struct OpenChunk {
+    seq: i64,
+    step_index: Option<i64>,
+    file: tokio::fs::File,
+    meta: tokio::fs::File,
+    bytes: i64,
+    lines: i64,
+}
+
+/// Log eines laufenden Jobs, in das gerade geschrieben wird.
+struct OpenLog {
+    agent_id: String,
+    /// Ende der bereits indexierten Chunks.
+    next_seq: i64,
+    byte_offset: i64,
+    line_offset: i64,
+    current: Option<OpenChunk>,
+    closed: bool,
+}
+
+impl OpenLog {
+    fn current_chunk(&self) -> Option<LogChunk> {
+        self.current.as_ref().map(|chunk| LogChunk {
+            seq: chunk.seq,
+            step_index: chunk.step_index,
+            byte_offset: self.byte_offset,
+            byte_length: chunk.bytes,
+            line_offset: self.line_offset,
+            line_count: chunk.lines,
+            compressed: false,
+        })
+    }
+}
+
+fn chunk_file_name(seq: i64, step_index: Option<i64>) -> String {
+    match step_index {
+        Some(index) => format!("{:06}-step{}.log", seq, index),
+        None => format!("{:06}-job.log", seq),
+    }
+}
+
+fn meta_file_name(seq: i64, step_index: Option<i64>) -> String {
+    let name = chunk_file_name(seq, step_index);
+    format!("{}.meta", name.trim_end_matches(".log"))
+}
+
+/// Liest eine Datei, nach der Komprimierung die `.gz`-Datei daneben.
+fn read_plain_or_gz(plain: &std::path::Path, compressed: &std::path::Path) -> io::Result<Vec<u8>> {
+    match std::fs::read(plain) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut data = Vec::new();
+            GzDecoder::new(std::fs::File::open(compressed)?).read_to_end(&mut data)?;
+            Ok(data)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Schritt eines Chunks aus dem Dateinamen, `None` wenn die Datei nicht zu `seq` gehört.
+fn parse_chunk_file_name(name: &str, seq: i64) -> Option<Option<i64>> {
+    let rest = name
+        .strip_prefix(&format!("{:06}-", seq))?
+        .strip_suffix(".log")?;
+    match rest {
+        "job" => Some(None),
+        _ => rest.strip_prefix("step")?.parse().ok().map(Some),
+    }
+}
+
+/// Die Logs aller Jobs. Klone teilen sich den Zustand.
+#[derive(Clone)]
+pub struct LogStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: Arc<ServerConfig>,
+    db_pool: DbPool,
+    open: DashMap<String, Arc<Mutex<OpenLog>>>,
+    followers: DashMap<String, broadcast::Sender<LogEvent>>,
+    /// Weckt den Suchindex, sobald das Log eines Jobs abgeschlossen ist.
+    finished: Notify,
+}
+
+impl LogStore {
+    pub fn new(config: Arc<ServerConfig>, db_pool: DbPool) -> Self {
+        LogStore {
+            inner: Arc::new(Inner {
+                config,
+                db_pool,
+                open: DashMap::new(),
+                followers: DashMap::new(),
+                finished: Notify::new(),
+            }),
+        }
+    }
+
+    fn chunk_path(&self, job_id: &str, chunk: &LogChunk, compressed: bool) -> PathBuf {
+        let mut name = chunk_file_name(chunk.seq, chunk.step_index);
+        if compressed {
+            name.push_str(".gz");
+        }
+        self.inner.config.job_log_dir(job_id).join(name)
+    }
+
+    fn meta_path(&self, job_id: &str, chunk: &LogChunk, compressed: bool) -> PathBuf {
+        let mut name = meta_file_name(chunk.seq, chunk.step_index);
+        if compressed {
+            name.push_str(".gz");
+        }
+        self.inner.config.job_log_dir(job_id).join(name)
+    }
+
+    async fn indexed_chunks(&self, job_id: &str) -> Result<Vec<LogChunk>> {
+        let chunks = with_db!(&self.inner.db_pool, |pool| {
+            sqlx::query_as::<_, LogChunk>(
+                "SELECT seq, step_index, byte_offset, byte_length, line_offset, line_count, \
+                 compressed FROM job_log_chunks WHERE job_id = $1 ORDER BY seq",
+            )
+            .bind(job_id)
+            .fetch_all(pool)
+            .await?
+        });
+        Ok(chunks)
+    }
+
+    /// Öffnet das Log eines Jobs, der gerade auf dem Agent läuft. Nach einem
+    /// Neustart des Servers wird ein noch nicht indexierter Chunk fortgesetzt.
+    async fn load(&self, agent_id: &str, job_id: &str) -> Result<Option<OpenLog>> {
+        let running: i64 = with_db!(&self.inner.db_pool, |pool| {
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM jobs WHERE id = $1 AND agent_id = $2 AND status = 'running'",
+            )
+            .bind(job_id)
+            .bind(agent_id)
+            .fetch_one(pool)
+            .await?
+        });
+        if running == 0 {
+            return Ok(None);
+        }
+
+        let mut log = OpenLog {
+            agent_id: agent_id.to_string(),
+            next_seq: 0,
+            byte_offset: 0,
+            line_offset: 0,
+            current: None,
+            closed: false,
+        };
+        if let Some(last) = self.indexed_chunks(job_id).await?.last() {
+            log.next_seq = last.seq + 1;
+            log.byte_offset = last.byte_offset + last.byte_length;
+            log.line_offset = last.line_offset + last.line_count;
+        }
+
+        let dir = self.inner.config.job_log_dir(job_id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(AppError::Io)?;
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(AppError::Io)?;
+        while let Some(entry) = entries.next_entry().await.map_err(AppError::Io)? {
+            let name = entry.file_name();
+            let Some(step_index) = parse_chunk_file_name(&name.to_string_lossy(), log.next_seq)
+            else {
+                continue;
+            };
+            let data = tokio::fs::read(entry.path()).await.map_err(AppError::Io)?;
+            let lines = data.iter().filter(|byte| **byte == b'\n').count() as i64;
+            let file = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(entry.path())
+                .await
+                .map_err(AppError::Io)?;
+            let meta = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(meta_file_name(log.next_seq, step_index)))
+                .await
+                .map_err(AppError::Io)?;
+            // Ein Absturz zwischen beiden Schreibvorgängen lässt die Metadaten zurück
+            meta.set_len(lines as u64 * META_RECORD_BYTES as u64)
+                .await
+                .map_err(AppError::Io)?;
+            log.current = Some(OpenChunk {
+                seq: log.next_seq,
+                step_index,
+                file,
+                meta,
+                bytes: data.len() as i64,
+                lines,
+            });
+            break;
+        }
+        Ok(Some(log))
+    }
+
+    async fn open_log(&self, agent_id: &str, job_id: &str) -> Result<Option<Arc<Mutex<OpenLog>>>> {
+        if let Some(log) = self.inner.open.get(job_id) {
+            return Ok(Some(log.clone()));
+        }
+        let Some(log) = self.load(agent_id, job_id).await? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.inner
+                .open
+                .entry(job_id.to_string())
+                .or_insert_with(|| Arc::new(Mutex::new(log)))
+                .clone(),
+        ))
+    }
+
+    /// Nimmt den aktuellen Chunk in den Index auf.
+    async fn seal(&self, job_id: &str, log: &mut OpenLog) -> Result<()> {
+        let Some(mut chunk) = log.current.take() else {
+            return Ok(());
+        };
+        chunk.file.flush().await.map_err(AppError::Io)?;
+        chunk.meta.flush().await.map_err(AppError::Io)?;
+        with_db!(&self.inner.db_pool, |pool| {
+            sqlx::query(
+                "INSERT INTO job_log_chunks \
+                 (job_id, seq, step_index, byte_offset, byte_length, line_offset, line_count) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(job_id)
+            .bind(chunk.seq)
+            .bind(chunk.step_index)
+            .bind(log.byte_offset)
+            .bind(chunk.bytes)
+            .bind(log.line_offset)
+            .bind(chunk.lines)
+            .execute(pool)
+            .await?;
+        });
+        log.next_seq = chunk.seq + 1;
+        log.byte_offset += chunk.bytes;
+        log.line_offset += chunk.lines;
+        Ok(())
+    }
+
+    /// Hängt die Ausgabe eines Agents an das Log an. Ausgaben zu Jobs, die
+    /// nicht auf diesem Agent laufen, werden verworfen.
+    pub async fn append(&self, agent_id: &str, message: &LogMessage) -> Result<()> {
+        let job_id = &message.job_id;
+        let Some(log) = self.open_log(agent_id, job_id).await? else {
+            debug!("Log für Job {} verworfen, der Job läuft nicht.", job_id);
+            return Ok(());
+        };
+        let mut log = log.lock().await;
+        if log.closed || log.agent_id != agent_id {
+            return Ok(());
+        }
+
+        let step_index = message.step_index.map(i64::from);
+        let lines: Vec<&str> = message
+            .output
+            .split('\n')
+            .map(|line| line.trim_end_matches('\r'))
+            .collect();
+        let mut data = String::new();
+        for line in &lines {
+            data.push_str(line);
+            data.push('\n');
+        }
+        let meta = LineMeta {
+            // Ältere Agents schicken nur Sekunden
+            timestamp_ms: match message.timestamp_ms {
+                0 => message.timestamp.saturating_mul(1000),
+                timestamp_ms => timestamp_ms,
+            },
+            stream: message.stream(),
+        };
+        let meta_data = meta.encode().repeat(lines.len());
+
+        if let Some(chunk) = &log.current {
+            if chunk.step_index != step_index || chunk.bytes + data.len() as i64 > CHUNK_MAX_BYTES {
+                self.seal(job_id, &mut log).await?;
+            }
+        }
+        if log.current.is_none() {
+            let seq = log.next_seq;
+            let dir = self.inner.config.job_log_dir(job_id);
+            let mut options = tokio::fs::OpenOptions::new();
+            options.create(true).append(true);
+            let file = options
+                .open(dir.join(chunk_file_name(seq, step_index)))
+                .await
+                .map_err(AppError::Io)?;
+            let meta = options
+                .open(dir.join(meta_file_name(seq, step_index)))
+                .await
+                .map_err(AppError::Io)?;
+            log.current = Some(OpenChunk {
+                seq,
+                step_index,
+                file,
+                meta,
+                bytes: 0,
+                lines: 0,
+            });
+        }
+
+        let first_line = {
+            let line_offset = log.line_offset;
+            let chunk = log.current.as_mut().expect("chunk was opened above");
+            chunk
+                .file
+                .write_all(data.as_bytes())
+                .await
+                .map_err(AppError::Io)?;
+            // Leser sehen den offenen Chunk nur, wenn die Daten die Datei erreicht haben
+            chunk.file.flush().await.map_err(AppError::Io)?;
+            chunk
+                .meta
+                .write_all(&meta_data)
+                .await
+                .map_err(AppError::Io)?;
+            chunk.meta.flush().await.map_err(AppError::Io)?;
+            let first_line = line_offset + chunk.lines;
+            chunk.bytes += data.len() as i64;
+            chunk.lines += lines.len() as i64;
+            first_line
+        };
+
+        if let Some(tx) = self.inner.followers.get(job_id) {
+            for (i, line) in lines.iter().enumerate() {
+                let _ = tx.send(LogEvent::Line(LogLine::new(
+                    first_line + i as i64,
+                    step_index,
+                    meta,
+                    line.to_string(),
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Schließt das Log eines beendeten Jobs ab und komprimiert es im Hintergrund.
+    pub async fn finish(&self, job_id: &str) {
+        let open = self.inner.open.get(job_id).map(|log| log.clone());
+        if let Some(log) = open {
+            let mut log = log.lock().await;
+            log.closed = true;
+            if let Err(e) = self.seal(job_id, &mut log).await {
+                error!("Konnte Log von Job {} nicht abschließen: {}", job_id, e);
+            }
+            // Erst jetzt steht der letzte Chunk im Index, siehe `chunks`
+            self.inner.open.remove(job_id);
+        }
+        if let Some((_, tx)) = self.inner.followers.remove(job_id) {
+            let _ = tx.send(LogEvent::End);
+        }
+
+        let store = self.clone();
+        let job_id = job_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = store.compress(&job_id).await {
+                error!("Konnte Log von Job {} nicht komprimieren: {}", job_id, e);
+            }
+            store.inner.finished.notify_one();
+        });
+    }
+
+    /// Der Job schreibt noch in sein Log, [`LogStore::finish`] steht aus.
+    pub fn is_open(&self, job_id: &str) -> bool {
+        self.inner.open.contains_key(job_id)
+    }
+
+    /// Wartet, bis das Log eines weiteren Jobs abgeschlossen ist.
+    pub async fn wait_finished(&self) {
+        self.inner.finished.notified().await;
+    }
+
+    async fn compress(&self, job_id: &str) -> Result<()> {
+        for chunk in self.indexed_chunks(job_id).await? {
+            if chunk.compressed {
+                continue;
+            }
+            // Chunks aus der Zeit vor den Metadaten haben keine `.meta`-Datei
+            let files = [
+                (
+                    self.chunk_path(job_id, &chunk, false),
+                    self.chunk_path(job_id, &chunk, true),
+                    true,
+                ),
+                (
+                    self.meta_path(job_id, &chunk, false),
+                    self.meta_path(job_id, &chunk, true),
+                    false,
+                ),
+            ];
+            tokio::task::spawn_blocking(move || -> io::Result<()> {
+                for (plain, compressed, required) in files {
+                    let data = match std::fs::read(&plain) {
+                        Ok(data) => data,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => continue,
+                        Err(e) => return Err(e),
+                    };
+                    let tmp = compressed.with_extension("gz.tmp");
+                    let mut encoder =
+                        GzEncoder::new(std::fs::File::create(&tmp)?, Compression::default());
+                    encoder.write_all(&data)?;
+                    encoder.finish()?.sync_all()?;
+                    std::fs::rename(tmp, compressed)?;
+                }
+                Ok(())
+            })
+            .await?
+            .map_err(AppError::Io)?;
+
+            with_db!(&self.inner.db_pool, |pool| {
+                sqlx::query(
+                    "UPDATE job_log_chunks SET compressed = $1 WHERE job_id = $2 AND seq = $3",
+                )
+                .bind(true)
+                .bind(job_id)
+                .bind(chunk.seq)
+                .execute(pool)
+                .await?;
+            });
+            // Leser, die den Chunk noch als unkomprimiert kennen, greifen auf die .gz-Datei zurück
+            if let Err(e) = tokio::fs::remove_file(self.chunk_path(job_id, &chunk, false)).await {
+                error!(
+                    "Konnte Chunk {} von Job {} nicht entfernen: {}",
+                    chunk.seq, job_id, e
+                );
+            }
+            match tokio::fs::remove_file(self.meta_path(job_id, &chunk, false)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => error!(
+                    "Konnte Metadaten von Chunk {} von Job {} nicht entfernen: {}",
+                    chunk.seq, job_id, e
+                ),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Alle Chunks eines Jobs in Reihenfolge, samt dem offenen Chunk eines laufenden Jobs.
+    pub async fn chunks(&self, job_id: &str) -> Result<Vec<LogChunk>> {
+        let open = self.inner.open.get(job_id).map(|log| log.clone());
+        let Some(open) = open else {
+            return self.indexed_chunks(job_id).await;
+        };
+        // Solange das Log gesperrt ist, kann kein Chunk in den Index wandern
+        let log = open.lock().await;
+        let mut chunks = self.indexed_chunks(job_id).await?;
+        chunks.extend(log.current_chunk());
+        Ok(chunks)
+    }
+
+    /// Liest einen Chunk unkomprimiert, höchstens bis zu seiner indexierten Länge.
+    pub async fn read_chunk(&self, job_id: &str, chunk: &LogChunk) -> io::Result<Vec<u8>> {
+        let plain = self.chunk_path(job_id, chunk, false);
+        let compressed = self.chunk_path(job_id, chunk, true);
+        let length = chunk.byte_length.max(0) as usize;
+        let mut data = tokio::task::spawn_blocking(move || read_plain_or_gz(&plain, &compressed))
+            .await
+            .map_err(io::Error::other)??;
+        data.truncate(length);
+        Ok(data)
+    }
+
+    /// Zeitpunkt und Stream der Zeilen eines Chunks. Fehlende Einträge
+    /// werden mit leeren `LineMeta` aufgefüllt.
+    pub async fn read_meta(&self, job_id: &str, chunk: &LogChunk) -> io::Result<Vec<LineMeta>> {
+        let plain = self.meta_path(job_id, chunk, false);
+        let compressed = self.meta_path(job_id, chunk, true);
+        let data = match tokio::task::spawn_blocking(move || read_plain_or_gz(&plain, &compressed))
+            .await
+            .map_err(io::Error::other)?
+        {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut meta: Vec<LineMeta> = data
+            .chunks_exact(META_RECORD_BYTES)
+            .take(chunk.line_count.max(0) as usize)
+            .map(LineMeta::decode)
+            .collect();
+        meta.resize(chunk.line_count.max(0) as usize, LineMeta::default());
+        Ok(meta)
+    }
+
+    pub fn subscribe(&self, job_id: &str) -> broadcast::Receiver<LogEvent> {
+        self.inner
+            .followers
+            .entry(job_id.to_string())
+            .or_insert_with(|| broadcast::channel(FOLLOW_BUFFER).0)
+            .subscribe()
+    }
+
+    /// Entfernt den Kanal eines Jobs, dem niemand mehr folgt.
+    fn unsubscribe_idle(&self, job_id: &str) {
+        self.inner
+            .followers
+            .remove_if(job_id, |_, tx| tx.receiver_count() == 0);
+    }
+}
+
+/// Darstellung der Log-Zeilen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Die Ausgabe wie vom Agent gelesen, samt ANSI-Sequenzen.
+    #[default]
+    Raw,
+    /// Ohne ANSI-Sequenzen.
+    Plain,
+    /// HTML mit Farben als Inline-Styles, eine Zeile je
+    /// `<span class="log-line">` zum Einbetten in ein `<pre>`-Element.
+    Html,
+}
+
+impl LogFormat {
+    /// Die Zeile im Format; `html` trägt den Stil von einer Zeile zur nächsten.
+    fn output(self, line: &str, html: &mut ansi::HtmlRenderer) -> String {
+        match self {
+            LogFormat::Raw => line.to_string(),
+            LogFormat::Plain => ansi::strip(line),
+            LogFormat::Html => html.line(line),
+        }
+    }
+
+    /// Eine Zeile der Antwort von `GET /api/jobs/{id}/log`, samt Zeilenumbruch.
+    fn render(
+        self,
+        number: i64,
+        meta: LineMeta,
+        line: &str,
+        renderer: &mut ansi::HtmlRenderer,
+    ) -> String {
+        let LogFormat::Html = self else {
+            let mut output = self.output(line, renderer);
+            output.push('\n');
+            return output;
+        };
+        let mut html = format!("<span class=\"log-line\" data-line=\"{}\"", number);
+        if let Some(stream) = stream_name(meta.stream) {
+            html.push_str(&format!(" data-stream=\"{}\"", stream));
+        }
+        if meta.timestamp_ms > 0 {
+            html.push_str(&format!(" data-timestamp=\"{}\"", meta.timestamp_ms));
+        }
+        html.push('>');
+        html.push_str(&renderer.line(line));
+        html.push_str("</span>\n");
+        html
+    }
+}
+
+/// Query-Parameter für `GET /api/jobs/{id}/log`.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogQuery {
+    /// Nur die Ausgabe dieses Schritts.
+    pub step: Option<i64>,
+    /// Erste Zeile, ab 0.
+    pub offset: Option<i64>,
+    /// Höchstens so viele Zeilen.
+    pub limit: Option<i64>,
+    /// Liefert das Log als Server-Sent Events, bis der Job abgeschlossen ist.
+    #[serde(default)]
+    pub follow: bool,
+    /// Liefert das Log als Datei zum Herunterladen.
+    #[serde(default)]
+    pub download: bool,
+    /// `raw` (Default), `plain` oder `html`, auch für `follow`.
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+fn is_finished(status: &str) -> bool {
+    !matches!(status, "pending" | "running")
+}
+
+/// Chunks eines Schritts (oder alle) mit Positionen relativ zur Auswahl.
+fn select_chunks(chunks: Vec<LogChunk>, step: Option<i64>) -> Vec<LogChunk> {
+    let (mut byte_offset, mut line_offset) = (0, 0);
+    chunks
+        .into_iter()
+        .filter(|chunk| step.is_none() || chunk.step_index == step)
+        .map(|mut chunk| {
+            chunk.byte_offset = byte_offset;
+            chunk.line_offset = line_offset;
+            byte_offset += chunk.byte_length;
+            line_offset += chunk.line_count;
+            chunk
+        })
+        .collect()
+}
+
+/// Wertet einen `Range`-Header aus. `None`, wenn er ignoriert wird (Syntax oder
+/// mehrere Bereiche), `Some(Err)`, wenn der Bereich außerhalb des Logs liegt.
+fn parse_range(value: &str, total: i64) -> Option<std::result::Result<Range<i64>, ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let range = if start.is_empty() {
+        let suffix: i64 = end.parse().ok()?;
+        total.saturating_sub(suffix).max(0)..total
+    } else {
+        let start: i64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            total
+        } else {
+            end.parse::<i64>().ok()?.saturating_add(1).min(total)
+        };
+        start..end
+    };
+    Some(if range.start < range.end {
+        Ok(range)
+    } else {
+        Err(())
+    })
+}
+
+/// Die Bytes `range` der ausgewählten Chunks, Chunk für Chunk gelesen.
+fn byte_range_body(
+    store: LogStore,
+    job_id: String,
+    chunks: Vec<LogChunk>,
+    range: Range<i64>,
+) -> Body {
+    let chunks = chunks.into_iter().filter(move |chunk| {
+        chunk.byte_offset < range.end && chunk.byte_offset + chunk.byte_length > range.start
+    });
+    let stream = stream::iter(chunks).then(move |chunk| {
+        let store = store.clone();
+        let job_id = job_id.clone();
+        async move {
+            let data = store.read_chunk(&job_id, &chunk).await?;
+            let start = (range.start - chunk.byte_offset).max(0) as usize;
+            let end = ((range.end - chunk.byte_offset) as usize).min(data.len());
+            Ok::<_, io::Error>(Bytes::copy_from_slice(&data[start.min(end)..end]))
+        }
+    });
+    Body::from_stream(stream)
+}
+
+/// Die Zeilen `lines` der ausgewählten Chunks im gewünschten Format.
+fn line_range_body(
+    store: LogStore,
+    job_id: String,
+    chunks: Vec<LogChunk>,
+    lines: Range<i64>,
+    format: LogFormat,
+) -> Body {
+    let chunks = chunks.into_iter().filter(move |chunk| {
+        chunk.line_offset < lines.end && chunk.line_offset + chunk.line_count > lines.start
+    });
+    let stream = stream::iter(chunks).then(move |chunk| {
+        let store = store.clone();
+        let job_id = job_id.clone();
+        async move {
+            let data = store.read_chunk(&job_id, &chunk).await?;
+            let skip = (lines.start - chunk.line_offset).max(0) as usize;
+            let take = (lines.end - chunk.line_offset).max(0) as usize - skip;
+            let mut all = data.split_inclusive(|byte| *byte == b'\n');
+            if format == LogFormat::Raw {
+                return Ok(Bytes::from(
+                    all.skip(skip)
+                        .take(take)
+                        .flatten()
+                        .copied()
+                        .collect::<Vec<u8>>(),
+                ));
+            }
+            let meta = store.read_meta(&job_id, &chunk).await?;
+            // Farben gelten über Zeilengrenzen hinweg, auch aus übersprungenen Zeilen
+            let mut renderer = ansi::HtmlRenderer::default();
+            for line in all.by_ref().take(skip) {
+                renderer.skip(&String::from_utf8_lossy(line));
+            }
+            let mut rendered = String::new();
+            for (i, line) in all.take(take).enumerate() {
+                let number = chunk.line_offset + (skip + i) as i64;
+                let line = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line));
+                let meta = meta.get(skip + i).copied().unwrap_or_default();
+                rendered.push_str(&format.render(number, meta, &line, &mut renderer));
+            }
+            Ok::<_, io::Error>(Bytes::from(rendered))
+        }
+    });
+    Body::from_stream(stream)
+}
+
+/// `GET /api/jobs/{id}/log`: das Log als Text, wahlweise nur ein Schritt, ein
+/// Bereich in Bytes (`Range`-Header, nur `format=raw`) oder Zeilen
+/// (`offset`/`limit`), ohne ANSI-Sequenzen oder als HTML, als Download oder
+/// mit `follow=true` fortlaufend als Server-Sent Events.
+pub async fn get_job_log(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+    Query(query): Query<LogQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let job = jobs::fetch_job(&state, &job_id).await?;
+    authz::authorize_job(
+        &state.db_pool,
+        &auth,
+        job.project_id.as_deref(),
+        Action::ViewProject,
+    )
+    .await?;
+    if query.follow {
+        return follow_log(state, job_id, query, &headers).await;
+    }
+
+    let store = state.logs.clone();
+    let chunks = select_chunks(store.chunks(&job_id).await?, query.step);
+    let (total_bytes, total_lines) = chunks.last().map_or((0, 0), |chunk| {
+        (
+            chunk.byte_offset + chunk.byte_length,
+            chunk.line_offset + chunk.line_count,
+        )
+    });
+
+    let format = query.format;
+    let mut response = Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            match format {
+                LogFormat::Html => "text/html; charset=utf-8",
+                LogFormat::Raw | LogFormat::Plain => "text/plain; charset=utf-8",
+            },
+        )
+        .header(
+            header::ACCEPT_RANGES,
+            match format {
+                LogFormat::Raw => "bytes",
+                LogFormat::Plain | LogFormat::Html => "none",
+            },
+        )
+        .header("x-log-total-lines", total_lines)
+        .header("x-log-complete", is_finished(&job.status).to_string());
+    if query.download {
+        let extension = match format {
+            LogFormat::Html => "html",
+            LogFormat::Raw | LogFormat::Plain => "log",
+        };
+        let name = match query.step {
+            Some(step) => format!("{}-step{}.{}", job_id, step, extension),
+            None => format!("{}.{}", job_id, extension),
+        };
+        response = response.header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", name),
+        );
+    }
+
+    // Byte-Bereiche beziehen sich auf die unveränderte Ausgabe
+    let response = if query.offset.is_some() || query.limit.is_some() || format != LogFormat::Raw {
+        let start = query.offset.unwrap_or(0).clamp(0, total_lines);
+        let end = query
+            .limit
+            .map_or(total_lines, |limit| start.saturating_add(limit.max(0)))
+            .min(total_lines);
+        response.body(line_range_body(store, job_id, chunks, start..end, format))
+    } else {
+        let range = headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_range(value, total_bytes));
+        match range {
+            Some(Ok(range)) => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, total_bytes),
+                )
+                .body(byte_range_body(store, job_id, chunks, range)),
+            Some(Err(())) => response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", total_bytes))
+                .body(Body::empty()),
+            None => response.body(byte_range_body(store, job_id, chunks, 0..total_bytes)),
+        }
+    };
+    response.map_err(|e| AppError::Io(io::Error::other(e)))
+}
+
+fn line_event(line: &LogLine) -> Event {
+    Event::default()
+        .event("line")
+        .id(line.line.to_string())
+        .json_data(line)
+        .expect("log line serializes")
+}
+
+/// Folgt dem Log eines Jobs. Zeilennummern sind immer die des gesamten Logs,
+/// ein Client setzt nach einem Abbruch mit `Last-Event-ID` fort.
+async fn follow_log(
+    state: AppState,
+    job_id: String,
+    query: LogQuery,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let store = state.logs.clone();
+    let mut rx = store.subscribe(&job_id);
+    // Erst nach dem Abonnieren prüfen, sonst ginge das Ende eines gerade fertigen Jobs verloren
+    let job = jobs::fetch_job(&state, &job_id).await?;
+    let finished = is_finished(&job.status);
+    if finished {
+        drop(rx);
+        store.unsubscribe_idle(&job_id);
+        rx = broadcast::channel(1).1;
+    }
+    let mut next_line = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok()?.parse::<i64>().ok())
+        .map(|line| line + 1)
+        .or(query.offset)
+        .unwrap_or(0)
+        .max(0);
+    let step = query.step;
+    let format = query.format;
+    let shutdown = state.shutdown.wait();
+
+    let events = async_stream::stream! {
+        loop {
+            // Gespeicherte Zeilen ab `next_line`, danach die neuen über den Kanal
+            let chunks = match store.chunks(&job_id).await {
+                Ok(chunks) => select_chunks(chunks, None),
+                Err(e) => {
+                    error!("Konnte Log von Job {} nicht lesen: {}", job_id, e);
+                    return;
+                }
+            };
+            // Der Stil gilt innerhalb eines Chunks und für die neuen Zeilen des letzten
+            let mut renderer = ansi::HtmlRenderer::default();
+            for chunk in chunks {
+                if chunk.line_offset + chunk.line_count <= next_line {
+                    continue;
+                }
+                let read = async {
+                    let data = store.read_chunk(&job_id, &chunk).await?;
+                    let meta = store.read_meta(&job_id, &chunk).await?;
+                    Ok::<_, io::Error>((data, meta))
+                };
+                let (data, meta) = match read.await {
+                    Ok(read) => read,
+                    Err(e) => {
+                        error!("Konnte Log von Job {} nicht lesen: {}", job_id, e);
+                        return;
+                    }
+                };
+                renderer = ansi::HtmlRenderer::default();
+                for (i, line) in data.split_inclusive(|byte| *byte == b'\n').enumerate() {
+                    let number = chunk.line_offset + i as i64;
+                    let output = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(line));
+                    if number < next_line {
+                        renderer.skip(&output);
+                        continue;
+                    }
+                    next_line = number + 1;
+                    if step.is_none() || chunk.step_index == step {
+                        yield line_event(&LogLine::new(
+                            number,
+                            chunk.step_index,
+                            meta.get(i).copied().unwrap_or_default(),
+                            format.output(&output, &mut renderer),
+                        ));
+                    }
+                }
+            }
+            if finished {
+                break;
+            }
+            let lagged = loop {
+                match rx.recv().await {
+                    Ok(LogEvent::Line(line)) => {
+                        if line.line < next_line {
+                            continue;
+                        }
+                        next_line = line.line + 1;
+                        if step.is_none() || line.step_index == step {
+                            let output = format.output(&line.output, &mut renderer);
+                            yield line_event(&LogLine { output, ..line });
+                        }
+                    }
+                    Ok(LogEvent::End) | Err(broadcast::error::RecvError::Closed) => break false,
+                    Err(broadcast::error::RecvError::Lagged(_)) => break true,
+                }
+            };
+            // Zu weit zurückgefallen: die verpassten Zeilen aus dem Speicher nachholen
+            if !lagged {
+                break;
+            }
+        }
+        let status = match jobs::fetch_job(&state, &job_id).await {
+            Ok(job) => job.status,
+            Err(_) => "unknown".to_string(),
+        };
+        yield Event::default()
+            .event("end")
+            .json_data(serde_json::json!({ "status": status }))
+            .expect("status serializes");
+    };
+
+    Ok(
+        Sse::new(events.map(Ok::<_, Infallible>).take_until(shutdown))
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+    )
+}
@@ -0,0 +1,470 @@
+use crate::auth::AuthUser;
+use crate::authz::{self, Action};
+use crate::db::DbPool;
+use crate::grpc_server::runner::{FailureReason, JobResult, StepResult, StepStatus};
+use crate::http_server::{broadcast_job_update, broadcast_project_message};
+use crate::job_logs::LogStore;
+use crate::metrics;
+use crate::models::{Job, JobStep};
+use crate::otel;
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, AppError, Result, WsClientMap, WsServerMessage};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use sqlx::{types::Json as JsonColumn, QueryBuilder};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Query-Parameter für `GET /api/jobs`, z.B. `?project=<id>&status=failed&limit=20`.
+#[derive(Debug, Default, Deserialize)]
+pub struct JobFilter {
+    pub project: Option<String>,
+    pub pipeline: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Beschränkt die Liste auf Projekte, in denen dieser Benutzer Mitglied ist.
+    #[serde(skip)]
+    pub member: Option<String>,
+}
+
+pub async fn query_jobs(state: &AppState, filter: &JobFilter) -> Result<Vec<Job>> {
+    let limit = filter
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = filter.offset.unwrap_or(0).max(0);
+
+    // Der QueryBuilder setzt die Platzhalter passend zum Backend
+    let jobs = with_db!(&state.db_pool, |pool| {
+        let mut query = QueryBuilder::new("SELECT * FROM jobs WHERE 1 = 1");
+
+        if let Some(project_id) = &filter.project {
+            query.push(" AND project_id = ").push_bind(project_id);
+        }
+        if let Some(pipeline_id) = &filter.pipeline {
+            query.push(" AND pipeline_id = ").push_bind(pipeline_id);
+        }
+        if let Some(status) = &filter.status {
+            query.push(" AND status = ").push_bind(status);
+        }
+        if let Some(user_id) = &filter.member {
+            query
+                .push(" AND project_id IN (SELECT project_id FROM project_members WHERE user_id = ")
+                .push_bind(user_id)
+                .push(")");
+        }
+        query
+            .push(" ORDER BY created_at DESC, id LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        query.build_query_as::<Job>().fetch_all(pool).await?
+    });
+    Ok(jobs)
+}
+
+pub async fn fetch_job(state: &AppState, job_id: &str) -> Result<Job> {
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_optional(pool)
+            .await?
+    })
+    .ok_or_else(|| AppError::NotFound(format!("job '{}'", job_id)))
+}
+
+async fn fetch_job_by_id(db_pool: &DbPool, job_id: &str) -> Result<Option<Job>> {
+    let job = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_optional(pool)
+            .await?
+    });
+    Ok(job)
+}
+
+/// IDs der Jobs, die laut Datenbank gerade auf dem Agent laufen.
+pub async fn running_agent_jobs(db_pool: &DbPool, agent_id: &str) -> Result<Vec<String>> {
+    let job_ids = with_db!(db_pool, |pool| {
+        sqlx::query_scalar("SELECT id FROM jobs WHERE agent_id = $1 AND status = 'running'")
+            .bind(agent_id)
+            .fetch_all(pool)
+            .await?
+    });
+    Ok(job_ids)
+}
+
+/// Name einer Fehlerkategorie in der Datenbank und der API.
+fn failure_reason_name(reason: FailureReason) -> Option<&'static str> {
+    match reason {
+        FailureReason::Unspecified => None,
+        FailureReason::CommandFailed => Some("command_failed"),
+        FailureReason::CheckoutFailed => Some("checkout_failed"),
+        FailureReason::Timeout => Some("timeout"),
+        FailureReason::AgentLost => Some("agent_lost"),
+        FailureReason::Cancelled => Some("cancelled"),
+    }
+}
+
+fn step_status_name(status: StepStatus) -> Option<&'static str> {
+    match status {
+        StepStatus::Unspecified => None,
+        StepStatus::Running => Some("running"),
+        StepStatus::Success => Some("success"),
+        StepStatus::Failed => Some("failed"),
+        StepStatus::Skipped => Some("skipped"),
+        StepStatus::TimedOut => Some("timed_out"),
+        StepStatus::Cancelled => Some("cancelled"),
+    }
+}
+
+/// Ausgang eines Jobs, wie ihn der Agent meldet oder der Server feststellt.
+#[derive(Debug, Clone)]
+pub struct JobOutcome {
+    pub success: bool,
+    pub failure_reason: Option<&'static str>,
+    pub failure_message: Option<String>,
+}
+
+impl JobOutcome {
+    /// Ältere Agents melden nur `success`, der Fehlschlag bleibt dann ohne Kategorie.
+    pub fn from_result(result: &JobResult) -> Self {
+        if result.success {
+            return JobOutcome {
+                success: true,
+                failure_reason: None,
+                failure_message: None,
+            };
+        }
+        JobOutcome {
+            success: false,
+            failure_reason: failure_reason_name(result.failure_reason()),
+            failure_message: Some(result.failure_message.clone()).filter(|m| !m.is_empty()),
+        }
+    }
+
+    pub fn failed(reason: FailureReason, message: impl Into<String>) -> Self {
+        JobOutcome {
+            success: false,
+            failure_reason: failure_reason_name(reason),
+            failure_message: Some(message.into()),
+        }
+    }
+}
+
+/// Übernimmt das Ergebnis eines Agents. Nur laufende Jobs dieses Agents werden
+/// abgeschlossen, ein doppelt gemeldetes Ergebnis ändert nichts mehr.
+pub async fn finish_job(
+    db_pool: &DbPool,
+    ws_clients: &WsClientMap,
+    logs: &LogStore,
+    agent_id: &str,
+    job_id: &str,
+    outcome: &JobOutcome,
+) -> Result<Option<Job>> {
+    let status = if outcome.success { "success" } else { "failed" };
+    let rows_affected = with_db!(db_pool, |pool| {
+        sqlx::query(
+            "UPDATE jobs SET status = $1, finished_at = $2, failure_reason = $3, \
+             failure_message = $4 WHERE id = $5 AND agent_id = $6 AND status = 'running'",
+        )
+        .bind(status)
+        .bind(unix_timestamp())
+        .bind(outcome.failure_reason)
+        .bind(&outcome.failure_message)
+        .bind(job_id)
+        .bind(agent_id)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    });
+    if rows_affected == 0 {
+        return Ok(None);
+    }
+    if !outcome.success {
+        // Schritte, die der Agent nicht mehr abschließen konnte
+        with_db!(db_pool, |pool| {
+            sqlx::query(
+                "UPDATE job_steps SET status = CASE status WHEN 'running' THEN 'cancelled' \
+                 ELSE 'skipped' END WHERE job_id = $1 AND status IN ('pending', 'running')",
+            )
+            .bind(job_id)
+            .execute(pool)
+            .await?;
+        });
+    }
+    logs.finish(job_id).await;
+    let job = fetch_job_by_id(db_pool, job_id).await?;
+    if let Some(job) = &job {
+        metrics::job_finished(job);
+        otel::record_job(job);
+        broadcast_job_update(db_pool, ws_clients, job).await;
+    }
+    Ok(job)
+}
+
+/// Lässt alle laufenden Jobs eines Agents fehlschlagen, außer denen in `keep`.
+/// Liefert die IDs der abgeschlossenen Jobs.
+pub async fn fail_agent_jobs(
+    db_pool: &DbPool,
+    ws_clients: &WsClientMap,
+    logs: &LogStore,
+    agent_id: &str,
+    keep: &[String],
+) -> Result<Vec<String>> {
+    let mut failed = Vec::new();
+    for job_id in running_agent_jobs(db_pool, agent_id).await? {
+        if keep.contains(&job_id) {
+            continue;
+        }
+        let outcome = JobOutcome::failed(
+            FailureReason::AgentLost,
+            format!("agent '{}' no longer runs the job", agent_id),
+        );
+        if finish_job(db_pool, ws_clients, logs, agent_id, &job_id, &outcome)
+            .await?
+            .is_some()
+        {
+            failed.push(job_id);
+        }
+    }
+    Ok(failed)
+}
+
+/// Übernimmt den Stand eines Schritts, den ein Agent meldet. Nur Schritte
+/// laufender Jobs dieses Agents werden gespeichert.
+pub async fn record_step(
+    db_pool: &DbPool,
+    ws_clients: &WsClientMap,
+    agent_id: &str,
+    step: &StepResult,
+) -> Result<Option<JobStep>> {
+    let Some(status) = step_status_name(step.status()) else {
+        warn!("Schritt {} ohne Status ignoriert.", step.index);
+        return Ok(None);
+    };
+    let optional_ms = |ms: u64| (ms > 0).then_some(ms as i64);
+    let rows_affected = with_db!(db_pool, |pool| {
+        sqlx::query(
+            r#"
+            INSERT INTO job_steps
+                (job_id, step_index, command, status, exit_code, signal,
+                 started_at_ms, finished_at_ms, duration_ms)
+            SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+            WHERE EXISTS (
+                SELECT 1 FROM jobs WHERE id = $1 AND agent_id = $10 AND status = 'running'
+            )
+            ON CONFLICT (job_id, step_index) DO UPDATE SET
+                command = excluded.command,
+                status = excluded.status,
+                exit_code = excluded.exit_code,
+                signal = excluded.signal,
+                started_at_ms = excluded.started_at_ms,
+                finished_at_ms = excluded.finished_at_ms,
+                duration_ms = excluded.duration_ms
+            "#,
+        )
+        .bind(&step.job_id)
+        .bind(step.index as i64)
+        .bind(&step.command)
+        .bind(status)
+        .bind(step.exit_code.map(i64::from))
+        .bind(step.signal.map(i64::from))
+        .bind(optional_ms(step.started_at_ms))
+        .bind(optional_ms(step.finished_at_ms))
+        .bind((step.finished_at_ms > 0).then_some(step.duration_ms as i64))
+        .bind(agent_id)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    });
+    if rows_affected == 0 {
+        return Ok(None);
+    }
+
+    let step = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, JobStep>(
+            "SELECT * FROM job_steps WHERE job_id = $1 AND step_index = $2",
+        )
+        .bind(&step.job_id)
+        .bind(step.index as i64)
+        .fetch_one(pool)
+        .await?
+    });
+    if let Some(job) = fetch_job_by_id(db_pool, &step.job_id).await? {
+        let message = WsServerMessage::StepUpdate { step: step.clone() };
+        broadcast_project_message(db_pool, ws_clients, job.project_id.as_deref(), message).await;
+    }
+    Ok(Some(step))
+}
+
+pub async fn fetch_job_steps(db_pool: &DbPool, job_id: &str) -> Result<Vec<JobStep>> {
+    let steps = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, JobStep>(
+            "SELECT * FROM job_steps WHERE job_id = $1 ORDER BY step_index",
+        )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(steps)
+}
+
+/// Legt eine Kopie eines bestehenden Jobs als neuen `pending` Job an.
+pub async fn create_rerun(state: &AppState, auth: &AuthUser, job_id: &str) -> Result<Job> {
+    if state.shutdown.is_triggered() {
+        return Err(AppError::Unavailable(
+            "server is shutting down, no new jobs are accepted".to_string(),
+        ));
+    }
+    let job = fetch_job(state, job_id).await?;
+    authz::authorize_job(
+        &state.db_pool,
+        auth,
+        job.project_id.as_deref(),
+        Action::RerunJob,
+    )
+    .await?;
+
+    let new_job_id = Uuid::new_v4().to_string();
+    // Ohne Transaktion könnte der Scheduler einen Job ohne alle Schritte verteilen
+    with_db!(&state.db_pool, |pool| {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO jobs (id, status, repository_url, commands, project_id, pipeline_id)
+            VALUES ($1, 'pending', $2, $3, $4, $5)
+            "#,
+        )
+        .bind(&new_job_id)
+        .bind(&job.repository_url)
+        .bind(JsonColumn(&job.commands))
+        .bind(&job.project_id)
+        .bind(&job.pipeline_id)
+        .execute(&mut *tx)
+        .await?;
+        for (index, command) in job.commands.iter().enumerate() {
+            sqlx::query("INSERT INTO job_steps (job_id, step_index, command) VALUES ($1, $2, $3)")
+                .bind(&new_job_id)
+                .bind(index as i64)
+                .bind(command)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+    });
+
+    info!(
+        "Job {} von '{}' erneut gestartet als {}.",
+        job_id, auth.username, new_job_id
+    );
+    fetch_job(state, &new_job_id).await
+}
+
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(mut filter): Query<JobFilter>,
+) -> Result<Json<Vec<Job>>> {
+    if !auth.is_admin {
+        filter.member = Some(auth.user_id.clone());
+    }
+    Ok(Json(query_jobs(&state, &filter).await?))
+}
+
+pub async fn get_job(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Json<Job>> {
+    let job = fetch_job(&state, &job_id).await?;
+    authz::authorize_job(
+        &state.db_pool,
+        &auth,
+        job.project_id.as_deref(),
+        Action::ViewProject,
+    )
+    .await?;
+    Ok(Json(job))
+}
+
+pub async fn list_job_steps(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Json<Vec<JobStep>>> {
+    let job = fetch_job(&state, &job_id).await?;
+    authz::authorize_job(
+        &state.db_pool,
+        &auth,
+        job.project_id.as_deref(),
+        Action::ViewProject,
+    )
+    .await?;
+    Ok(Json(fetch_job_steps(&state.db_pool, &job_id).await?))
+}
+
+/// Setzt oder entfernt die Markierung, die einen Job vor dem Löschen schützt.
+pub async fn set_job_pinned(
+    state: &AppState,
+    auth: &AuthUser,
+    job_id: &str,
+    pinned: bool,
+) -> Result<Job> {
+    let job = fetch_job(state, job_id).await?;
+    authz::authorize_job(
+        &state.db_pool,
+        auth,
+        job.project_id.as_deref(),
+        Action::PinJob,
+    )
+    .await?;
+
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query("UPDATE jobs SET pinned = $1 WHERE id = $2")
+            .bind(pinned)
+            .bind(job_id)
+            .execute(pool)
+            .await?;
+    });
+    fetch_job(state, job_id).await
+}
+
+pub async fn pin_job(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Json<Job>> {
+    let job = set_job_pinned(&state, &auth, &job_id, true).await?;
+    broadcast_job_update(&state.db_pool, &state.ws_clients, &job).await;
+    Ok(Json(job))
+}
+
+pub async fn unpin_job(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<Json<Job>> {
+    let job = set_job_pinned(&state, &auth, &job_id, false).await?;
+    broadcast_job_update(&state.db_pool, &state.ws_clients, &job).await;
+    Ok(Json(job))
+}
+
+pub async fn rerun_job(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(job_id): Path<String>,
+) -> Result<(StatusCode, Json<Job>)> {
+    let job = create_rerun(&state, &auth, &job_id).await?;
+    broadcast_job_update(&state.db_pool, &state.ws_clients, &job).await;
+    Ok((StatusCode::CREATED, Json(job)))
+}
@@ -2,6 +2,7 @@ use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use std::result::Result as StdResult;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
 use uuid::Uuid;
 
@@ -10,7 +11,11 @@ use uuid::Uuid;
 pub enum WsServerMessage {
     InitialState { agents: Vec<models::Agent> },
     AgentUpdate { agent: models::Agent },
+    AgentMetrics { metrics: models::AgentMetrics },
     StatsUpdate { online: usize, offline: usize },
+    JobUpdate { job: models::Job },
+    StepUpdate { step: models::JobStep },
+    Error { message: String },
 }
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
@@ -18,18 +23,66 @@ pub enum WsClientMessage {
     RequestRerun { job_id: String },
 }
 pub type WsClientTx = UnboundedSender<WsServerMessage>;
-pub type WsClientMap = Arc<DashMap<Uuid, WsClientTx>>;
 
-pub type LiveAgentMap = Arc<
-    DashMap<String, Sender<StdResult<crate::grpc_server::runner::ServerCommand, tonic::Status>>>,
->;
+/// Ein verbundener WebSocket-Client samt dem Benutzer, der sich authentifiziert hat.
+#[derive(Clone)]
+pub struct WsClient {
+    pub tx: WsClientTx,
+    pub user: auth::AuthUser,
+}
+pub type WsClientMap = Arc<DashMap<Uuid, WsClient>>;
+
+/// Ein verbundener Agent samt Kanal für Befehle und ausgehandelter Protokollversion.
+#[derive(Clone)]
+pub struct LiveAgent {
+    pub tx: Sender<StdResult<grpc_server::runner::ServerCommand, tonic::Status>>,
+    pub protocol_version: u32,
+}
+
+impl LiveAgent {
+    pub fn supports(&self, feature: protocol::Feature) -> bool {
+        feature.supported_by(self.protocol_version)
+    }
+}
+
+pub type LiveAgentMap = Arc<DashMap<String, LiveAgent>>;
+
+/// Aktuelle Zeit als Unix-Timestamp in Sekunden.
+pub fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
 
+pub mod agent_auth;
+pub mod agent_metrics;
+pub mod agents;
+pub mod ansi;
+pub mod auth;
+pub mod authz;
+pub mod config;
 pub mod db;
 pub mod error;
 pub mod grpc_server;
 pub mod http_server;
+pub mod job_logs;
+pub mod jobs;
+pub mod liveness;
+pub mod log_search;
+pub mod logging;
+pub mod metrics;
+pub mod migrations;
 pub mod models;
+pub mod otel;
+pub mod projects;
+pub mod protocol;
+pub mod retention;
+pub mod scheduler;
+pub mod shutdown;
 pub mod state;
 pub mod tasks;
+pub mod tls;
+pub mod users;
 
 pub use error::{AppError, Result};
@@ -0,0 +1,469 @@
+use crate::db::DbPool;
+use crate::grpc_server::{broadcast_ws_message, fetch_agent};
+use crate::job_logs::LogStore;
+use crate::jobs;
+use crate::metrics;
+use crate::{unix_timestamp, with_db, Result, WsClientMap, WsServerMessage};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{error, info};
+use uuid::Uuid;
+
+struct AgentLiveness {
+    /// Kennzeichnet den Stream, damit ein alter Stream beim Trennen
+    /// nicht den Eintrag einer neueren Verbindung entfernt.
+    stream: u64,
+    /// Mit dieser ID setzt der Agent die Session nach einem Reconnect fort.
+    session_id: String,
+    /// Höchste verarbeitete Sequenznummer der Session.
+    last_seq: u64,
+    /// Ein Stream ist verbunden. Ohne Stream wartet die Session bis zur Frist auf den Agent.
+    attached: bool,
+    /// Der Agent hat angekündigt, dass er herunterfährt; er setzt die Session nicht fort.
+    stopping: bool,
+    /// Der Agent nimmt keine neuen Jobs mehr an, auch nachdem er offline war.
+    draining: bool,
+    deadline: Instant,
+    /// Ausgehandeltes Heartbeat-Intervall, Grundlage für die Verspätung eines Heartbeats.
+    heartbeat_interval: Duration,
+    last_heartbeat: i64,
+    /// Heartbeat ist noch nicht in der Datenbank.
+    pending: bool,
+    /// Die Frist ist abgelaufen und der Agent als offline markiert.
+    expired: bool,
+}
+
+/// Ergebnis einer Registrierung.
+pub struct Registration {
+    pub stream: u64,
+    pub session_id: String,
+    /// Die bisherige Session wird fortgesetzt.
+    pub resumed: bool,
+    /// Bis hierhin hat der Server die Logs und Ergebnisse der Session verarbeitet.
+    pub last_seq: u64,
+}
+
+/// Wie ein Stream geendet hat, aus Sicht der Session.
+pub enum StreamEnd {
+    /// Ein neuerer Stream gehört inzwischen zum Agent.
+    Superseded,
+    /// Die Session wartet bis zur Frist darauf, dass der Agent sie fortsetzt.
+    Detached,
+    /// Die Session ist beendet, der Agent ist offline.
+    Closed { last_heartbeat: i64 },
+}
+
+struct Expired {
+    agent_id: String,
+    last_heartbeat: i64,
+    /// Die Session hatte keinen Stream mehr und ist damit beendet.
+    detached: bool,
+}
+
+/// Verfolgt im Speicher die Sessions der Agents und wann der nächste Heartbeat
+/// fällig ist. Läuft eine Frist ab, markiert der Watchdog den Agent sofort als
+/// offline; Heartbeats landen gesammelt über [`Liveness::flush`] in der Datenbank.
+/// Trennt ein Agent die Verbindung unerwartet, kann er seine Session bis zum
+/// Ablauf der Frist fortsetzen.
+#[derive(Clone)]
+pub struct Liveness {
+    agents: Arc<Mutex<HashMap<String, AgentLiveness>>>,
+    next_stream: Arc<AtomicU64>,
+    changed: Arc<Notify>,
+    heartbeat_interval: Duration,
+    offline_after: Duration,
+}
+
+impl Liveness {
+    pub fn new(heartbeat_interval: Duration, offline_after: Duration) -> Self {
+        Liveness {
+            agents: Arc::default(),
+            next_stream: Arc::new(AtomicU64::new(1)),
+            changed: Arc::new(Notify::new()),
+            heartbeat_interval,
+            offline_after,
+        }
+    }
+
+    pub fn offline_after(&self) -> Duration {
+        self.offline_after
+    }
+
+    /// Handelt das Heartbeat-Intervall aus: der Wunsch des Agents (0 = keiner),
+    /// begrenzt auf die Hälfte der Offline-Frist, damit ein verlorener
+    /// Heartbeat den Agent nicht gleich offline setzt.
+    pub fn negotiate_interval(&self, requested_secs: u32) -> Duration {
+        let max = (self.offline_after / 2).max(Duration::from_secs(1));
+        match requested_secs {
+            0 => self.heartbeat_interval,
+            secs => Duration::from_secs(secs.into()),
+        }
+        .clamp(Duration::from_secs(1), max)
+    }
+
+    /// Beginnt die Überwachung eines registrierten Agents. Nennt der Agent die
+    /// ID seiner noch bestehenden Session, wird sie fortgesetzt, sonst beginnt eine neue.
+    pub fn register(
+        &self,
+        agent_id: &str,
+        resume_session_id: &str,
+        heartbeat_interval: Duration,
+    ) -> Registration {
+        let stream = self.next_stream.fetch_add(1, Ordering::Relaxed);
+        let mut agents = self.agents.lock().unwrap();
+        let previous = agents
+            .remove(agent_id)
+            .filter(|entry| !resume_session_id.is_empty() && entry.session_id == resume_session_id);
+        let resumed = previous.is_some();
+        let (session_id, last_seq, draining) = previous
+            .map(|entry| (entry.session_id, entry.last_seq, entry.draining))
+            .unwrap_or_else(|| (Uuid::new_v4().to_string(), 0, false));
+        agents.insert(
+            agent_id.to_string(),
+            AgentLiveness {
+                stream,
+                session_id: session_id.clone(),
+                last_seq,
+                attached: true,
+                stopping: false,
+                draining,
+                deadline: Instant::now() + self.offline_after,
+                heartbeat_interval,
+                last_heartbeat: unix_timestamp(),
+                pending: false,
+                expired: false,
+            },
+        );
+        drop(agents);
+        self.changed.notify_one();
+        Registration {
+            stream,
+            session_id,
+            resumed,
+            last_seq,
+        }
+    }
+
+    /// Verlängert die Frist. Liefert `true`, wenn der Agent zuvor als offline markiert war.
+    pub fn heartbeat(&self, agent_id: &str, stream: u64) -> bool {
+        let mut agents = self.agents.lock().unwrap();
+        let Some(entry) = agents
+            .get_mut(agent_id)
+            .filter(|entry| entry.stream == stream)
+        else {
+            return false;
+        };
+        // Die Frist lief ab dem letzten Heartbeat, daraus ergibt sich dessen Zeitpunkt
+        let now = Instant::now();
+        let previous = entry.deadline - self.offline_after;
+        metrics::heartbeat_lag(
+            now.saturating_duration_since(previous)
+                .saturating_sub(entry.heartbeat_interval),
+        );
+        entry.deadline = now + self.offline_after;
+        entry.last_heartbeat = unix_timestamp();
+        entry.pending = true;
+        std::mem::take(&mut entry.expired)
+    }
+
+    /// Prüft die Sequenznummer einer Nachricht. Liefert `false` für Nachrichten,
+    /// die der Server schon verarbeitet hat, etwa nach einem Resume erneut gesendete.
+    /// Nachrichten ohne Sequenznummer werden immer verarbeitet.
+    pub fn accept(&self, agent_id: &str, stream: u64, seq: u64) -> bool {
+        if seq == 0 {
+            return true;
+        }
+        let agents = self.agents.lock().unwrap();
+        agents
+            .get(agent_id)
+            .filter(|entry| entry.stream == stream)
+            .is_some_and(|entry| seq > entry.last_seq)
+    }
+
+    /// Hält fest, dass eine Nachricht verarbeitet wurde. Erst danach gilt sie
+    /// als bestätigt. Schlägt eine fehl, endet der Stream, und der Agent sendet
+    /// sie samt allen späteren nach dem Resume erneut.
+    pub fn processed(&self, agent_id: &str, stream: u64, seq: u64) {
+        if seq == 0 {
+            return;
+        }
+        let mut agents = self.agents.lock().unwrap();
+        let Some(entry) = agents
+            .get_mut(agent_id)
+            .filter(|entry| entry.stream == stream)
+        else {
+            return;
+        };
+        entry.last_seq = entry.last_seq.max(seq);
+    }
+
+    /// Höchste verarbeitete Sequenznummer, die dem Agent bestätigt werden kann.
+    pub fn last_seq(&self, agent_id: &str, stream: u64) -> Option<u64> {
+        let agents = self.agents.lock().unwrap();
+        agents
+            .get(agent_id)
+            .filter(|entry| entry.stream == stream)
+            .map(|entry| entry.last_seq)
+    }
+
+    /// Der Agent nimmt keine neuen Jobs mehr an. Mit `stopping` fährt er
+    /// herunter; beim Trennen endet seine Session dann sofort.
+    pub fn mark_draining(&self, agent_id: &str, stream: u64, stopping: bool) {
+        let mut agents = self.agents.lock().unwrap();
+        if let Some(entry) = agents
+            .get_mut(agent_id)
+            .filter(|entry| entry.stream == stream)
+        {
+            entry.draining = true;
+            entry.stopping |= stopping;
+        }
+    }
+
+    /// Drain auf Anweisung eines Admins. Gilt für die aktuelle Session des Agents,
+    /// unabhängig vom Stream, damit ein Heartbeat nach einer Lücke ihn nicht wieder `online` setzt.
+    pub fn drain(&self, agent_id: &str) {
+        let mut agents = self.agents.lock().unwrap();
+        if let Some(entry) = agents.get_mut(agent_id) {
+            entry.draining = true;
+        }
+    }
+
+    /// Status des Agents, solange er Heartbeats sendet: `online` oder `draining`.
+    pub fn live_status(&self, agent_id: &str, stream: u64) -> &'static str {
+        let agents = self.agents.lock().unwrap();
+        match agents.get(agent_id).filter(|entry| entry.stream == stream) {
+            Some(entry) if entry.draining => "draining",
+            _ => "online",
+        }
+    }
+
+    /// Der Stream ist beendet. Kann der Agent die Session fortsetzen und hat er
+    /// nicht angekündigt herunterzufahren, bleibt sie bis zur Frist bestehen.
+    pub fn disconnect(&self, agent_id: &str, stream: u64, resumable: bool) -> StreamEnd {
+        let mut agents = self.agents.lock().unwrap();
+        let Some(entry) = agents
+            .get_mut(agent_id)
+            .filter(|entry| entry.stream == stream)
+        else {
+            return StreamEnd::Superseded;
+        };
+        if resumable && !entry.stopping && !entry.expired {
+            entry.attached = false;
+            return StreamEnd::Detached;
+        }
+        let last_heartbeat = entry.last_heartbeat;
+        agents.remove(agent_id);
+        StreamEnd::Closed { last_heartbeat }
+    }
+
+    /// Markiert alle Agents mit abgelaufener Frist. Sessions ohne Stream enden dabei.
+    fn expire_due(&self) -> Vec<Expired> {
+        let now = Instant::now();
+        let mut agents = self.agents.lock().unwrap();
+        let expired: Vec<Expired> = agents
+            .iter_mut()
+            .filter(|(_, entry)| !entry.expired && entry.deadline <= now)
+            .map(|(agent_id, entry)| {
+                entry.expired = true;
+                entry.pending = false;
+                Expired {
+                    agent_id: agent_id.clone(),
+                    last_heartbeat: entry.last_heartbeat,
+                    detached: !entry.attached,
+                }
+            })
+            .collect();
+        agents.retain(|_, entry| entry.attached || !entry.expired);
+        expired
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        let agents = self.agents.lock().unwrap();
+        agents
+            .values()
+            .filter(|entry| !entry.expired)
+            .map(|entry| entry.deadline)
+            .min()
+    }
+
+    /// Schreibt alle seit dem letzten Aufruf eingegangenen Heartbeats in einer Transaktion.
+    pub async fn flush(&self, db_pool: &DbPool) -> Result<usize> {
+        let pending: Vec<(String, i64)> = {
+            let mut agents = self.agents.lock().unwrap();
+            agents
+                .iter_mut()
+                .filter(|(_, entry)| entry.pending)
+                .map(|(agent_id, entry)| {
+                    entry.pending = false;
+                    (agent_id.clone(), entry.last_heartbeat)
+                })
+                .collect()
+        };
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        with_db!(db_pool, |pool| {
+            let mut tx = pool.begin().await?;
+            for (agent_id, last_heartbeat) in &pending {
+                sqlx::query("UPDATE agents SET last_heartbeat = $1 WHERE id = $2")
+                    .bind(last_heartbeat)
+                    .bind(agent_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+        });
+        Ok(pending.len())
+    }
+
+    /// Startet den Watchdog, der Agents beim Ablauf ihrer Frist sofort offline
+    /// setzt. Endet dabei eine Session ohne Stream, schlagen ihre laufenden Jobs fehl.
+    pub fn spawn_watchdog(&self, db_pool: DbPool, ws_clients: WsClientMap, logs: LogStore) {
+        let liveness = self.clone();
+        tokio::spawn(async move {
+            loop {
+                // Neue Registrierungen wecken den Watchdog, damit er die Frist neu berechnet
+                let changed = liveness.changed.notified();
+                match liveness.next_deadline() {
+                    Some(deadline) => tokio::select! {
+                        _ = tokio::time::sleep_until(deadline) => {}
+                        _ = changed => continue,
+                    },
+                    None => {
+                        changed.await;
+                        continue;
+                    }
+                }
+
+                for expired in liveness.expire_due() {
+                    let agent_id = &expired.agent_id;
+                    info!(
+                        "Agent '{}' hat seit {}s keinen Heartbeat gesendet, markiere als offline.",
+                        agent_id,
+                        liveness.offline_after.as_secs()
+                    );
+                    let query = with_db!(&db_pool, |pool| {
+                        sqlx::query(
+                            "UPDATE agents SET status = 'offline', last_heartbeat = $1 WHERE id = $2",
+                        )
+                        .bind(expired.last_heartbeat)
+                        .bind(agent_id)
+                        .execute(pool)
+                        .await
+                        .map(|_| ())
+                    });
+                    if let Err(e) = query {
+                        error!("Konnte Agent '{}' nicht offline setzen: {}", agent_id, e);
+                        continue;
+                    }
+                    if let Ok(Some(agent)) = fetch_agent(&db_pool, agent_id).await {
+                        broadcast_ws_message(&ws_clients, &WsServerMessage::AgentUpdate { agent })
+                            .await;
+                    }
+                    if expired.detached {
+                        info!(
+                            "Session von Agent '{}' nicht fortgesetzt, laufende Jobs schlagen fehl.",
+                            agent_id
+                        );
+                        if let Err(e) =
+                            jobs::fail_agent_jobs(&db_pool, &ws_clients, &logs, agent_id, &[]).await
+                        {
+                            error!(
+                                "Konnte Jobs von Agent '{}' nicht abschließen: {}",
+                                agent_id, e
+                            );
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn liveness() -> Liveness {
+        Liveness::new(Duration::from_secs(10), Duration::from_secs(30))
+    }
+
+    #[test]
+    fn draining_survives_resume() {
+        let liveness = liveness();
+        let first = liveness.register("a1", "", Duration::from_secs(10));
+        assert_eq!(liveness.live_status("a1", first.stream), "online");
+
+        liveness.mark_draining("a1", first.stream, false);
+        assert_eq!(liveness.live_status("a1", first.stream), "draining");
+        assert!(matches!(
+            liveness.disconnect("a1", first.stream, true),
+            StreamEnd::Detached
+        ));
+
+        // Nach dem Resume bleibt der Agent im Drain, auch wenn er wieder Heartbeats sendet
+        let second = liveness.register("a1", &first.session_id, Duration::from_secs(10));
+        assert!(second.resumed);
+        liveness.heartbeat("a1", second.stream);
+        assert_eq!(liveness.live_status("a1", second.stream), "draining");
+
+        // Eine neue Session beginnt ohne Drain
+        let fresh = liveness.register("a1", "", Duration::from_secs(10));
+        assert!(!fresh.resumed);
+        assert_eq!(liveness.live_status("a1", fresh.stream), "online");
+    }
+
+    #[test]
+    fn remote_drain_applies_to_the_session() {
+        let liveness = liveness();
+        let first = liveness.register("a1", "", Duration::from_secs(10));
+        liveness.drain("a1");
+        liveness.heartbeat("a1", first.stream);
+        assert_eq!(liveness.live_status("a1", first.stream), "draining");
+
+        // Auch ohne Stream bleibt der Drain bis zum Resume erhalten
+        liveness.disconnect("a1", first.stream, true);
+        liveness.drain("a1");
+        let second = liveness.register("a1", &first.session_id, Duration::from_secs(10));
+        assert_eq!(liveness.live_status("a1", second.stream), "draining");
+    }
+
+    #[test]
+    fn failed_message_is_not_acknowledged() {
+        let liveness = liveness();
+        let first = liveness.register("a1", "", Duration::from_secs(10));
+        assert!(liveness.accept("a1", first.stream, 1));
+        liveness.processed("a1", first.stream, 1);
+        assert!(!liveness.accept("a1", first.stream, 1));
+
+        // Nachricht 2 schlägt fehl, der Stream endet vor Nachricht 3
+        assert!(liveness.accept("a1", first.stream, 2));
+        assert_eq!(liveness.last_seq("a1", first.stream), Some(1));
+        liveness.disconnect("a1", first.stream, true);
+
+        // Der Agent sendet nach dem Resume ab Nachricht 2 erneut, jede genau einmal
+        let second = liveness.register("a1", &first.session_id, Duration::from_secs(10));
+        assert_eq!(second.last_seq, 1);
+        for seq in 2..=3 {
+            assert!(liveness.accept("a1", second.stream, seq));
+            liveness.processed("a1", second.stream, seq);
+        }
+        assert!(!liveness.accept("a1", second.stream, 3));
+        assert_eq!(liveness.last_seq("a1", second.stream), Some(3));
+    }
+
+    #[test]
+    fn stopping_agent_does_not_detach() {
+        let liveness = liveness();
+        let registration = liveness.register("a1", "", Duration::from_secs(10));
+        liveness.mark_draining("a1", registration.stream, true);
+        assert!(matches!(
+            liveness.disconnect("a1", registration.stream, true),
+            StreamEnd::Closed { .. }
+        ));
+    }
+}
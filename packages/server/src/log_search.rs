@@ -0,0 +1,307 @@
+//! Volltextsuche über die Logs abgeschlossener Jobs. Der Indexer übernimmt
+//! die Zeilen nach Abschluss eines Jobs in `job_log_lines`; gesucht wird mit
+//! FTS5 (SQLite) bzw. `tsvector` (Postgres).
+
+use crate::ansi;
+use crate::auth::AuthUser;
+use crate::db::{DbPool, Dialect};
+use crate::job_logs::LogStore;
+use crate::state::AppState;
+use crate::{with_db, AppError, Result};
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, QueryBuilder};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+/// Auch ohne Weckruf sucht der Indexer so oft nach abgeschlossenen Jobs,
+/// z.B. nach einem Neustart.
+const INDEX_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Nach einem Datenbankfehler, z.B. einer gesperrten SQLite-Datei, versucht
+/// es der Indexer früher erneut.
+const INDEX_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Jobs, die der Indexer pro Durchlauf übernimmt.
+const INDEX_BATCH_JOBS: i64 = 20;
+/// Zeilen pro INSERT, unterhalb der Parametergrenzen beider Backends.
+const INSERT_BATCH_LINES: usize = 500;
+/// Längere Zeilen werden nur mit ihrem Anfang indexiert.
+const MAX_INDEXED_LINE_BYTES: usize = 4096;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+const DEFAULT_CONTEXT: i64 = 2;
+const MAX_CONTEXT: i64 = 10;
+
+struct IndexedLine {
+    step_index: Option<i64>,
+    line_no: i64,
+    content: String,
+}
+
+fn truncate_line(line: &str) -> &str {
+    if line.len() <= MAX_INDEXED_LINE_BYTES {
+        return line;
+    }
+    let mut end = MAX_INDEXED_LINE_BYTES;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+/// Liest das Log eines Jobs ohne ANSI-Sequenzen, damit Farben die
+/// Suchbegriffe nicht zerteilen.
+async fn read_lines(logs: &LogStore, job_id: &str) -> Result<Vec<IndexedLine>> {
+    let mut lines = Vec::new();
+    for chunk in logs.chunks(job_id).await? {
+        let data = logs
+            .read_chunk(job_id, &chunk)
+            .await
+            .map_err(AppError::Io)?;
+        let text = String::from_utf8_lossy(&data);
+        for (i, line) in text.lines().enumerate() {
+            lines.push(IndexedLine {
+                step_index: chunk.step_index,
+                line_no: chunk.line_offset + i as i64,
+                content: truncate_line(&ansi::strip(line)).to_string(),
+            });
+        }
+    }
+    Ok(lines)
+}
+
+/// Ersetzt die Zeilen eines Jobs im Suchindex und markiert ihn als indexiert.
+async fn store_lines(db_pool: &DbPool, job_id: &str, lines: &[IndexedLine]) -> Result<()> {
+    with_db!(db_pool, |pool| {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM job_log_lines WHERE job_id = $1")
+            .bind(job_id)
+            .execute(&mut *tx)
+            .await?;
+        for batch in lines.chunks(INSERT_BATCH_LINES) {
+            QueryBuilder::new("INSERT INTO job_log_lines (job_id, step_index, line_no, content) ")
+                .push_values(batch, |mut row, line| {
+                    row.push_bind(job_id)
+                        .push_bind(line.step_index)
+                        .push_bind(line.line_no)
+                        .push_bind(&line.content);
+                })
+                .build()
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("UPDATE jobs SET log_indexed = $1 WHERE id = $2")
+            .bind(true)
+            .bind(job_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    });
+    Ok(())
+}
+
+/// Übernimmt die Logs aller abgeschlossenen, noch nicht indexierten Jobs.
+/// Ein Job, dessen Log sich nicht lesen lässt, hält die übrigen nicht auf;
+/// bei einem Datenbankfehler bleibt er offen und kommt im nächsten Durchlauf dran.
+async fn index_pending(db_pool: &DbPool, logs: &LogStore) -> Result<()> {
+    loop {
+        let job_ids: Vec<String> = with_db!(db_pool, |pool| {
+            sqlx::query_scalar(
+                "SELECT id FROM jobs WHERE status NOT IN ('pending', 'running') \
+                 AND NOT log_indexed ORDER BY finished_at, id LIMIT $1",
+            )
+            .bind(INDEX_BATCH_JOBS)
+            .fetch_all(pool)
+            .await?
+        });
+        let mut progress = false;
+        for job_id in job_ids {
+            // Der Job ist schon beendet, sein Log aber noch nicht abgeschlossen;
+            // LogStore::finish weckt den Indexer danach erneut
+            if logs.is_open(&job_id) {
+                continue;
+            }
+            progress = true;
+            let lines = match read_lines(logs, &job_id).await {
+                Ok(lines) => lines,
+                Err(e) => {
+                    // Ohne Zeilen indexiert, sonst stünde der Job bei jedem Durchlauf wieder vorne
+                    error!(
+                        "Log von Job {} nicht lesbar, wird bei der Suche fehlen: {}",
+                        job_id, e
+                    );
+                    Vec::new()
+                }
+            };
+            store_lines(db_pool, &job_id, &lines).await?;
+            debug!("Log von Job {} indexiert ({} Zeilen).", job_id, lines.len());
+        }
+        if !progress {
+            return Ok(());
+        }
+    }
+}
+
+/// Startet den Indexer, der die Logs abgeschlossener Jobs in den Suchindex übernimmt.
+pub fn spawn_log_indexer(db_pool: DbPool, logs: LogStore) {
+    tokio::spawn(async move {
+        loop {
+            let wait = match index_pending(&db_pool, &logs).await {
+                Ok(()) => INDEX_POLL_INTERVAL,
+                Err(e) => {
+                    error!("Fehler beim Indexieren der Job-Logs: {}", e);
+                    INDEX_RETRY_DELAY
+                }
+            };
+            tokio::select! {
+                _ = logs.wait_finished() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+    info!("Log-Indexer gestartet.");
+}
+
+/// Query-Parameter für `GET /api/search/logs`, z.B. `?q=connection refused&project=<id>`.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogSearchQuery {
+    /// Suchbegriffe, die alle in der Zeile vorkommen müssen.
+    pub q: String,
+    pub project: Option<String>,
+    /// Nur Jobs, die ab diesem Zeitpunkt (Unix-Sekunden) angelegt wurden.
+    pub since: Option<i64>,
+    pub limit: Option<i64>,
+    /// Zeilen vor und nach jedem Treffer.
+    pub context: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+struct MatchRow {
+    job_id: String,
+    project_id: Option<String>,
+    status: String,
+    created_at: i64,
+    step_index: Option<i64>,
+    line_no: i64,
+    content: String,
+}
+
+#[derive(Debug, FromRow)]
+struct ContextRow {
+    job_id: String,
+    line_no: i64,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogSearchMatch {
+    pub job_id: String,
+    pub project_id: Option<String>,
+    pub job_status: String,
+    pub created_at: i64,
+    pub step_index: Option<i64>,
+    /// Zeilennummer im Log des Jobs, ab 0, wie bei `GET /api/jobs/{id}/log`.
+    pub line: i64,
+    pub output: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+pub async fn search_logs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<LogSearchQuery>,
+) -> Result<Json<Vec<LogSearchMatch>>> {
+    if query.q.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "query parameter 'q' is required".to_string(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let context = query
+        .context
+        .unwrap_or(DEFAULT_CONTEXT)
+        .clamp(0, MAX_CONTEXT);
+
+    let rows = with_db!(&state.db_pool, |pool| {
+        let mut sql = QueryBuilder::new(
+            "SELECT l.job_id, j.project_id, j.status, j.created_at, l.step_index, l.line_no, \
+             l.content FROM job_log_lines l JOIN jobs j ON j.id = l.job_id ",
+        );
+        Dialect::push_log_match(&mut sql, &query.q);
+        if let Some(project_id) = &query.project {
+            sql.push(" AND j.project_id = ").push_bind(project_id);
+        }
+        if let Some(since) = query.since {
+            sql.push(" AND j.created_at >= ").push_bind(since);
+        }
+        if !auth.is_admin {
+            sql.push(
+                " AND j.project_id IN (SELECT project_id FROM project_members WHERE user_id = ",
+            )
+            .push_bind(&auth.user_id)
+            .push(")");
+        }
+        sql.push(" ORDER BY j.created_at DESC, l.job_id, l.line_no LIMIT ")
+            .push_bind(limit);
+        sql.build_query_as::<MatchRow>().fetch_all(pool).await?
+    });
+
+    // Die Umgebung aller Treffer in einer Abfrage
+    let mut lines: HashMap<String, HashMap<i64, String>> = HashMap::new();
+    if context > 0 && !rows.is_empty() {
+        let context_rows = with_db!(&state.db_pool, |pool| {
+            let mut sql =
+                QueryBuilder::new("SELECT job_id, line_no, content FROM job_log_lines WHERE ");
+            let mut ranges = sql.separated(" OR ");
+            for row in &rows {
+                ranges
+                    .push("(job_id = ")
+                    .push_bind_unseparated(&row.job_id)
+                    .push_unseparated(" AND line_no BETWEEN ")
+                    .push_bind_unseparated(row.line_no - context)
+                    .push_unseparated(" AND ")
+                    .push_bind_unseparated(row.line_no + context)
+                    .push_unseparated(")");
+            }
+            sql.build_query_as::<ContextRow>().fetch_all(pool).await?
+        });
+        for line in context_rows {
+            lines
+                .entry(line.job_id)
+                .or_default()
+                .insert(line.line_no, line.content);
+        }
+    }
+    let surrounding = |job_id: &str, range: Range<i64>| -> Vec<String> {
+        let Some(job_lines) = lines.get(job_id) else {
+            return Vec::new();
+        };
+        range
+            .filter_map(|line_no| job_lines.get(&line_no).cloned())
+            .collect()
+    };
+
+    let mut matches = Vec::with_capacity(rows.len());
+    for row in rows {
+        let before = surrounding(&row.job_id, row.line_no - context..row.line_no);
+        let after = surrounding(&row.job_id, row.line_no + 1..row.line_no + context + 1);
+        matches.push(LogSearchMatch {
+            job_id: row.job_id,
+            project_id: row.project_id,
+            job_status: row.status,
+            created_at: row.created_at,
+            step_index: row.step_index,
+            line: row.line_no,
+            output: row.content,
+            before,
+            after,
+        });
+    }
+    Ok(Json(matches))
+}
@@ -0,0 +1,20 @@
+use crate::config::{LogFormat, LoggingConfig};
+use std::io::IsTerminal;
+use tracing_subscriber::EnvFilter;
+
+/// Richtet die Log-Ausgabe ein. Im JSON-Format enthält jede Zeile die Felder
+/// der aktiven Spans, etwa `agent_id` und `trace_id` einer Agent-Session.
+pub fn init(config: &LoggingConfig) {
+    // Das Level wurde beim Laden der Konfiguration bereits geprüft
+    let filter = EnvFilter::new(&config.level);
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.format {
+        // Farben nur im Terminal, nicht in umgeleiteten Logdateien
+        LogFormat::Pretty => builder.with_ansi(std::io::stdout().is_terminal()).init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
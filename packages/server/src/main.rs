@@ -1,63 +1,376 @@
+use clap::{Args, Parser, Subcommand};
 use futures_util::future::TryFutureExt;
 use server::{
+    auth,
+    config::{self, ConfigOverrides, ServerConfig},
     db,
     grpc_server::{MyRunnerService, RunnerServiceServer},
     http_server,
+    job_logs::LogStore,
+    liveness::Liveness,
+    log_search, logging, migrations, otel,
+    shutdown::{self, Shutdown},
     state::AppState,
-    tasks, AppError, LiveAgentMap, Result, WsClientMap,
+    tasks, tls, AppError, LiveAgentMap, Result, WsClientMap,
 };
-use std::io;
-use std::net::SocketAddr;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use tonic::transport::Server;
+use tracing::{error, info, warn};
+
+#[derive(Parser)]
+#[command(name = "server", about = "Deliversphere server")]
+struct Cli {
+    /// Config-Datei (TOML). Default: `SERVER_CONFIG` oder ./deliversphere.toml, falls vorhanden
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Startet REST/WebSocket- und gRPC-Server (Standard)
+    Serve(ServeArgs),
+    /// Zeigt, wendet an oder macht Datenbank-Migrationen rückgängig
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateCommand,
+    },
+    /// Prüft die Konfiguration (Datei, Env-Vars) ohne den Server zu starten
+    CheckConfig {
+        /// Zu prüfende Datei, alternativ zu --config
+        file: Option<PathBuf>,
+    },
+    /// Erzeugt eine lokale CA sowie Server- und Agent-Zertifikate für mTLS in der Entwicklung
+    DevCerts {
+        /// Zielverzeichnis für die PEM-Dateien
+        #[arg(long, default_value = "certs")]
+        out_dir: PathBuf,
+        /// DNS-Namen bzw. IPs, unter denen Agents den Server erreichen
+        #[arg(long = "server-name", default_values_t = ["localhost".to_string(), "127.0.0.1".to_string()])]
+        server_names: Vec<String>,
+        /// Agent-IDs, für die ein Client-Zertifikat erzeugt wird (Common Name = Agent-ID)
+        #[arg(long = "agent", required = true)]
+        agent_ids: Vec<String>,
+    },
+    /// Startet einen OTLP-Collector (gRPC), der empfangene Spans ausgibt, zum Testen des Exports
+    DevOtelCollector {
+        #[arg(long, default_value = "127.0.0.1:4317")]
+        addr: std::net::SocketAddr,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateCommand {
+    /// Listet alle Migrationen und ob sie angewendet sind
+    Status,
+    /// Wendet alle ausstehenden Migrationen an
+    Up,
+    /// Macht die zuletzt angewendeten Migrationen rückgängig
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+}
+
+#[derive(Args, Default)]
+struct ServeArgs {
+    #[arg(long)]
+    http_addr: Option<String>,
+    #[arg(long)]
+    grpc_addr: Option<String>,
+    #[arg(long)]
+    database_url: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    println!("Initialisiere Server...");
-    let db_pool = db::init_pool().await?;
+    let cli = Cli::parse();
+    match cli
+        .command
+        .unwrap_or_else(|| Command::Serve(ServeArgs::default()))
+    {
+        Command::Serve(args) => {
+            let overrides = ConfigOverrides {
+                http_addr: args.http_addr,
+                grpc_addr: args.grpc_addr,
+                database_url: args.database_url,
+            };
+            let config = config::load_config(cli.config.as_deref(), &overrides)?;
+            init_logging(&config);
+            serve(config).await
+        }
+        Command::Migrate { action } => {
+            let config = config::load_config(cli.config.as_deref(), &ConfigOverrides::default())?;
+            init_logging(&config);
+            migrate(&config, action).await
+        }
+        Command::CheckConfig { file } => check_config(file.or(cli.config).as_deref()),
+        Command::DevCerts {
+            out_dir,
+            server_names,
+            agent_ids,
+        } => tls::generate_dev_certs(&out_dir, &server_names, &agent_ids),
+        Command::DevOtelCollector { addr } => otel::run_dev_collector(addr).await,
+    }
+}
+
+fn init_logging(config: &ServerConfig) {
+    logging::init(&config.logging);
+    if let Some(path) = &config.config_file {
+        info!(path = %path.display(), "Config-Datei geladen");
+    }
+}
+
+fn check_config(file: Option<&std::path::Path>) -> Result<()> {
+    let config = config::load_config(file, &ConfigOverrides::default())?;
+    println!("Konfiguration ist gültig:");
+    println!("  REST/WebSocket: {}", config.http_addr);
+    println!("  gRPC:           {}", config.grpc_addr);
+    println!(
+        "  Datenbank:      {} (max. {} Verbindungen)",
+        config.redacted_database_url(),
+        config.database.max_connections
+    );
+    println!(
+        "  Heartbeats:     alle {}s, offline nach {}s, gespeichert alle {}s",
+        config.heartbeat_interval.as_secs(),
+        config.agent_offline_after.as_secs(),
+        config.heartbeat_flush_interval.as_secs()
+    );
+    println!(
+        "  Shutdown:       max. {}s Wartezeit",
+        config.shutdown_timeout.as_secs()
+    );
+    println!("  Daten:          {}", config.data_dir.display());
+    println!("  Logs:           {}", config.log_dir.display());
+    println!(
+        "  Aufbewahrung:   {}",
+        if config.retention.is_enabled() {
+            format!(
+                "max_age_days={}, max_jobs_per_project={}, max_storage_mb={}, keep_last_successful={}",
+                config
+                    .retention
+                    .max_age
+                    .map_or("-".to_string(), |age| (age.as_secs() / 86400).to_string()),
+                config
+                    .retention
+                    .max_jobs_per_project
+                    .map_or("-".to_string(), |n| n.to_string()),
+                config
+                    .retention
+                    .max_storage_bytes
+                    .map_or("-".to_string(), |bytes| (bytes / 1024 / 1024).to_string()),
+                config.retention.keep_last_successful
+            )
+        } else {
+            "aus".to_string()
+        }
+    );
+    println!(
+        "  Agent-Metriken: {}h aufbewahren",
+        config.retention.agent_metrics_max_age.as_secs() / 3600
+    );
+    println!(
+        "  Scheduling:     min_free_disk_mb={}, min_available_memory_mb={}",
+        config
+            .scheduling
+            .min_free_disk_bytes
+            .map_or("-".to_string(), |bytes| (bytes / 1024 / 1024).to_string()),
+        config
+            .scheduling
+            .min_available_memory_bytes
+            .map_or("-".to_string(), |bytes| (bytes / 1024 / 1024).to_string()),
+    );
+    println!(
+        "  gRPC TLS:       {}",
+        match &config.tls {
+            Some(tls) if tls.client_ca.is_some() => "mTLS",
+            Some(_) => "TLS",
+            None => "aus",
+        }
+    );
+    println!(
+        "  Features:       bootstrap_admin={}, websocket={}, metrics={}",
+        config.features.bootstrap_admin, config.features.websocket, config.features.metrics
+    );
+    println!(
+        "  Logging:        format={}, level={}",
+        match config.logging.format {
+            config::LogFormat::Pretty => "pretty",
+            config::LogFormat::Json => "json",
+        },
+        config.logging.level
+    );
+    println!(
+        "  OTLP-Export:    {}",
+        match &config.otel.endpoint {
+            Some(endpoint) => format!("{} als '{}'", endpoint, config.otel.service_name),
+            None => "aus".to_string(),
+        }
+    );
+    Ok(())
+}
+
+async fn migrate(config: &ServerConfig, action: MigrateCommand) -> Result<()> {
+    let db_pool = db::init_pool(&config.database).await?;
+    match action {
+        MigrateCommand::Status => {
+            let status = migrations::status(&db_pool).await?;
+            for migration in &status.migrations {
+                println!(
+                    "{} {} {}",
+                    if migration.applied { "[x]" } else { "[ ]" },
+                    migration.version,
+                    migration.description
+                );
+            }
+            for version in &status.unknown_versions {
+                println!("[?] {} (unbekannt, neuer als dieses Binary)", version);
+            }
+            println!("{} Migration(en) ausstehend.", status.pending());
+        }
+        MigrateCommand::Up => {
+            let applied = migrations::run_pending(&db_pool).await?;
+            println!("{} Migration(en) angewendet.", applied);
+        }
+        MigrateCommand::Down { steps } => {
+            let reverted = migrations::revert(&db_pool, steps).await?;
+            for version in &reverted {
+                println!("Migration {} rückgängig gemacht.", version);
+            }
+            if reverted.is_empty() {
+                println!("Keine angewendeten Migrationen vorhanden.");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn serve(config: ServerConfig) -> Result<()> {
+    info!("Initialisiere Server...");
+    otel::init(&config.otel)?;
+    fs::create_dir_all(&config.data_dir)?;
+    fs::create_dir_all(&config.log_dir)?;
+
+    let config = Arc::new(config);
+    let db_pool = db::init_pool(&config.database).await?;
+    let applied = migrations::run_pending(&db_pool).await?;
+    if applied > 0 {
+        info!("{} Datenbank-Migration(en) angewendet.", applied);
+    }
+    if config.features.bootstrap_admin {
+        auth::bootstrap_admin(&db_pool).await?;
+    }
     let live_agents = LiveAgentMap::default();
     let ws_clients = WsClientMap::default();
+    let shutdown = Shutdown::new();
+    let logs = LogStore::new(config.clone(), db_pool.clone());
+    let liveness = Liveness::new(config.heartbeat_interval, config.agent_offline_after);
     let app_state = AppState {
+        config: config.clone(),
         db_pool: db_pool.clone(),
         ws_clients: ws_clients.clone(),
+        logs: logs.clone(),
+        live_agents: live_agents.clone(),
+        liveness: liveness.clone(),
+        shutdown: shutdown.clone(),
     };
 
-    tasks::spawn_background_tasks(db_pool.clone());
+    // Nach einem Absturz können noch Agents als online in der Datenbank stehen
+    shutdown::mark_agents_offline(&db_pool).await?;
+    liveness.spawn_watchdog(db_pool.clone(), ws_clients.clone(), logs.clone());
+    tasks::spawn_background_tasks(
+        db_pool.clone(),
+        liveness.clone(),
+        config.heartbeat_flush_interval,
+        config.retention.agent_metrics_max_age,
+    );
+    tasks::spawn_retention_task(db_pool.clone(), config.clone());
+    tasks::spawn_dispatcher(
+        db_pool.clone(),
+        live_agents.clone(),
+        ws_clients.clone(),
+        config.clone(),
+        shutdown.clone(),
+    );
+    log_search::spawn_log_indexer(db_pool.clone(), logs.clone());
 
-    let grpc_addr = "[::]:3001".parse().map_err(|e| {
-        AppError::Io(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Invalid gRPC address: {}", e),
-        ))
-    })?;
+    let grpc_addr = config.grpc_addr;
     let runner_service = MyRunnerService {
-        db_pool,
-        live_agents,
+        db_pool: db_pool.clone(),
+        live_agents: live_agents.clone(),
         ws_clients,
+        logs,
+        liveness: liveness.clone(),
+        shutdown: shutdown.clone(),
     };
-    let grpc_server_future = Server::builder()
+    let mut grpc_builder = Server::builder();
+    if let Some(tls_settings) = &config.tls {
+        grpc_builder = grpc_builder.tls_config(tls_settings.server_tls_config()?)?;
+        info!(
+            "gRPC TLS aktiv{}.",
+            if tls_settings.client_ca.is_some() {
+                " mit Client-Zertifikatsprüfung (mTLS)"
+            } else {
+                ""
+            }
+        );
+    }
+    let grpc_server_future = grpc_builder
         .add_service(RunnerServiceServer::new(runner_service))
-        .serve(grpc_addr)
-        .map_err(|e| AppError::from(e));
-
-    let rest_addr: SocketAddr = "[::]:3000".parse().map_err(|e| {
-        AppError::Io(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Invalid REST address: {}", e),
-        ))
-    })?;
+        .serve_with_shutdown(grpc_addr, shutdown.wait())
+        .map_err(AppError::from);
+
+    let rest_addr = config.http_addr;
     let rest_router = http_server::create_router(app_state);
     let listener = TcpListener::bind(rest_addr).await?;
     let rest_server_future = async {
         axum::serve(listener, rest_router.into_make_service())
+            .with_graceful_shutdown(shutdown.wait())
             .await
-            .map_err(|e| AppError::Io(e))
+            .map_err(AppError::Io)
     };
 
-    println!("REST/WebSocket Server lauscht auf {}", rest_addr);
-    println!("gRPC Server lauscht auf {}", grpc_addr);
-    tokio::try_join!(grpc_server_future, rest_server_future)?;
+    // Beim Signal: keine neuen Jobs und Agents mehr annehmen, Agents benachrichtigen
+    // und die Server auf laufende Requests und Streams warten lassen
+    let signal_handler = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::wait_for_signal().await;
+            shutdown.trigger();
+            shutdown::notify_agents(&live_agents, "server is shutting down").await;
+        }
+    });
+
+    info!("REST/WebSocket Server lauscht auf {}", rest_addr);
+    info!("gRPC Server lauscht auf {}", grpc_addr);
+    tokio::select! {
+        result = async { tokio::try_join!(grpc_server_future, rest_server_future) } => {
+            result?;
+        }
+        _ = shutdown.wait_grace(config.shutdown_timeout) => {
+            warn!(
+                "Offene Verbindungen nach {}s nicht beendet, fahre trotzdem herunter.",
+                config.shutdown_timeout.as_secs()
+            );
+        }
+    }
+    signal_handler.abort();
 
-    println!("Server heruntergefahren.");
+    if let Err(e) = liveness.flush(&db_pool).await {
+        error!("Fehler beim Speichern der Heartbeats: {}", e);
+    }
+    match shutdown::mark_agents_offline(&db_pool).await {
+        Ok(0) => {}
+        Ok(count) => info!("{} Agents als 'offline' markiert.", count),
+        Err(e) => error!("Konnte Agents nicht als offline markieren: {}", e),
+    }
+    db_pool.close().await;
+    let _ = tokio::task::spawn_blocking(otel::shutdown).await;
+    info!("Server heruntergefahren.");
     Ok(())
 }
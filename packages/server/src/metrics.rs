@@ -0,0 +1,195 @@
+//! Prometheus-Metriken des Servers unter `GET /metrics`. Die Namen sind stabil,
+//! Dashboards und Alerts bauen darauf auf; umbenennen nur mit Übergangszeit.
+//!
+//! | Name                                        | Typ       | Labels    |
+//! |---------------------------------------------|-----------|-----------|
+//! | `deliversphere_jobs`                        | Gauge     | `status`  |
+//! | `deliversphere_jobs_finished_total`         | Counter   | `status`  |
+//! | `deliversphere_job_queue_wait_seconds`      | Histogram |           |
+//! | `deliversphere_job_duration_seconds`        | Histogram |           |
+//! | `deliversphere_agents`                      | Gauge     | `status`  |
+//! | `deliversphere_grpc_streams`                | Gauge     |           |
+//! | `deliversphere_websocket_clients`           | Gauge     |           |
+//! | `deliversphere_db_query_duration_seconds`   | Histogram | `backend` |
+//! | `deliversphere_heartbeat_lag_seconds`       | Histogram |           |
+//!
+//! Die Gauges für Jobs und Agents werden bei jedem Abruf aus der Datenbank gelesen.
+
+use crate::models::Job;
+use crate::state::AppState;
+use crate::{with_db, Result};
+use axum::{extract::State, http::header, response::IntoResponse};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+pub const JOBS: &str = "deliversphere_jobs";
+pub const JOBS_FINISHED_TOTAL: &str = "deliversphere_jobs_finished_total";
+pub const JOB_QUEUE_WAIT_SECONDS: &str = "deliversphere_job_queue_wait_seconds";
+pub const JOB_DURATION_SECONDS: &str = "deliversphere_job_duration_seconds";
+pub const AGENTS: &str = "deliversphere_agents";
+pub const GRPC_STREAMS: &str = "deliversphere_grpc_streams";
+pub const WEBSOCKET_CLIENTS: &str = "deliversphere_websocket_clients";
+pub const DB_QUERY_DURATION_SECONDS: &str = "deliversphere_db_query_duration_seconds";
+pub const HEARTBEAT_LAG_SECONDS: &str = "deliversphere_heartbeat_lag_seconds";
+
+struct Metrics {
+    registry: Registry,
+    jobs: IntGaugeVec,
+    jobs_finished: IntCounterVec,
+    job_queue_wait: Histogram,
+    job_duration: Histogram,
+    agents: IntGaugeVec,
+    grpc_streams: IntGauge,
+    websocket_clients: IntGauge,
+    db_query_duration: HistogramVec,
+    heartbeat_lag: Histogram,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        // Jobs dauern Sekunden bis Stunden: 1s bis ca. 4,5h
+        let job_buckets = exponential_buckets(1.0, 2.0, 15).unwrap();
+        let metrics = Metrics {
+            registry: Registry::new(),
+            jobs: IntGaugeVec::new(Opts::new(JOBS, "Jobs by current status"), &["status"])
+                .unwrap(),
+            jobs_finished: IntCounterVec::new(
+                Opts::new(JOBS_FINISHED_TOTAL, "Jobs finished by an agent, by result"),
+                &["status"],
+            )
+            .unwrap(),
+            job_queue_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    JOB_QUEUE_WAIT_SECONDS,
+                    "Time from job creation until an agent started it, recorded when the job finishes",
+                )
+                .buckets(job_buckets.clone()),
+            )
+            .unwrap(),
+            job_duration: Histogram::with_opts(
+                HistogramOpts::new(JOB_DURATION_SECONDS, "Time from job start until its result")
+                    .buckets(job_buckets),
+            )
+            .unwrap(),
+            agents: IntGaugeVec::new(Opts::new(AGENTS, "Agents by status"), &["status"]).unwrap(),
+            grpc_streams: IntGauge::new(GRPC_STREAMS, "Open agent gRPC streams").unwrap(),
+            websocket_clients: IntGauge::new(WEBSOCKET_CLIENTS, "Connected WebSocket clients")
+                .unwrap(),
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new(DB_QUERY_DURATION_SECONDS, "Duration of database operations"),
+                &["backend"],
+            )
+            .unwrap(),
+            heartbeat_lag: Histogram::with_opts(
+                HistogramOpts::new(
+                    HEARTBEAT_LAG_SECONDS,
+                    "How much later than the negotiated interval a heartbeat arrived",
+                )
+                .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0]),
+            )
+            .unwrap(),
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+            Box::new(metrics.jobs.clone()),
+            Box::new(metrics.jobs_finished.clone()),
+            Box::new(metrics.job_queue_wait.clone()),
+            Box::new(metrics.job_duration.clone()),
+            Box::new(metrics.agents.clone()),
+            Box::new(metrics.grpc_streams.clone()),
+            Box::new(metrics.websocket_clients.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+            Box::new(metrics.heartbeat_lag.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+}
+
+/// Misst eine Datenbankoperation bis zum Drop, siehe [`with_db!`].
+pub fn db_query_timer(backend: &str) -> HistogramTimer {
+    METRICS
+        .db_query_duration
+        .with_label_values(&[backend])
+        .start_timer()
+}
+
+/// Erfasst einen abgeschlossenen Job samt Warte- und Laufzeit.
+pub fn job_finished(job: &Job) {
+    METRICS
+        .jobs_finished
+        .with_label_values(&[&job.status])
+        .inc();
+    if let Some(started_at) = job.started_at {
+        METRICS
+            .job_queue_wait
+            .observe((started_at - job.created_at).max(0) as f64);
+        if let Some(finished_at) = job.finished_at {
+            METRICS
+                .job_duration
+                .observe((finished_at - started_at).max(0) as f64);
+        }
+    }
+}
+
+pub fn heartbeat_lag(lag: Duration) {
+    METRICS.heartbeat_lag.observe(lag.as_secs_f64());
+}
+
+/// Zählt einen offenen gRPC-Stream, solange der Guard lebt.
+pub struct GrpcStreamGuard(());
+
+impl GrpcStreamGuard {
+    pub fn open() -> Self {
+        METRICS.grpc_streams.inc();
+        GrpcStreamGuard(())
+    }
+}
+
+impl Drop for GrpcStreamGuard {
+    fn drop(&mut self) {
+        METRICS.grpc_streams.dec();
+    }
+}
+
+/// Anzahl je Status, z.B. aus `SELECT status, COUNT(*) ... GROUP BY status`.
+type StatusCounts = Vec<(String, i64)>;
+
+async fn refresh_gauges(state: &AppState) -> Result<()> {
+    let (jobs, agents): (StatusCounts, StatusCounts) = with_db!(&state.db_pool, |pool| {
+        (
+            sqlx::query_as("SELECT status, COUNT(*) FROM jobs GROUP BY status")
+                .fetch_all(pool)
+                .await?,
+            sqlx::query_as("SELECT status, COUNT(*) FROM agents GROUP BY status")
+                .fetch_all(pool)
+                .await?,
+        )
+    });
+    // Statuswerte ohne Zeilen sollen verschwinden statt stehen zu bleiben
+    METRICS.jobs.reset();
+    for (status, count) in jobs {
+        METRICS.jobs.with_label_values(&[&status]).set(count);
+    }
+    METRICS.agents.reset();
+    for (status, count) in agents {
+        METRICS.agents.with_label_values(&[&status]).set(count);
+    }
+    METRICS.websocket_clients.set(state.ws_clients.len() as i64);
+    Ok(())
+}
+
+/// Metriken im Prometheus-Textformat.
+pub async fn metrics_handler(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    refresh_gauges(&state).await?;
+    let mut body = Vec::new();
+    TextEncoder::new().encode(&METRICS.registry.gather(), &mut body)?;
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
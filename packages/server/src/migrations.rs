@@ -0,0 +1,108 @@
+//! Die Migrationen werden beim Kompilieren eingebettet (siehe
+//! [`Dialect::migrator`](crate::db::Dialect::migrator)), sqlx-cli ist zur
+//! Laufzeit nicht nötig. Jede Migration hat eine `.up.sql` und `.down.sql`.
+
+use crate::db::{DbPool, Dialect};
+use crate::{with_db, AppError, Result};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::{Postgres, Sqlite};
+use std::collections::HashSet;
+
+fn migrator(db_pool: &DbPool) -> &'static Migrator {
+    match db_pool {
+        DbPool::Sqlite(_) => Sqlite::migrator(),
+        DbPool::Postgres(_) => Postgres::migrator(),
+    }
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Bekannte Migrationen (aufsteigend) sowie Versionen, die in der Datenbank
+/// angewendet sind, die dieses Binary aber nicht kennt.
+#[derive(Debug)]
+pub struct SchemaStatus {
+    pub migrations: Vec<MigrationStatus>,
+    pub unknown_versions: Vec<i64>,
+}
+
+impl SchemaStatus {
+    pub fn pending(&self) -> usize {
+        self.migrations.iter().filter(|m| !m.applied).count()
+    }
+}
+
+async fn applied_versions(db_pool: &DbPool) -> Result<Vec<i64>> {
+    let applied = with_db!(db_pool, |pool| {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        conn.list_applied_migrations().await?
+    });
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+pub async fn status(db_pool: &DbPool) -> Result<SchemaStatus> {
+    let applied: HashSet<i64> = applied_versions(db_pool).await?.into_iter().collect();
+    let migrator = migrator(db_pool);
+
+    let migrations = migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect();
+    let mut unknown_versions: Vec<i64> = applied
+        .into_iter()
+        .filter(|version| !migrator.version_exists(*version))
+        .collect();
+    unknown_versions.sort();
+
+    Ok(SchemaStatus {
+        migrations,
+        unknown_versions,
+    })
+}
+
+/// Wendet alle ausstehenden Migrationen an. Parallel startende Server warten
+/// dabei auf den Lock des Migrators (Advisory Lock bei Postgres).
+/// Ist die Datenbank bereits auf einem neueren Schema, bricht der Start ab.
+pub async fn run_pending(db_pool: &DbPool) -> Result<usize> {
+    let status = status(db_pool).await?;
+    if let Some(newest) = status.unknown_versions.last() {
+        return Err(AppError::Schema(format!(
+            "database schema is at version {}, which this server does not know; refusing to start with an older binary",
+            newest
+        )));
+    }
+
+    let pending = status.pending();
+    if pending > 0 {
+        let migrator = migrator(db_pool);
+        with_db!(db_pool, |pool| migrator.run(pool).await?);
+    }
+    Ok(pending)
+}
+
+/// Macht die letzten `steps` angewendeten Migrationen rückgängig und liefert
+/// deren Versionen, die neueste zuerst.
+pub async fn revert(db_pool: &DbPool, steps: usize) -> Result<Vec<i64>> {
+    let mut applied = applied_versions(db_pool).await?;
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    let reverted: Vec<i64> = applied.iter().take(steps).copied().collect();
+    if reverted.is_empty() {
+        return Ok(reverted);
+    }
+    // undo() setzt alle Migrationen oberhalb der Zielversion zurück
+    let target = applied.get(steps).copied().unwrap_or(0);
+    let migrator = migrator(db_pool);
+    with_db!(db_pool, |pool| migrator.undo(pool, target).await?);
+    Ok(reverted)
+}
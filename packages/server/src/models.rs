@@ -7,15 +7,121 @@ pub struct Agent {
     pub hostname: String,
     pub status: String,
     pub last_heartbeat: i64,
+    /// Ausgehandelte Protokollversion, 0 vor der ersten Registrierung.
+    pub protocol_version: i64,
+    pub agent_version: String,
+    /// Nur bei Status `incompatible` gesetzt.
+    pub incompatible_reason: Option<String>,
+    #[sqlx(json)]
+    pub labels: Vec<String>,
+    /// So viele Jobs verteilt der Scheduler gleichzeitig an den Agent.
+    pub concurrency: i64,
+}
+
+/// Ein Messpunkt aus dem Heartbeat eines Agents.
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct AgentMetrics {
+    pub agent_id: String,
+    pub recorded_at: i64,
+    pub cpu_usage: f64,
+    pub load_average: f64,
+    pub memory_total_bytes: i64,
+    pub memory_available_bytes: i64,
+    pub disk_total_bytes: i64,
+    pub disk_free_bytes: i64,
+    pub running_jobs: i64,
+    pub agent_version: String,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub is_admin: bool,
+    pub created_at: i64,
+}
+
+/// Metadaten eines API-Tokens; der Token selbst wird nur bei der Erstellung ausgegeben.
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    #[sqlx(json)]
+    pub scopes: Vec<crate::auth::Scope>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    pub repository_url: String,
+    pub default_branch: String,
+    pub pipeline_path: String,
+    // Das Secret wird nur geschrieben, nie über die API ausgeliefert
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<String>,
+    #[sqlx(json)]
+    pub settings: serde_json::Value,
+    pub created_at: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct ProjectMember {
+    pub project_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub role: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct Pipeline {
+    pub id: String,
+    pub project_id: String,
+    pub branch: String,
+    pub pipeline_path: String,
+    pub status: String,
+    pub created_at: i64,
 }
 
 #[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
 pub struct Job {
     pub id: String,
     pub agent_id: Option<String>,
+    pub project_id: Option<String>,
+    pub pipeline_id: Option<String>,
     pub status: String,
     pub repository_url: String,
     #[sqlx(json)]
     pub commands: Vec<String>,
     pub created_at: i64,
+    /// Angepinnte Jobs sind von den Aufbewahrungsregeln ausgenommen.
+    pub pinned: bool,
+    /// Seit ein Agent den Job übernommen hat.
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Kategorie des Fehlschlags, z.B. `command_failed` oder `agent_lost`.
+    pub failure_reason: Option<String>,
+    pub failure_message: Option<String>,
+}
+
+/// Ein Befehl eines Jobs mit seinem Ergebnis. Zeitpunkte in Unix-Millisekunden.
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct JobStep {
+    pub job_id: String,
+    pub step_index: i64,
+    pub command: String,
+    pub status: String,
+    pub exit_code: Option<i64>,
+    pub signal: Option<i64>,
+    pub started_at_ms: Option<i64>,
+    pub finished_at_ms: Option<i64>,
+    pub duration_ms: Option<i64>,
 }
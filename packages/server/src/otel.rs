@@ -0,0 +1,274 @@
+//! Export der Job-Traces per OTLP. Jeder abgeschlossene Job ergibt einen Trace:
+//! der Server liefert den Wurzel-Span `job` mit `queue_wait` und `dispatch`, der Agent hängt
+//! die Ausführung (`execute` mit `checkout`, `step`) darunter.
+//!
+//! Trace- und Wurzel-Span-ID werden aus der Job-ID abgeleitet, Server und Agent
+//! kommen so ohne Austausch auf dieselben IDs. Der Agent rechnet identisch, siehe
+//! `agent::otel`.
+
+use crate::config::OtelConfig;
+use crate::models::Job;
+use crate::{AppError, Result};
+use opentelemetry::trace::{
+    Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer, TracerProvider as _,
+};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    trace_service_server::{TraceService, TraceServiceServer},
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tracing::info;
+
+static ENABLED: OnceLock<()> = OnceLock::new();
+
+/// Richtet den Export ein, falls ein Collector konfiguriert ist. Muss innerhalb
+/// der Tokio-Runtime aufgerufen werden.
+pub fn init(config: &OtelConfig) -> Result<()> {
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(());
+    };
+    let provider =
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(sdktrace::config().with_resource(Resource::new(vec![
+                KeyValue::new("service.name", config.service_name.clone()),
+            ])))
+            .install_batch(runtime::Tokio)
+            .map_err(|e| AppError::Config(format!("OTLP export could not be set up: {}", e)))?
+            .provider()
+            .ok_or_else(|| {
+                AppError::Config("OTLP pipeline did not install a tracer provider".to_string())
+            })?;
+    global::set_tracer_provider(provider);
+    let _ = ENABLED.set(());
+    info!("Job-Traces werden an {} exportiert.", endpoint);
+    Ok(())
+}
+
+/// Exportiert die noch gepufferten Spans. Blockiert, daher nicht auf einem
+/// Runtime-Thread aufrufen.
+pub fn shutdown() {
+    if ENABLED.get().is_some() {
+        global::shutdown_tracer_provider();
+    }
+}
+
+/// Trace-ID eines Jobs: die 128 Bit seiner UUID. Andere IDs haben keinen Trace.
+pub fn job_trace_id(job_id: &str) -> Option<TraceId> {
+    let hex: String = job_id.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    TraceId::from_hex(&hex)
+        .ok()
+        .filter(|id| *id != TraceId::INVALID)
+}
+
+/// Span-ID des Wurzel-Spans `job`, unter den der Agent seine Spans hängt.
+pub fn job_root_span_id(trace_id: TraceId) -> SpanId {
+    let bytes = trace_id.to_bytes();
+    let mut id = [0u8; 8];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = bytes[i] ^ bytes[i + 8];
+    }
+    // Eine Span-ID aus lauter Nullen ist ungültig
+    if id == [0; 8] {
+        id[7] = 1;
+    }
+    SpanId::from_bytes(id)
+}
+
+fn timestamp(secs: i64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Erfasst einen abgeschlossenen Job als Wurzel-Span samt Wartezeit in der Queue.
+pub fn record_job(job: &Job) {
+    if ENABLED.get().is_none() {
+        return;
+    }
+    let Some(trace_id) = job_trace_id(&job.id) else {
+        return;
+    };
+    let tracer = global::tracer_provider().tracer("deliversphere-server");
+    let created_at = timestamp(job.created_at);
+    let finished_at = timestamp(job.finished_at.unwrap_or(job.created_at));
+
+    let mut attributes = vec![
+        KeyValue::new("job.id", job.id.clone()),
+        KeyValue::new("job.status", job.status.clone()),
+        KeyValue::new("job.repository_url", job.repository_url.clone()),
+        KeyValue::new("job.commands", job.commands.len() as i64),
+    ];
+    if let Some(agent_id) = &job.agent_id {
+        attributes.push(KeyValue::new("agent.id", agent_id.clone()));
+    }
+    if let Some(project_id) = &job.project_id {
+        attributes.push(KeyValue::new("project.id", project_id.clone()));
+    }
+    if let Some(pipeline_id) = &job.pipeline_id {
+        attributes.push(KeyValue::new("pipeline.id", pipeline_id.clone()));
+    }
+    if let Some(failure_reason) = &job.failure_reason {
+        attributes.push(KeyValue::new("job.failure_reason", failure_reason.clone()));
+    }
+    let root = tracer.build_with_context(
+        tracer
+            .span_builder("job")
+            .with_kind(SpanKind::Server)
+            .with_trace_id(trace_id)
+            .with_span_id(job_root_span_id(trace_id))
+            .with_start_time(created_at)
+            .with_attributes(attributes)
+            .with_status(if job.status == "success" {
+                Status::Ok
+            } else {
+                Status::error(job.status.clone())
+            }),
+        &Context::new(),
+    );
+    let cx = Context::new().with_span(root);
+
+    if let Some(started_at) = job.started_at {
+        tracer
+            .build_with_context(
+                tracer
+                    .span_builder("queue_wait")
+                    .with_start_time(created_at),
+                &cx,
+            )
+            .end_with_timestamp(timestamp(started_at));
+    }
+    // Ohne explizites Ende setzt das SDK bei gleicher Start- und Endsekunde "jetzt"
+    cx.span().end_with_timestamp(finished_at);
+}
+
+/// Erfasst die Übergabe eines Jobs an einen Agent, von `start` bis jetzt.
+/// Der Span hängt unter dem Wurzel-Span, den [`record_job`] erst am Ende exportiert.
+pub fn record_dispatch(job: &Job, agent_id: &str, start: SystemTime) {
+    if ENABLED.get().is_none() {
+        return;
+    }
+    let Some(trace_id) = job_trace_id(&job.id) else {
+        return;
+    };
+    let tracer = global::tracer_provider().tracer("deliversphere-server");
+    let root = SpanContext::new(
+        trace_id,
+        job_root_span_id(trace_id),
+        TraceFlags::SAMPLED,
+        false,
+        TraceState::default(),
+    );
+    tracer
+        .build_with_context(
+            tracer
+                .span_builder("dispatch")
+                .with_start_time(start)
+                .with_attributes(vec![KeyValue::new("agent.id", agent_id.to_string())]),
+            &Context::new().with_remote_span_context(root),
+        )
+        .end();
+}
+
+/// Ein minimaler OTLP-Collector für die Entwicklung: gibt empfangene Spans aus.
+#[derive(Default)]
+pub struct DevCollector {
+    /// Bekommt jede ausgegebene Zeile zusätzlich, etwa für Tests.
+    lines: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl DevCollector {
+    pub fn with_sink(lines: mpsc::UnboundedSender<String>) -> Self {
+        DevCollector { lines: Some(lines) }
+    }
+}
+
+#[tonic::async_trait]
+impl TraceService for DevCollector {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportTraceServiceRequest>,
+    ) -> std::result::Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+        for resource_spans in request.into_inner().resource_spans {
+            let service = resource_spans
+                .resource
+                .iter()
+                .flat_map(|resource| &resource.attributes)
+                .find(|attribute| attribute.key == "service.name")
+                .and_then(|attribute| attribute.value.as_ref()?.value.as_ref())
+                .map(format_value)
+                .unwrap_or_default();
+            for span in resource_spans
+                .scope_spans
+                .into_iter()
+                .flat_map(|scope| scope.spans)
+            {
+                let attributes: Vec<String> = span
+                    .attributes
+                    .iter()
+                    .filter_map(|attribute| {
+                        let value = attribute.value.as_ref()?.value.as_ref()?;
+                        Some(format!("{}={}", attribute.key, format_value(value)))
+                    })
+                    .collect();
+                let line = format!(
+                    "{} {} parent={} {} {:>8.3}s [{}] {}",
+                    hex::encode(&span.trace_id),
+                    hex::encode(&span.span_id),
+                    if span.parent_span_id.is_empty() {
+                        "-".to_string()
+                    } else {
+                        hex::encode(&span.parent_span_id)
+                    },
+                    span.name,
+                    span.end_time_unix_nano
+                        .saturating_sub(span.start_time_unix_nano) as f64
+                        / 1e9,
+                    service,
+                    attributes.join(" ")
+                );
+                println!("{}", line);
+                if let Some(lines) = &self.lines {
+                    let _ = lines.send(line);
+                }
+            }
+        }
+        Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::StringValue(value) => value.clone(),
+        Value::BoolValue(value) => value.to_string(),
+        Value::IntValue(value) => value.to_string(),
+        Value::DoubleValue(value) => value.to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Startet den Entwicklungs-Collector und läuft bis Ctrl+C.
+pub async fn run_dev_collector(addr: SocketAddr) -> Result<()> {
+    println!("OTLP-Collector lauscht auf {} (gRPC)", addr);
+    tonic::transport::Server::builder()
+        .add_service(TraceServiceServer::new(DevCollector::default()))
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use crate::auth::AuthUser;
+use crate::authz::{self, Action, Role};
+use crate::jobs::{query_jobs, JobFilter};
+use crate::models::{Job, Pipeline, Project, ProjectMember};
+use crate::state::AppState;
+use crate::{with_db, AppError, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+use sqlx::types::Json as JsonColumn;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateProject {
+    pub name: String,
+    pub repository_url: String,
+    pub default_branch: Option<String>,
+    pub pipeline_path: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub settings: Option<serde_json::Value>,
+}
+
+/// Alle Felder optional: nur gesetzte Felder werden überschrieben.
+/// Ein leeres `webhook_secret` entfernt das Secret.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProject {
+    pub name: Option<String>,
+    pub repository_url: Option<String>,
+    pub default_branch: Option<String>,
+    pub pipeline_path: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub settings: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetMemberRole {
+    pub role: Role,
+}
+
+fn require_non_empty(field: &str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "'{}' must not be empty",
+            field
+        )));
+    }
+    Ok(())
+}
+
+fn require_object(settings: &serde_json::Value) -> Result<()> {
+    if !settings.is_object() {
+        return Err(AppError::BadRequest(
+            "'settings' must be a JSON object".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn fetch_project(state: &AppState, project_id: &str) -> Result<Project> {
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, Project>("SELECT * FROM projects WHERE id = $1")
+            .bind(project_id)
+            .fetch_optional(pool)
+            .await?
+    })
+    .ok_or_else(|| AppError::NotFound(format!("project '{}'", project_id)))
+}
+
+/// Globale Admins sehen alle Projekte, alle anderen nur die, in denen sie Mitglied sind.
+pub async fn list_projects(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Vec<Project>>> {
+    let projects = with_db!(&state.db_pool, |pool| {
+        if auth.is_admin {
+            sqlx::query_as::<_, Project>("SELECT * FROM projects ORDER BY name")
+                .fetch_all(pool)
+                .await?
+        } else {
+            sqlx::query_as::<_, Project>(
+                r#"
+                SELECT p.* FROM projects p
+                JOIN project_members m ON m.project_id = p.id
+                WHERE m.user_id = $1
+                ORDER BY p.name
+                "#,
+            )
+            .bind(&auth.user_id)
+            .fetch_all(pool)
+            .await?
+        }
+    });
+    Ok(Json(projects))
+}
+
+pub async fn get_project(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+) -> Result<Json<Project>> {
+    let project = fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project.id, Action::ViewProject).await?;
+    Ok(Json(project))
+}
+
+/// Jeder Benutzer mit `write`-Scope darf Projekte anlegen und wird deren Admin.
+pub async fn create_project(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<CreateProject>,
+) -> Result<(StatusCode, Json<Project>)> {
+    require_non_empty("name", &input.name)?;
+    require_non_empty("repository_url", &input.repository_url)?;
+    let settings = input.settings.unwrap_or_else(|| serde_json::json!({}));
+    require_object(&settings)?;
+
+    let project_id = Uuid::new_v4().to_string();
+    let webhook_secret = input.webhook_secret.filter(|s| !s.is_empty());
+    // Projekt und Admin gemeinsam, sonst bliebe ein Projekt ohne Admin zurück
+    with_db!(&state.db_pool, |pool| {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO projects (id, name, repository_url, default_branch, pipeline_path, webhook_secret, settings)
+            VALUES ($1, $2, $3, COALESCE($4, 'main'), COALESCE($5, '.deliversphere.yml'), $6, $7)
+            "#,
+        )
+        .bind(&project_id)
+        .bind(input.name.trim())
+        .bind(&input.repository_url)
+        .bind(&input.default_branch)
+        .bind(&input.pipeline_path)
+        .bind(&webhook_secret)
+        .bind(JsonColumn(&settings))
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO project_members (project_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(&project_id)
+            .bind(&auth.user_id)
+            .bind(Role::Admin.as_str())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    });
+
+    let project = fetch_project(&state, &project_id).await?;
+    info!("Projekt '{}' angelegt ({}).", project.name, project.id);
+    Ok((StatusCode::CREATED, Json(project)))
+}
+
+pub async fn update_project(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+    Json(input): Json<UpdateProject>,
+) -> Result<Json<Project>> {
+    let existing = fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::EditProject).await?;
+
+    if let Some(name) = &input.name {
+        require_non_empty("name", name)?;
+    }
+    if let Some(url) = &input.repository_url {
+        require_non_empty("repository_url", url)?;
+    }
+    if let Some(settings) = &input.settings {
+        require_object(settings)?;
+    }
+
+    let webhook_secret = match input.webhook_secret {
+        Some(secret) if secret.is_empty() => None,
+        Some(secret) => Some(secret),
+        None => existing.webhook_secret,
+    };
+
+    let name = input
+        .name
+        .as_deref()
+        .map(str::trim)
+        .unwrap_or(&existing.name);
+    let repository_url = input.repository_url.unwrap_or(existing.repository_url);
+    let default_branch = input.default_branch.unwrap_or(existing.default_branch);
+    let pipeline_path = input.pipeline_path.unwrap_or(existing.pipeline_path);
+    let settings = input.settings.unwrap_or(existing.settings);
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query(
+            r#"
+            UPDATE projects SET
+                name = $1, repository_url = $2, default_branch = $3, pipeline_path = $4,
+                webhook_secret = $5, settings = $6
+            WHERE id = $7
+            "#,
+        )
+        .bind(name)
+        .bind(&repository_url)
+        .bind(&default_branch)
+        .bind(&pipeline_path)
+        .bind(&webhook_secret)
+        .bind(JsonColumn(&settings))
+        .bind(&project_id)
+        .execute(pool)
+        .await?;
+    });
+
+    Ok(Json(fetch_project(&state, &project_id).await?))
+}
+
+/// Pipelines und Mitgliedschaften werden mitgelöscht, Jobs bleiben ohne Projekt erhalten.
+pub async fn delete_project(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+) -> Result<StatusCode> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::DeleteProject).await?;
+
+    let rows_affected = with_db!(&state.db_pool, |pool| {
+        sqlx::query("DELETE FROM projects WHERE id = $1")
+            .bind(&project_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+    });
+
+    if rows_affected == 0 {
+        return Err(AppError::NotFound(format!("project '{}'", project_id)));
+    }
+    info!("Projekt {} gelöscht.", project_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_project_jobs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+    Query(mut filter): Query<JobFilter>,
+) -> Result<Json<Vec<Job>>> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::ViewProject).await?;
+    filter.project = Some(project_id);
+    Ok(Json(query_jobs(&state, &filter).await?))
+}
+
+pub async fn list_project_pipelines(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<Pipeline>>> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::ViewProject).await?;
+    let pipelines = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, Pipeline>(
+            "SELECT * FROM pipelines WHERE project_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(&project_id)
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(Json(pipelines))
+}
+
+pub async fn list_members(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(project_id): Path<String>,
+) -> Result<Json<Vec<ProjectMember>>> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::ViewProject).await?;
+
+    let members = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, ProjectMember>(
+            r#"
+            SELECT m.project_id, m.user_id, u.username, m.role, m.created_at
+            FROM project_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.project_id = $1
+            ORDER BY u.username
+            "#,
+        )
+        .bind(&project_id)
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(Json(members))
+}
+
+/// Niemand außer globalen Admins kann Rollen oberhalb der eigenen vergeben oder entziehen.
+async fn ensure_role_manageable(
+    state: &AppState,
+    auth: &AuthUser,
+    project_id: &str,
+    role: Role,
+) -> Result<()> {
+    if auth.is_admin {
+        return Ok(());
+    }
+    let own_role = authz::project_role(&state.db_pool, &auth.user_id, project_id).await?;
+    match own_role {
+        Some(own_role) if role <= own_role => Ok(()),
+        _ => Err(AppError::Forbidden(format!(
+            "cannot manage role '{}'",
+            role
+        ))),
+    }
+}
+
+/// Fügt ein Mitglied hinzu oder ändert seine Rolle.
+pub async fn set_member_role(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((project_id, user_id)): Path<(String, String)>,
+    Json(input): Json<SetMemberRole>,
+) -> Result<StatusCode> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::ManageMembers).await?;
+
+    ensure_role_manageable(&state, &auth, &project_id, input.role).await?;
+    if let Some(current) = authz::project_role(&state.db_pool, &user_id, &project_id).await? {
+        ensure_role_manageable(&state, &auth, &project_id, current).await?;
+    }
+
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query(
+            r#"
+            INSERT INTO project_members (project_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT(project_id, user_id) DO UPDATE SET role = excluded.role
+            "#,
+        )
+        .bind(&project_id)
+        .bind(&user_id)
+        .bind(input.role.as_str())
+        .execute(pool)
+        .await?;
+    });
+    info!(
+        "Benutzer {} hat im Projekt {} jetzt die Rolle '{}'.",
+        user_id, project_id, input.role
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_member(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((project_id, user_id)): Path<(String, String)>,
+) -> Result<StatusCode> {
+    fetch_project(&state, &project_id).await?;
+    authz::authorize_project(&state.db_pool, &auth, &project_id, Action::ManageMembers).await?;
+    if let Some(role) = authz::project_role(&state.db_pool, &user_id, &project_id).await? {
+        ensure_role_manageable(&state, &auth, &project_id, role).await?;
+    }
+
+    let rows_affected = with_db!(&state.db_pool, |pool| {
+        sqlx::query("DELETE FROM project_members WHERE project_id = $1 AND user_id = $2")
+            .bind(&project_id)
+            .bind(&user_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+    });
+    if rows_affected == 0 {
+        return Err(AppError::NotFound(format!(
+            "member '{}' in project '{}'",
+            user_id, project_id
+        )));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
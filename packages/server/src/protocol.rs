@@ -0,0 +1,80 @@
+//! Versionen des gRPC-Protokolls zwischen Server und Agent, siehe `proto/runner.proto`.
+
+/// Agents vor der Versionsaushandlung senden keine Version und gelten als Version 1.
+pub const LEGACY_VERSION: u32 = 1;
+/// Die höchste Version, die dieser Server spricht.
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Teile des Protokolls, die erst ab einer bestimmten Version verfügbar sind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// RegisterAck samt SessionSettings nach der Registrierung.
+    RegisterAck,
+    /// Fortsetzen einer Session nach einem Reconnect, mit Sequenznummern und Acks.
+    SessionResume,
+}
+
+impl Feature {
+    pub fn since(self) -> u32 {
+        match self {
+            Feature::RegisterAck => 2,
+            Feature::SessionResume => 3,
+        }
+    }
+
+    pub fn supported_by(self, protocol_version: u32) -> bool {
+        protocol_version >= self.since()
+    }
+}
+
+/// Handelt die Protokollversion aus den Angaben des Agents aus: die höchste
+/// Version, die beide Seiten sprechen. Der Server spricht alle Versionen ab
+/// [`LEGACY_VERSION`], abgelehnt werden nur Agents, die eine neuere verlangen.
+pub fn negotiate(agent_version: u32, agent_min_version: u32) -> std::result::Result<u32, String> {
+    let agent_version = agent_version.max(LEGACY_VERSION);
+    let agent_min_version = agent_min_version.clamp(LEGACY_VERSION, agent_version);
+    if agent_min_version > CURRENT_VERSION {
+        return Err(format!(
+            "agent requires protocol version {} or newer, server supports up to {}",
+            agent_min_version, CURRENT_VERSION
+        ));
+    }
+    Ok(agent_version.min(CURRENT_VERSION))
+}
+
+/// Metadata-Key, unter dem ein Agent die Trace-ID seiner Session mitschickt.
+/// Sie steht im Span der Session, so lassen sich die Logs von Server und
+/// Agent zu einem Job zusammenführen.
+pub const TRACE_ID_METADATA: &str = "x-trace-id";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_agent_gets_version_one() {
+        assert_eq!(negotiate(0, 0), Ok(LEGACY_VERSION));
+    }
+
+    #[test]
+    fn picks_highest_common_version() {
+        assert_eq!(negotiate(2, 1), Ok(2));
+        assert_eq!(negotiate(CURRENT_VERSION + 2, 1), Ok(CURRENT_VERSION));
+        // Eine Mindestversion über der eigenen Version zählt als die eigene
+        assert_eq!(negotiate(2, 9), Ok(2));
+    }
+
+    #[test]
+    fn rejects_agent_requiring_newer_version() {
+        let reason = negotiate(CURRENT_VERSION + 2, CURRENT_VERSION + 1).unwrap_err();
+        assert!(reason.contains("server supports up to"), "{reason}");
+    }
+
+    #[test]
+    fn features_follow_version() {
+        assert!(!Feature::RegisterAck.supported_by(LEGACY_VERSION));
+        assert!(Feature::RegisterAck.supported_by(2));
+        assert!(!Feature::SessionResume.supported_by(2));
+        assert!(Feature::SessionResume.supported_by(CURRENT_VERSION));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::auth::{AuthUser, Scope};
+use crate::config::ServerConfig;
+use crate::db::DbPool;
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, Result};
+use axum::{extract::State, Json};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+/// Nur abgeschlossene Jobs kommen für das Löschen in Frage.
+const FINISHED_STATUSES: &str = "'success', 'failed', 'cancelled'";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    MaxAge,
+    MaxJobsPerProject,
+    MaxStorage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrunableJob {
+    pub id: String,
+    pub project_id: Option<String>,
+    pub status: String,
+    pub created_at: i64,
+    pub reason: PruneReason,
+    /// Größe der Logs und Artefakte des Jobs.
+    pub bytes: u64,
+}
+
+/// Ergebnis eines (Probe-)Laufs der Aufbewahrungsregeln.
+#[derive(Debug, Default, Serialize)]
+pub struct RetentionReport {
+    pub jobs: Vec<PrunableJob>,
+    /// Belegter Speicher aller abgeschlossenen Jobs vor dem Löschen.
+    pub storage_bytes: u64,
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, FromRow)]
+struct Candidate {
+    id: String,
+    project_id: Option<String>,
+    status: String,
+    created_at: i64,
+    pinned: bool,
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+fn job_paths(config: &ServerConfig, job_id: &str) -> [PathBuf; 2] {
+    [config.job_log_dir(job_id), config.job_artifact_dir(job_id)]
+}
+
+fn job_size(config: &ServerConfig, job_id: &str) -> u64 {
+    job_paths(config, job_id)
+        .iter()
+        .filter_map(|path| dir_size(path).ok())
+        .sum()
+}
+
+fn remove_job_files(config: &ServerConfig, job_id: &str) {
+    for path in job_paths(config, job_id) {
+        match fs::remove_dir_all(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => error!("Konnte {} nicht löschen: {}", path.display(), e),
+        }
+    }
+}
+
+/// Ermittelt, welche Jobs die Aufbewahrungsregeln löschen würden, ohne etwas zu ändern.
+///
+/// Angepinnte Jobs und (mit `keep_last_successful`) der neueste erfolgreiche
+/// Job jedes Projekts bleiben immer erhalten. Die Regeln greifen nacheinander:
+/// erst das Alter, dann die Anzahl pro Projekt und zuletzt der Speicherplatz,
+/// bei dem die ältesten Jobs zuerst gelöscht werden.
+pub async fn plan(db_pool: &DbPool, config: &ServerConfig) -> Result<RetentionReport> {
+    let retention = &config.retention;
+    let candidates = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, Candidate>(&format!(
+            "SELECT id, project_id, status, created_at, pinned FROM jobs \
+             WHERE status IN ({}) ORDER BY created_at DESC, id",
+            FINISHED_STATUSES
+        ))
+        .fetch_all(pool)
+        .await?
+    });
+
+    let sizes: Vec<u64> = {
+        let config = config.clone();
+        let ids: Vec<String> = candidates.iter().map(|job| job.id.clone()).collect();
+        tokio::task::spawn_blocking(move || ids.iter().map(|id| job_size(&config, id)).collect())
+            .await?
+    };
+
+    let mut protected: HashSet<usize> = HashSet::new();
+    let mut last_successful: HashSet<Option<&str>> = HashSet::new();
+    for (index, job) in candidates.iter().enumerate() {
+        let newest_success = retention.keep_last_successful
+            && job.status == "success"
+            && last_successful.insert(job.project_id.as_deref());
+        if job.pinned || newest_success {
+            protected.insert(index);
+        }
+    }
+
+    let mut reasons: HashMap<usize, PruneReason> = HashMap::new();
+    if let Some(max_age) = retention.max_age {
+        let cutoff = unix_timestamp() - max_age.as_secs() as i64;
+        for (index, job) in candidates.iter().enumerate() {
+            if job.created_at < cutoff && !protected.contains(&index) {
+                reasons.insert(index, PruneReason::MaxAge);
+            }
+        }
+    }
+    if let Some(max_jobs) = retention.max_jobs_per_project {
+        let mut counts: HashMap<Option<&str>, u64> = HashMap::new();
+        for (index, job) in candidates.iter().enumerate() {
+            let count = counts.entry(job.project_id.as_deref()).or_default();
+            *count += 1;
+            if *count > max_jobs && !protected.contains(&index) {
+                reasons
+                    .entry(index)
+                    .or_insert(PruneReason::MaxJobsPerProject);
+            }
+        }
+    }
+
+    let storage_bytes: u64 = sizes.iter().sum();
+    if let Some(max_bytes) = retention.max_storage_bytes {
+        let mut remaining = storage_bytes - reasons.keys().map(|index| sizes[*index]).sum::<u64>();
+        for index in (0..candidates.len()).rev() {
+            if remaining <= max_bytes {
+                break;
+            }
+            if !protected.contains(&index) && !reasons.contains_key(&index) {
+                reasons.insert(index, PruneReason::MaxStorage);
+                remaining -= sizes[index];
+            }
+        }
+    }
+
+    let mut report = RetentionReport {
+        storage_bytes,
+        ..RetentionReport::default()
+    };
+    for (index, job) in candidates.into_iter().enumerate() {
+        if let Some(reason) = reasons.get(&index) {
+            report.freed_bytes += sizes[index];
+            report.jobs.push(PrunableJob {
+                id: job.id,
+                project_id: job.project_id,
+                status: job.status,
+                created_at: job.created_at,
+                reason: *reason,
+                bytes: sizes[index],
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Löscht die Jobs aus dem Report samt Logs und Artefakten. Wurde ein Job
+/// inzwischen angepinnt, bleibt er erhalten. Liefert die Anzahl gelöschter Jobs.
+pub async fn apply(
+    db_pool: &DbPool,
+    config: &ServerConfig,
+    report: &RetentionReport,
+) -> Result<usize> {
+    let mut deleted = 0;
+    for job in &report.jobs {
+        let rows_affected = with_db!(db_pool, |pool| {
+            sqlx::query("DELETE FROM jobs WHERE id = $1 AND NOT pinned")
+                .bind(&job.id)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        if rows_affected > 0 {
+            remove_job_files(config, &job.id);
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Probelauf: zeigt, was die Aufbewahrungsregeln jetzt löschen würden.
+pub async fn preview_retention(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<RetentionReport>> {
+    auth.require_scope(Scope::Admin)?;
+    if !state.config.retention.is_enabled() {
+        return Ok(Json(RetentionReport::default()));
+    }
+    Ok(Json(plan(&state.db_pool, &state.config).await?))
+}
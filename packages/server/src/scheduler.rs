@@ -0,0 +1,194 @@
+use crate::agent_metrics;
+use crate::config::SchedulingConfig;
+use crate::db::DbPool;
+use crate::grpc_server::runner::{
+    server_command::Payload as CommandPayload, RunJob, ServerCommand,
+};
+use crate::http_server::broadcast_job_update;
+use crate::models::{AgentMetrics, Job};
+use crate::otel;
+use crate::{unix_timestamp, with_db, LiveAgentMap, Result, WsClientMap};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Jobs, die pro Durchlauf höchstens verteilt werden.
+const DISPATCH_BATCH_JOBS: i64 = 50;
+
+/// Prüft die Mindestwerte für Festplatte und Arbeitsspeicher gegen einen Messpunkt.
+pub fn meets_thresholds(metrics: &AgentMetrics, config: &SchedulingConfig) -> bool {
+    let enough_disk = config
+        .min_free_disk_bytes
+        .is_none_or(|min| metrics.disk_free_bytes as u64 >= min);
+    let enough_memory = config
+        .min_available_memory_bytes
+        .is_none_or(|min| metrics.memory_available_bytes as u64 >= min);
+    enough_disk && enough_memory
+}
+
+/// Agents, die einen neuen Job bekommen dürfen: verbunden, `online` (nicht
+/// `draining`) und laut letztem Heartbeat über den konfigurierten Mindestwerten.
+/// Sind Mindestwerte gesetzt, fallen Agents ohne Messpunkt heraus.
+pub async fn eligible_agents(
+    db_pool: &DbPool,
+    live_agents: &LiveAgentMap,
+    config: &SchedulingConfig,
+) -> Result<Vec<String>> {
+    let online: Vec<String> = with_db!(db_pool, |pool| {
+        sqlx::query_scalar("SELECT id FROM agents WHERE status = 'online' ORDER BY id")
+            .fetch_all(pool)
+            .await?
+    });
+    let latest = agent_metrics::latest(db_pool).await?;
+    let thresholds_set =
+        config.min_free_disk_bytes.is_some() || config.min_available_memory_bytes.is_some();
+
+    let eligible = online
+        .into_iter()
+        .filter(|agent_id| live_agents.contains_key(agent_id))
+        .filter(
+            |agent_id| match latest.iter().find(|metrics| &metrics.agent_id == agent_id) {
+                Some(metrics) => meets_thresholds(metrics, config),
+                None => !thresholds_set,
+            },
+        )
+        .collect();
+    Ok(eligible)
+}
+
+/// Übergibt einen Job an den Agent: `running` mit Agent und Startzeit. Nur
+/// ein noch wartender Job wird übernommen.
+async fn claim_job(db_pool: &DbPool, job_id: &str, agent_id: &str) -> Result<Option<Job>> {
+    let job = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, Job>(
+            "UPDATE jobs SET status = 'running', agent_id = $1, started_at = $2 \
+             WHERE id = $3 AND status = 'pending' RETURNING *",
+        )
+        .bind(agent_id)
+        .bind(unix_timestamp())
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?
+    });
+    Ok(job)
+}
+
+/// Gibt einen übernommenen Job wieder frei, wenn der Agent nicht erreichbar war.
+async fn release_job(db_pool: &DbPool, job_id: &str, agent_id: &str) -> Result<()> {
+    with_db!(db_pool, |pool| {
+        sqlx::query(
+            "UPDATE jobs SET status = 'pending', agent_id = NULL, started_at = NULL \
+             WHERE id = $1 AND agent_id = $2 AND status = 'running'",
+        )
+        .bind(job_id)
+        .bind(agent_id)
+        .execute(pool)
+        .await?;
+    });
+    Ok(())
+}
+
+/// Freie Plätze der Agents aus [`eligible_agents`], reihum: jeder Agent führt
+/// bis zu `concurrency` Jobs gleichzeitig aus.
+async fn free_slots(
+    db_pool: &DbPool,
+    live_agents: &LiveAgentMap,
+    config: &SchedulingConfig,
+) -> Result<Vec<String>> {
+    let concurrency: HashMap<String, i64> = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, (String, i64)>("SELECT id, concurrency FROM agents")
+            .fetch_all(pool)
+            .await?
+    })
+    .into_iter()
+    .collect();
+    let running: HashMap<String, i64> = with_db!(db_pool, |pool| {
+        sqlx::query_as::<_, (String, i64)>(
+            "SELECT agent_id, COUNT(*) FROM jobs \
+             WHERE status = 'running' AND agent_id IS NOT NULL GROUP BY agent_id",
+        )
+        .fetch_all(pool)
+        .await?
+    })
+    .into_iter()
+    .collect();
+
+    let free: Vec<(String, i64)> = eligible_agents(db_pool, live_agents, config)
+        .await?
+        .into_iter()
+        .map(|agent_id| {
+            let limit = concurrency.get(&agent_id).copied().unwrap_or(1);
+            let free = limit - running.get(&agent_id).copied().unwrap_or(0);
+            (agent_id, free)
+        })
+        .collect();
+    let rounds = free.iter().map(|(_, free)| *free).max().unwrap_or(0);
+    let mut slots = Vec::new();
+    for round in 0..rounds {
+        slots.extend(
+            free.iter()
+                .filter(|(_, free)| *free > round)
+                .map(|(agent_id, _)| agent_id.clone()),
+        );
+    }
+    Ok(slots)
+}
+
+/// Verteilt wartende Jobs, die ältesten zuerst, an Agents aus
+/// [`eligible_agents`] mit freien Plätzen. Liefert die Anzahl der verteilten Jobs.
+pub async fn dispatch_pending(
+    db_pool: &DbPool,
+    live_agents: &LiveAgentMap,
+    ws_clients: &WsClientMap,
+    config: &SchedulingConfig,
+) -> Result<usize> {
+    let pending: Vec<String> = with_db!(db_pool, |pool| {
+        sqlx::query_scalar(
+            "SELECT id FROM jobs WHERE status = 'pending' ORDER BY created_at, id LIMIT $1",
+        )
+        .bind(DISPATCH_BATCH_JOBS)
+        .fetch_all(pool)
+        .await?
+    });
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let mut agents = free_slots(db_pool, live_agents, config).await?.into_iter();
+
+    let mut dispatched = 0;
+    for job_id in pending {
+        let Some(agent_id) = agents.next() else {
+            break;
+        };
+        let Some(tx) = live_agents.get(&agent_id).map(|agent| agent.tx.clone()) else {
+            continue;
+        };
+        let start = SystemTime::now();
+        let Some(job) = claim_job(db_pool, &job_id, &agent_id).await? else {
+            continue;
+        };
+
+        let command = ServerCommand {
+            payload: Some(CommandPayload::Job(RunJob {
+                job_id: job.id.clone(),
+                repository_url: job.repository_url.clone(),
+                commands: job.commands.clone(),
+                timeout_secs: 0,
+            })),
+        };
+        if tx.send(Ok(command)).await.is_err() {
+            warn!(
+                "Agent '{}' nicht erreichbar, Job {} wartet weiter.",
+                agent_id, job.id
+            );
+            release_job(db_pool, &job.id, &agent_id).await?;
+            continue;
+        }
+        info!("Job {} an Agent '{}' übergeben.", job.id, agent_id);
+        otel::record_dispatch(&job, &agent_id, start);
+        broadcast_job_update(db_pool, ws_clients, &job).await;
+        dispatched += 1;
+    }
+    Ok(dispatched)
+}
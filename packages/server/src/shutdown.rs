@@ -0,0 +1,124 @@
+use crate::db::DbPool;
+use crate::grpc_server::runner::{server_command::Payload as CommandPayload, ServerCommand};
+use crate::{with_db, LiveAgentMap, Result};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Nach dieser Zeit sollen sich Agents nach einem Shutdown neu verbinden.
+const RECONNECT_AFTER_SECS: u32 = 5;
+
+/// Gemeinsamer Zustand für das Herunterfahren. Alle Klone sehen dasselbe Signal.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (tx, _) = watch::channel(false);
+        Shutdown { tx: Arc::new(tx) }
+    }
+
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Wartet, bis das Herunterfahren ausgelöst wurde. Der Future ist
+    /// unabhängig von `self`, z.B. für `with_graceful_shutdown`.
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.tx.subscribe();
+        async move {
+            let _ = rx.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    /// Wie [`Shutdown::wait`], kehrt aber erst `grace` nach dem Auslösen zurück.
+    pub async fn wait_grace(&self, grace: Duration) {
+        self.wait().await;
+        tokio::time::sleep(grace).await;
+    }
+}
+
+/// Wartet auf Ctrl+C bzw. SIGTERM.
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Konnte Ctrl+C-Handler nicht installieren: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Konnte SIGTERM-Handler nicht installieren: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Ctrl+C empfangen, fahre herunter..."),
+        _ = terminate => info!("SIGTERM empfangen, fahre herunter..."),
+    }
+}
+
+/// Kündigt allen verbundenen Agents den Shutdown an. Die Agents trennen
+/// daraufhin selbst die Verbindung und melden sich später neu an.
+pub async fn notify_agents(live_agents: &LiveAgentMap, reason: &str) {
+    let agents: Vec<_> = live_agents
+        .iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect();
+
+    for (agent_id, agent) in agents {
+        let command = ServerCommand {
+            payload: Some(CommandPayload::Shutdown(
+                crate::grpc_server::runner::Shutdown {
+                    reason: reason.to_string(),
+                    reconnect_after_secs: RECONNECT_AFTER_SECS,
+                },
+            )),
+        };
+        // Ein hängender Agent darf das Herunterfahren nicht blockieren
+        if let Err(e) = agent.tx.try_send(Ok(command)) {
+            warn!(
+                "Konnte Agent '{}' den Shutdown nicht ankündigen: {}",
+                agent_id, e
+            );
+        }
+    }
+}
+
+/// Markiert alle Agents als offline, die sich beim Herunterfahren nicht
+/// selbst abgemeldet haben. Abgelehnte Agents behalten den Status `incompatible`.
+pub async fn mark_agents_offline(db_pool: &DbPool) -> Result<u64> {
+    let rows_affected = with_db!(db_pool, |pool| {
+        sqlx::query(
+            "UPDATE agents SET status = 'offline' WHERE status NOT IN ('offline', 'incompatible')",
+        )
+        .execute(pool)
+        .await?
+        .rows_affected()
+    });
+    Ok(rows_affected)
+}
@@ -1,10 +1,18 @@
+use crate::config::ServerConfig;
 use crate::db::DbPool;
-use crate::WsClientMap;
+use crate::job_logs::LogStore;
+use crate::liveness::Liveness;
+use crate::shutdown::Shutdown;
+use crate::{LiveAgentMap, WsClientMap};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
+    pub config: Arc<ServerConfig>,
     pub db_pool: DbPool,
     pub ws_clients: WsClientMap,
-    // Füge hier zukünftigen Shared State hinzu (z.B. LiveAgentMap für gRPC)
-    // pub live_agents: LiveAgentMap, // Wenn benötigt
+    pub logs: LogStore,
+    pub live_agents: LiveAgentMap,
+    pub liveness: Liveness,
+    pub shutdown: Shutdown,
 }
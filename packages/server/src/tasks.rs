@@ -1,36 +1,89 @@
+use crate::config::ServerConfig;
 use crate::db::DbPool;
-use sqlx;
+use crate::liveness::Liveness;
+use crate::shutdown::Shutdown;
+use crate::{agent_metrics, retention, scheduler, LiveAgentMap, WsClientMap};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::interval;
+use tracing::{error, info};
 
-pub fn spawn_background_tasks(db_pool: DbPool) {
+/// So oft verteilt der Dispatcher wartende Jobs an freie Agents.
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Schreibt die gesammelten Heartbeats regelmäßig in die Datenbank und löscht
+/// alte Agent-Messpunkte. Offline-Übergänge erkennt der Watchdog von [`Liveness`].
+pub fn spawn_background_tasks(
+    db_pool: DbPool,
+    liveness: Liveness,
+    flush_interval: Duration,
+    agent_metrics_max_age: Duration,
+) {
     tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(30));
+        let mut interval = interval(flush_interval);
         loop {
             interval.tick().await;
-            println!("\n--- Agent Health Check ---");
 
-            let cutoff_time = (std::time::SystemTime::now() - Duration::from_secs(60))
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as i64;
+            if let Err(e) = liveness.flush(&db_pool).await {
+                error!("Fehler beim Speichern der Heartbeats: {}", e);
+            }
 
-            let query_result = sqlx::query(
-                "UPDATE agents SET status = 'offline' WHERE last_heartbeat < ? AND status = 'online'"
-            )
-            .bind(cutoff_time)
-            .execute(&db_pool) // Verwende den übergebenen Pool
-            .await;
+            match agent_metrics::prune(&db_pool, agent_metrics_max_age).await {
+                Ok(0) => {}
+                Ok(deleted) => info!("{} alte Agent-Messpunkte gelöscht.", deleted),
+                Err(e) => error!("Fehler beim Löschen alter Agent-Messpunkte: {}", e),
+            }
+        }
+    });
+}
 
-            match query_result {
-                Ok(result) => {
-                    if result.rows_affected() > 0 {
-                        println!("{} Agents als 'offline' markiert.", result.rows_affected());
-                        // TODO: Broadcast StatsUpdate via WsClientMap?
-                        // Braucht Zugriff auf WsClientMap oder einen separaten Broadcast-Channel.
-                    }
+/// Wendet die Aufbewahrungsregeln regelmäßig an, falls ein Limit konfiguriert ist.
+pub fn spawn_retention_task(db_pool: DbPool, config: Arc<ServerConfig>) {
+    if !config.retention.is_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = interval(config.retention.interval);
+        loop {
+            interval.tick().await;
+            let report = match retention::plan(&db_pool, &config).await {
+                Ok(report) => report,
+                Err(e) => {
+                    error!("Fehler beim Prüfen der Aufbewahrungsregeln: {}", e);
+                    continue;
                 }
-                Err(e) => eprintln!("Fehler beim Health-Check-Update: {}", e),
+            };
+            if report.jobs.is_empty() {
+                continue;
+            }
+            match retention::apply(&db_pool, &config, &report).await {
+                Ok(deleted) => info!(
+                    "Aufbewahrung: {} Jobs gelöscht, {} Bytes freigegeben.",
+                    deleted, report.freed_bytes
+                ),
+                Err(e) => error!("Fehler beim Löschen alter Jobs: {}", e),
+            }
+        }
+    });
+}
+
+/// Verteilt wartende Jobs an freie Agents, bis der Server herunterfährt.
+pub fn spawn_dispatcher(
+    db_pool: DbPool,
+    live_agents: LiveAgentMap,
+    ws_clients: WsClientMap,
+    config: Arc<ServerConfig>,
+    shutdown: Shutdown,
+) {
+    tokio::spawn(async move {
+        let mut interval = interval(DISPATCH_INTERVAL);
+        while !shutdown.is_triggered() {
+            interval.tick().await;
+            if let Err(e) =
+                scheduler::dispatch_pending(&db_pool, &live_agents, &ws_clients, &config.scheduling)
+                    .await
+            {
+                error!("Fehler beim Verteilen der Jobs: {}", e);
             }
         }
     });
@@ -0,0 +1,131 @@
+use crate::{AppError, Result};
+use rcgen::{
+    BasicConstraints, Certificate as RcgenCertificate, CertificateParams, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyUsagePurpose,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Pfade für TLS auf dem gRPC-Port, aus dem `[tls]`-Abschnitt der Konfiguration.
+/// Mit `client_ca` wird zusätzlich von jedem Agent ein Client-Zertifikat
+/// verlangt, das von dieser CA signiert ist (mTLS).
+#[derive(Clone, Debug)]
+pub struct GrpcTlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl GrpcTlsSettings {
+    pub fn server_tls_config(&self) -> Result<ServerTlsConfig> {
+        let cert = read_pem(&self.cert)?;
+        let key = read_pem(&self.key)?;
+        let mut config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(client_ca) = &self.client_ca {
+            config = config.client_ca_root(Certificate::from_pem(read_pem(client_ca)?));
+        }
+        Ok(config)
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| AppError::Tls(format!("could not read {}: {}", path.display(), e)))
+}
+
+/// Liefert den Common Name (CN) des Client-Zertifikats, das ist die Agent-ID.
+/// tonic reicht die Peer-Zertifikate DER-kodiert durch.
+pub fn peer_identity(certs: &[Certificate]) -> Option<String> {
+    let leaf = certs.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(leaf.get_ref()).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+fn tls_error(e: rcgen::Error) -> AppError {
+    AppError::Tls(e.to_string())
+}
+
+fn write_file(path: &Path, content: &str, private: bool) -> Result<()> {
+    let io_error =
+        |e: std::io::Error| AppError::Tls(format!("could not write {}: {}", path.display(), e));
+    fs::write(path, content).map_err(io_error)?;
+
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(io_error)?;
+    }
+    println!("  {}", path.display());
+    Ok(())
+}
+
+/// Erzeugt eine lokale CA sowie Server- und Agent-Zertifikate für die Entwicklung.
+/// Nicht für Produktion gedacht: die Zertifikate sind praktisch unbegrenzt gültig.
+pub fn generate_dev_certs(
+    out_dir: &Path,
+    server_names: &[String],
+    agent_ids: &[String],
+) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .map_err(|e| AppError::Tls(format!("could not create {}: {}", out_dir.display(), e)))?;
+    println!(
+        "Schreibe Entwicklungs-Zertifikate nach {}:",
+        out_dir.display()
+    );
+
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "Deliversphere Dev CA");
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca = RcgenCertificate::from_params(ca_params).map_err(tls_error)?;
+    write_file(
+        &out_dir.join("ca.pem"),
+        &ca.serialize_pem().map_err(tls_error)?,
+        false,
+    )?;
+    write_file(
+        &out_dir.join("ca.key"),
+        &ca.serialize_private_key_pem(),
+        true,
+    )?;
+
+    let mut server_params = CertificateParams::new(server_names.to_vec());
+    server_params
+        .distinguished_name
+        .push(DnType::CommonName, "deliversphere-server");
+    server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server = RcgenCertificate::from_params(server_params).map_err(tls_error)?;
+    write_file(
+        &out_dir.join("server.pem"),
+        &server.serialize_pem_with_signer(&ca).map_err(tls_error)?,
+        false,
+    )?;
+    write_file(
+        &out_dir.join("server.key"),
+        &server.serialize_private_key_pem(),
+        true,
+    )?;
+
+    for agent_id in agent_ids {
+        let mut agent_params = CertificateParams::new(Vec::new());
+        agent_params
+            .distinguished_name
+            .push(DnType::CommonName, agent_id.as_str());
+        agent_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let agent = RcgenCertificate::from_params(agent_params).map_err(tls_error)?;
+        write_file(
+            &out_dir.join(format!("agent-{}.pem", agent_id)),
+            &agent.serialize_pem_with_signer(&ca).map_err(tls_error)?,
+            false,
+        )?;
+        write_file(
+            &out_dir.join(format!("agent-{}.key", agent_id)),
+            &agent.serialize_private_key_pem(),
+            true,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use crate::auth::{issue_token, AuthUser, Scope};
+use crate::models::{ApiToken, User};
+use crate::state::AppState;
+use crate::{unix_timestamp, with_db, AppError, Result};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct CurrentUser {
+    pub user: User,
+    pub token_id: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUser {
+    pub username: String,
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<i64>,
+}
+
+/// Antwort auf die Token-Erstellung; `token` ist danach nicht mehr abrufbar.
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
+
+async fn fetch_user(state: &AppState, user_id: &str) -> Result<User> {
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+    })
+    .ok_or_else(|| AppError::NotFound(format!("user '{}'", user_id)))
+}
+
+async fn create_token_for(
+    state: &AppState,
+    auth: &AuthUser,
+    owner: &User,
+    input: CreateToken,
+) -> Result<(StatusCode, Json<CreatedToken>)> {
+    if input.name.trim().is_empty() {
+        return Err(AppError::BadRequest("'name' must not be empty".to_string()));
+    }
+    if input.scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "'scopes' must contain at least one scope".to_string(),
+        ));
+    }
+    // Ein Token darf keine Rechte vergeben, die der Aufrufer selbst nicht hat
+    for scope in &input.scopes {
+        auth.require_scope(*scope)?;
+    }
+    if input.scopes.contains(&Scope::Admin) && !owner.is_admin {
+        return Err(AppError::Forbidden(
+            "the 'admin' scope requires an admin user".to_string(),
+        ));
+    }
+
+    let expires_at = match input.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(AppError::BadRequest(
+                "'expires_in_days' must be positive".to_string(),
+            ))
+        }
+        Some(days) => Some(unix_timestamp() + days * 24 * 60 * 60),
+        None => None,
+    };
+
+    let (api_token, token) = issue_token(
+        &state.db_pool,
+        &owner.id,
+        input.name.trim(),
+        &input.scopes,
+        expires_at,
+    )
+    .await?;
+    info!(
+        "Token '{}' für Benutzer '{}' erstellt.",
+        api_token.name, owner.username
+    );
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, api_token })))
+}
+
+pub async fn current_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<CurrentUser>> {
+    let user = fetch_user(&state, &auth.user_id).await?;
+    Ok(Json(CurrentUser {
+        user,
+        token_id: auth.token_id,
+        scopes: auth.scopes,
+    }))
+}
+
+pub async fn list_tokens(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Json<Vec<ApiToken>>> {
+    let tokens = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, ApiToken>(
+            "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(&auth.user_id)
+        .fetch_all(pool)
+        .await?
+    });
+    Ok(Json(tokens))
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<CreateToken>,
+) -> Result<(StatusCode, Json<CreatedToken>)> {
+    let owner = fetch_user(&state, &auth.user_id).await?;
+    create_token_for(&state, &auth, &owner, input).await
+}
+
+/// Widerruft einen Token. Eigene Tokens darf jeder widerrufen,
+/// fremde nur mit `admin`-Scope.
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(token_id): Path<String>,
+) -> Result<StatusCode> {
+    let token = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, ApiToken>("SELECT * FROM api_tokens WHERE id = $1")
+            .bind(&token_id)
+            .fetch_optional(pool)
+            .await?
+    })
+    .ok_or_else(|| AppError::NotFound(format!("token '{}'", token_id)))?;
+
+    if token.user_id != auth.user_id {
+        auth.require_scope(Scope::Admin)?;
+    }
+
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query("UPDATE api_tokens SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL")
+            .bind(unix_timestamp())
+            .bind(&token_id)
+            .execute(pool)
+            .await?;
+    });
+    info!("Token {} widerrufen.", token_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_users(State(state): State<AppState>, auth: AuthUser) -> Result<Json<Vec<User>>> {
+    auth.require_scope(Scope::Admin)?;
+    let users = with_db!(&state.db_pool, |pool| {
+        sqlx::query_as::<_, User>("SELECT * FROM users ORDER BY username")
+            .fetch_all(pool)
+            .await?
+    });
+    Ok(Json(users))
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<CreateUser>,
+) -> Result<(StatusCode, Json<User>)> {
+    auth.require_scope(Scope::Admin)?;
+    if input.username.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "'username' must not be empty".to_string(),
+        ));
+    }
+
+    let user_id = Uuid::new_v4().to_string();
+    with_db!(&state.db_pool, |pool| {
+        sqlx::query("INSERT INTO users (id, username, is_admin) VALUES ($1, $2, $3)")
+            .bind(&user_id)
+            .bind(input.username.trim())
+            .bind(input.is_admin)
+            .execute(pool)
+            .await?;
+    });
+
+    let user = fetch_user(&state, &user_id).await?;
+    info!("Benutzer '{}' angelegt.", user.username);
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// Stellt einem anderen Benutzer einen Token aus, z.B. für den ersten Login.
+pub async fn create_user_token(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<String>,
+    Json(input): Json<CreateToken>,
+) -> Result<(StatusCode, Json<CreatedToken>)> {
+    auth.require_scope(Scope::Admin)?;
+    let owner = fetch_user(&state, &user_id).await?;
+    create_token_for(&state, &auth, &owner, input).await
+}
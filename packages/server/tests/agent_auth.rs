@@ -0,0 +1,134 @@
+mod common;
+
+use common::{bearer, create_agent, enrollment_token, TestDb};
+use server::agent_auth;
+use server::AppError;
+use server::LiveAgentMap;
+use tonic::metadata::MetadataMap;
+
+#[tokio::test]
+async fn enrollment_cannot_take_over_an_existing_agent() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let first = enrollment_token(pool, None).await;
+        let credential = agent_auth::enroll_agent(pool, &first, "agent-1")
+            .await
+            .unwrap();
+
+        // Ein zweiter, ungebundener Token übernimmt die ID nicht ...
+        let second = enrollment_token(pool, None).await;
+        let result = agent_auth::enroll_agent(pool, &second, "agent-1").await;
+        assert!(
+            matches!(result, Err(AppError::Forbidden(_))),
+            "{}",
+            db.backend()
+        );
+        let agent_id = agent_auth::authenticate_agent(pool, &bearer(&credential))
+            .await
+            .unwrap();
+        assert_eq!(agent_id, "agent-1");
+        // ... und bleibt für einen neuen Agent gültig.
+        agent_auth::enroll_agent(pool, &second, "agent-2")
+            .await
+            .unwrap();
+
+        // Ein gebundener Token gilt nur für seinen Agent.
+        let bound = enrollment_token(pool, Some("agent-1")).await;
+        let result = agent_auth::enroll_agent(pool, &bound, "agent-3").await;
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        let replaced = agent_auth::enroll_agent(pool, &bound, "agent-1")
+            .await
+            .unwrap();
+        assert!(agent_auth::authenticate_agent(pool, &bearer(&credential))
+            .await
+            .is_err());
+        assert_eq!(
+            agent_auth::authenticate_agent(pool, &bearer(&replaced))
+                .await
+                .unwrap(),
+            "agent-1"
+        );
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn revocation_blocks_certificate_only_agents() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let live_agents = LiveAgentMap::default();
+        create_agent(pool, "cert-agent", "offline").await;
+        let identity = || Some("cert-agent".to_string());
+
+        let agent_id = agent_auth::resolve_agent_identity(pool, &MetadataMap::new(), identity())
+            .await
+            .unwrap();
+        assert_eq!(agent_id, "cert-agent");
+
+        agent_auth::revoke_agent_credential(pool, &live_agents, "cert-agent")
+            .await
+            .unwrap();
+        let result =
+            agent_auth::resolve_agent_identity(pool, &MetadataMap::new(), identity()).await;
+        assert!(
+            matches!(result, Err(AppError::Unauthorized(_))),
+            "{}",
+            db.backend()
+        );
+        assert!(matches!(
+            agent_auth::revoke_agent_credential(pool, &live_agents, "cert-agent").await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            agent_auth::revoke_agent_credential(pool, &live_agents, "unknown").await,
+            Err(AppError::NotFound(_))
+        ));
+
+        // Erneutes Enrollment durch einen Admin hebt den Widerruf auf.
+        let bound = enrollment_token(pool, Some("cert-agent")).await;
+        let credential = agent_auth::enroll_agent(pool, &bound, "cert-agent")
+            .await
+            .unwrap();
+        assert!(
+            agent_auth::resolve_agent_identity(pool, &bearer(&credential), identity())
+                .await
+                .is_ok()
+        );
+        assert!(
+            agent_auth::resolve_agent_identity(pool, &MetadataMap::new(), identity())
+                .await
+                .is_ok()
+        );
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn revoking_a_credential_blocks_both_login_paths() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let token = enrollment_token(pool, None).await;
+        let credential = agent_auth::enroll_agent(pool, &token, "agent-1")
+            .await
+            .unwrap();
+        agent_auth::revoke_agent_credential(pool, &LiveAgentMap::default(), "agent-1")
+            .await
+            .unwrap();
+
+        assert!(agent_auth::authenticate_agent(pool, &bearer(&credential))
+            .await
+            .is_err());
+        let result = agent_auth::resolve_agent_identity(
+            pool,
+            &MetadataMap::new(),
+            Some("agent-1".to_string()),
+        )
+        .await;
+        assert!(
+            matches!(result, Err(AppError::Unauthorized(_))),
+            "{}",
+            db.backend()
+        );
+        db.close().await;
+    }
+}
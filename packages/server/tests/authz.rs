@@ -0,0 +1,64 @@
+mod common;
+
+use common::{add_member, create_project, create_user, TestDb};
+use server::authz::{self, Action, Role};
+use server::AppError;
+
+#[tokio::test]
+async fn authorize_project_uses_the_membership_of_the_project() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let project = create_project(pool, "alpha").await;
+        let other = create_project(pool, "beta").await;
+        let developer = create_user(pool, "dev", false).await;
+        let outsider = create_user(pool, "outsider", false).await;
+        let admin = create_user(pool, "root", true).await;
+        add_member(pool, &project, &developer, Role::Developer).await;
+
+        assert!(
+            authz::authorize_project(pool, &developer, &project, Action::ViewProject)
+                .await
+                .is_ok()
+        );
+        assert!(
+            authz::authorize_project(pool, &developer, &project, Action::TriggerJob)
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            authz::authorize_project(pool, &developer, &project, Action::PinJob).await,
+            Err(AppError::Forbidden(_))
+        ));
+        // Die Rolle gilt nur im eigenen Projekt.
+        assert!(matches!(
+            authz::authorize_project(pool, &developer, &other, Action::ViewProject).await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(matches!(
+            authz::authorize_project(pool, &outsider, &project, Action::ViewProject).await,
+            Err(AppError::Forbidden(_))
+        ));
+        assert!(
+            authz::authorize_project(pool, &admin, &other, Action::DeleteProject)
+                .await
+                .is_ok()
+        );
+
+        // Jobs ohne Projekt sehen nur globale Admins.
+        assert!(
+            authz::authorize_job(pool, &developer, None, Action::ViewProject)
+                .await
+                .is_err()
+        );
+        assert!(
+            authz::authorize_job(pool, &admin, None, Action::ViewProject)
+                .await
+                .is_ok()
+        );
+
+        let roles = authz::project_roles(pool, &project).await.unwrap();
+        assert_eq!(roles.len(), 1, "{}", db.backend());
+        assert_eq!(roles.get(&developer.user_id), Some(&Role::Developer));
+        db.close().await;
+    }
+}
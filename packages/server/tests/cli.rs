@@ -0,0 +1,62 @@
+use std::fs;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+/// Startet `server check-config` ohne geerbte Env-Vars im Verzeichnis `dir`.
+fn check_config(dir: &TempDir, config: &str, env: &[(&str, &str)]) -> Output {
+    fs::write(dir.path().join("deliversphere.toml"), config).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg("check-config")
+        .current_dir(dir.path())
+        .env_clear()
+        .envs(env.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn check_config_reports_the_resolved_config() {
+    let dir = TempDir::new().unwrap();
+    let output = check_config(
+        &dir,
+        r#"
+        [server]
+        http_addr = "127.0.0.1:4000"
+
+        [database]
+        url = "postgres://ci:secret@db:5432/ci"
+
+        [retention]
+        max_storage_mb = 512
+        "#,
+        &[("SERVER_HTTP_ADDR", "127.0.0.1:5000")],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Konfiguration ist gültig"), "{}", stdout);
+    // Env-Vars gehen vor der Datei
+    assert!(stdout.contains("127.0.0.1:5000"), "{}", stdout);
+    assert!(stdout.contains("max_storage_mb=512"), "{}", stdout);
+    assert!(!stdout.contains("secret"), "{}", stdout);
+}
+
+#[test]
+fn check_config_names_the_invalid_key() {
+    let dir = TempDir::new().unwrap();
+    let output = check_config(
+        &dir,
+        r#"
+        [database]
+        url = "sqlite:ci.db"
+        "#,
+        &[("SERVER_RETENTION_MAX_STORAGE_MB", &u64::MAX.to_string())],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("retention.max_storage_mb"), "{}", stderr);
+    assert!(!dir.path().join("ci.db").exists());
+}
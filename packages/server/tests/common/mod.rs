@@ -0,0 +1,348 @@
+//! Gemeinsame Hilfen der Integrationstests: frisch migrierte Datenbanken
+//! für beide Backends und Testdaten.
+//!
+//! SQLite läuft immer in einer temporären Datei. Postgres nur, wenn
+//! `TEST_POSTGRES_URL` auf eine erreichbare Datenbank zeigt, z.B.
+//! `postgres://postgres@127.0.0.1:5432/postgres`; jeder Test legt sich
+//! daneben eine eigene Datenbank an und löscht sie am Ende wieder.
+
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use http_body_util::BodyExt;
+use server::agent_auth;
+use server::auth::{self, AuthUser, Scope};
+use server::authz::Role;
+use server::config::{
+    DatabaseConfig, Features, LogFormat, LoggingConfig, OtelConfig, RetentionConfig,
+    SchedulingConfig, ServerConfig,
+};
+use server::db::{self, DbPool};
+use server::grpc_server::runner::ServerCommand;
+use server::job_logs::LogStore;
+use server::liveness::Liveness;
+use server::models::Job;
+use server::shutdown::Shutdown;
+use server::state::AppState;
+use server::{migrations, protocol, with_db, LiveAgent, LiveAgentMap, WsClientMap};
+use sqlx::{Connection, PgConnection};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+use tonic::metadata::MetadataMap;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+pub struct TestDb {
+    pub pool: DbPool,
+    /// Für die SQLite-Datei sowie Logs und Artefakte, wird mit dem Test gelöscht.
+    dir: TempDir,
+    /// Verbindung zur Ausgangsdatenbank und Name der Testdatenbank (Postgres).
+    postgres: Option<(String, String)>,
+}
+
+impl TestDb {
+    pub async fn sqlite() -> TestDb {
+        let dir = TempDir::new().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        TestDb::migrated(&url, dir, None).await
+    }
+
+    pub async fn postgres() -> Option<TestDb> {
+        let admin_url = std::env::var("TEST_POSTGRES_URL").ok()?;
+        let name = format!("ds_test_{}", Uuid::new_v4().simple());
+        let mut conn = PgConnection::connect(&admin_url).await.unwrap();
+        sqlx::query(&format!("CREATE DATABASE {}", name))
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let (base, _) = admin_url.rsplit_once('/').unwrap();
+        let url = format!("{}/{}", base, name);
+        let dir = TempDir::new().unwrap();
+        Some(TestDb::migrated(&url, dir, Some((admin_url, name))).await)
+    }
+
+    async fn migrated(url: &str, dir: TempDir, postgres: Option<(String, String)>) -> TestDb {
+        let pool = db::init_pool(&DatabaseConfig {
+            url: url.to_string(),
+            max_connections: 5,
+        })
+        .await
+        .unwrap();
+        migrations::run_pending(&pool).await.unwrap();
+        TestDb {
+            pool,
+            dir,
+            postgres,
+        }
+    }
+
+    /// Alle verfügbaren Backends, SQLite zuerst.
+    pub async fn all() -> Vec<TestDb> {
+        let mut dbs = vec![TestDb::sqlite().await];
+        dbs.extend(TestDb::postgres().await);
+        dbs
+    }
+
+    pub fn backend(&self) -> &'static str {
+        self.pool.backend().name()
+    }
+
+    /// Zustand des HTTP-Servers auf dieser Datenbank, mit Default-Konfiguration.
+    pub fn app_state(&self) -> AppState {
+        let data_dir = self.dir.path().join("data");
+        let config = Arc::new(ServerConfig {
+            http_addr: "127.0.0.1:0".parse().unwrap(),
+            grpc_addr: "127.0.0.1:0".parse().unwrap(),
+            database: DatabaseConfig {
+                url: String::new(),
+                max_connections: 5,
+            },
+            heartbeat_interval: Duration::from_secs(10),
+            heartbeat_flush_interval: Duration::from_secs(15),
+            agent_offline_after: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(1),
+            log_dir: data_dir.join("logs"),
+            data_dir,
+            tls: None,
+            retention: RetentionConfig {
+                interval: Duration::from_secs(3600),
+                max_age: None,
+                max_jobs_per_project: None,
+                max_storage_bytes: None,
+                keep_last_successful: true,
+                agent_metrics_max_age: Duration::from_secs(3600),
+            },
+            scheduling: SchedulingConfig::default(),
+            features: Features {
+                bootstrap_admin: false,
+                websocket: true,
+                metrics: true,
+            },
+            logging: LoggingConfig {
+                format: LogFormat::Pretty,
+                level: "info".to_string(),
+            },
+            otel: OtelConfig {
+                endpoint: None,
+                service_name: "deliversphere-test".to_string(),
+            },
+            config_file: None,
+        });
+        AppState {
+            logs: LogStore::new(config.clone(), self.pool.clone()),
+            liveness: Liveness::new(config.heartbeat_interval, config.agent_offline_after),
+            config,
+            db_pool: self.pool.clone(),
+            ws_clients: WsClientMap::default(),
+            live_agents: LiveAgentMap::default(),
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    pub async fn close(self) {
+        self.pool.close().await;
+        if let Some((admin_url, name)) = self.postgres {
+            let mut conn = PgConnection::connect(&admin_url).await.unwrap();
+            sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", name))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        drop(self.dir);
+    }
+}
+
+pub async fn create_user(pool: &DbPool, username: &str, is_admin: bool) -> AuthUser {
+    let user_id = Uuid::new_v4().to_string();
+    with_db!(pool, |pool| {
+        sqlx::query("INSERT INTO users (id, username, is_admin) VALUES ($1, $2, $3)")
+            .bind(&user_id)
+            .bind(username)
+            .bind(is_admin)
+            .execute(pool)
+            .await
+            .unwrap();
+    });
+    AuthUser {
+        user_id,
+        username: username.to_string(),
+        is_admin,
+        token_id: Uuid::new_v4().to_string(),
+        scopes: vec![Scope::Admin],
+    }
+}
+
+pub async fn create_project(pool: &DbPool, name: &str) -> String {
+    let project_id = Uuid::new_v4().to_string();
+    with_db!(pool, |pool| {
+        sqlx::query("INSERT INTO projects (id, name, repository_url) VALUES ($1, $2, $3)")
+            .bind(&project_id)
+            .bind(name)
+            .bind(format!("https://git.example.com/{}.git", name))
+            .execute(pool)
+            .await
+            .unwrap();
+    });
+    project_id
+}
+
+pub async fn add_member(pool: &DbPool, project_id: &str, user: &AuthUser, role: Role) {
+    with_db!(pool, |pool| {
+        sqlx::query("INSERT INTO project_members (project_id, user_id, role) VALUES ($1, $2, $3)")
+            .bind(project_id)
+            .bind(&user.user_id)
+            .bind(role.as_str())
+            .execute(pool)
+            .await
+            .unwrap();
+    });
+}
+
+pub async fn enrollment_token(pool: &DbPool, agent_id: Option<&str>) -> String {
+    let admin = create_user(pool, &format!("admin-{}", Uuid::new_v4()), true).await;
+    let expires_at = server::unix_timestamp() + 3600;
+    let (_, token) =
+        agent_auth::create_enrollment_token(pool, &admin.user_id, expires_at, agent_id)
+            .await
+            .unwrap();
+    token
+}
+
+/// gRPC-Metadaten mit `authorization: Bearer <credential>`.
+pub fn bearer(credential: &str) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    metadata.insert(
+        "authorization",
+        format!("Bearer {}", credential).parse().unwrap(),
+    );
+    metadata
+}
+
+/// Ein Agent, wie ihn die Registrierung anlegt.
+pub async fn create_agent(pool: &DbPool, agent_id: &str, status: &str) {
+    with_db!(pool, |pool| {
+        sqlx::query(
+            "INSERT INTO agents (id, hostname, status, last_heartbeat) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(agent_id)
+        .bind(format!("{}.local", agent_id))
+        .bind(status)
+        .bind(server::unix_timestamp())
+        .execute(pool)
+        .await
+        .unwrap();
+    });
+}
+
+/// Ein API-Token mit allen Scopes für `user`.
+pub async fn api_token(pool: &DbPool, user: &AuthUser) -> String {
+    let (_, token) = auth::issue_token(pool, &user.user_id, "test", &[Scope::Admin], None)
+        .await
+        .unwrap();
+    token
+}
+
+/// Schickt einen Request an den Router und liefert Status und Body.
+pub async fn send(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, String) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token));
+    let body = match body {
+        Some(body) => {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let response = router
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Wie [`send`], erwartet `expected` und eine JSON-Antwort.
+pub async fn send_json(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+    expected: StatusCode,
+) -> serde_json::Value {
+    let (status, body) = send(router, method.clone(), uri, token, body).await;
+    assert_eq!(status, expected, "{} {}: {}", method, uri, body);
+    if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_str(&body).unwrap()
+    }
+}
+
+/// Ein wartender Job mit einem Schritt je Befehl, wie ihn ein Rerun anlegt.
+pub async fn create_job(pool: &DbPool, project_id: Option<&str>, commands: &[&str]) -> String {
+    let job_id = Uuid::new_v4().to_string();
+    let commands: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+    with_db!(pool, |pool| {
+        sqlx::query(
+            "INSERT INTO jobs (id, status, repository_url, commands, project_id) \
+             VALUES ($1, 'pending', $2, $3, $4)",
+        )
+        .bind(&job_id)
+        .bind("https://git.example.com/repo.git")
+        .bind(sqlx::types::Json(&commands))
+        .bind(project_id)
+        .execute(pool)
+        .await
+        .unwrap();
+        for (index, command) in commands.iter().enumerate() {
+            sqlx::query("INSERT INTO job_steps (job_id, step_index, command) VALUES ($1, $2, $3)")
+                .bind(&job_id)
+                .bind(index as i64)
+                .bind(command)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+    });
+    job_id
+}
+
+/// Ein verbundener Agent; über den Receiver kommen die Befehle des Servers an.
+pub fn connect_agent(live_agents: &LiveAgentMap, agent_id: &str) -> CommandReceiver {
+    let (tx, rx) = mpsc::channel(16);
+    live_agents.insert(
+        agent_id.to_string(),
+        LiveAgent {
+            tx,
+            protocol_version: protocol::CURRENT_VERSION,
+        },
+    );
+    rx
+}
+
+pub type CommandReceiver = mpsc::Receiver<Result<ServerCommand, tonic::Status>>;
+
+pub async fn fetch_job(pool: &DbPool, job_id: &str) -> Job {
+    with_db!(pool, |pool| {
+        sqlx::query_as::<_, Job>("SELECT * FROM jobs WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    })
+}
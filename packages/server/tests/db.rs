@@ -0,0 +1,178 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{add_member, api_token, create_job, create_user, fetch_job, send_json, TestDb};
+use serde_json::json;
+use server::authz::Role;
+use server::http_server::create_router;
+use server::{migrations, with_db};
+
+#[tokio::test]
+async fn migrations_revert_and_reapply() {
+    for db in TestDb::all().await {
+        let status = migrations::status(&db.pool).await.unwrap();
+        let total = status.migrations.len();
+        assert!(total > 0);
+        assert_eq!(status.pending(), 0, "{}", db.backend());
+        assert!(status.unknown_versions.is_empty());
+
+        let reverted = migrations::revert(&db.pool, total).await.unwrap();
+        assert_eq!(reverted.len(), total, "{}", db.backend());
+        assert!(reverted.windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(migrations::status(&db.pool).await.unwrap().pending(), total);
+
+        assert_eq!(migrations::run_pending(&db.pool).await.unwrap(), total);
+        assert_eq!(migrations::status(&db.pool).await.unwrap().pending(), 0);
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn project_crud_smoke() {
+    for db in TestDb::all().await {
+        let router = create_router(db.app_state());
+        let owner = create_user(&db.pool, "owner", false).await;
+        let token = api_token(&db.pool, &owner).await;
+        let viewer = create_user(&db.pool, "viewer", false).await;
+        let viewer_token = api_token(&db.pool, &viewer).await;
+
+        let project = send_json(
+            &router,
+            Method::POST,
+            "/api/projects",
+            &token,
+            Some(json!({"name": "alpha", "repository_url": "https://git.example.com/alpha.git"})),
+            StatusCode::CREATED,
+        )
+        .await;
+        let id = project["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/projects/{}", id);
+        assert_eq!(project["default_branch"], "main", "{}", db.backend());
+
+        let updated = send_json(
+            &router,
+            Method::PUT,
+            &uri,
+            &token,
+            Some(json!({"name": "beta", "settings": {"timeout": 60}})),
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(updated["name"], "beta");
+        assert_eq!(updated["settings"]["timeout"], 60);
+
+        // Die Liste enthält nur Projekte, in denen der Benutzer Mitglied ist.
+        let listed = send_json(
+            &router,
+            Method::GET,
+            "/api/projects",
+            &viewer_token,
+            None,
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(listed, json!([]));
+        add_member(&db.pool, &id, &viewer, Role::Viewer).await;
+        let listed = send_json(
+            &router,
+            Method::GET,
+            "/api/projects",
+            &viewer_token,
+            None,
+            StatusCode::OK,
+        )
+        .await;
+        assert_eq!(listed[0]["id"], id.as_str());
+        send_json(
+            &router,
+            Method::DELETE,
+            &uri,
+            &viewer_token,
+            None,
+            StatusCode::FORBIDDEN,
+        )
+        .await;
+
+        send_json(
+            &router,
+            Method::DELETE,
+            &uri,
+            &token,
+            None,
+            StatusCode::NO_CONTENT,
+        )
+        .await;
+        send_json(
+            &router,
+            Method::GET,
+            &uri,
+            &token,
+            None,
+            StatusCode::NOT_FOUND,
+        )
+        .await;
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn deleting_a_project_keeps_its_jobs() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let router = create_router(db.app_state());
+        let owner = create_user(pool, "owner", false).await;
+        let token = api_token(pool, &owner).await;
+        let project = send_json(
+            &router,
+            Method::POST,
+            "/api/projects",
+            &token,
+            Some(json!({"name": "alpha", "repository_url": "https://git.example.com/alpha.git"})),
+            StatusCode::CREATED,
+        )
+        .await;
+        let project_id = project["id"].as_str().unwrap();
+
+        // Ein Job eines Pipeline-Laufs und einer direkt im Projekt
+        let in_pipeline = create_job(pool, Some(project_id), &["make"]).await;
+        let direct = create_job(pool, Some(project_id), &["make test"]).await;
+        with_db!(pool, |pool| {
+            sqlx::query(
+                "INSERT INTO pipelines (id, project_id, branch, pipeline_path) \
+                 VALUES ('p1', $1, 'main', '.deliversphere.yml')",
+            )
+            .bind(project_id)
+            .execute(pool)
+            .await
+            .unwrap();
+            sqlx::query("UPDATE jobs SET pipeline_id = 'p1' WHERE id = $1")
+                .bind(&in_pipeline)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+
+        send_json(
+            &router,
+            Method::DELETE,
+            &format!("/api/projects/{}", project_id),
+            &token,
+            None,
+            StatusCode::NO_CONTENT,
+        )
+        .await;
+        for job_id in [&in_pipeline, &direct] {
+            let job = fetch_job(pool, job_id).await;
+            assert_eq!(job.project_id, None, "{}", db.backend());
+            assert_eq!(job.pipeline_id, None, "{}", db.backend());
+        }
+        let pipelines: i64 = with_db!(pool, |pool| {
+            sqlx::query_scalar("SELECT COUNT(*) FROM pipelines")
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        });
+        assert_eq!(pipelines, 0);
+        db.close().await;
+    }
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use common::{
+    add_member, api_token, create_agent, create_job, create_project, create_user, send_json, TestDb,
+};
+use serde_json::Value;
+use server::authz::Role;
+use server::grpc_server::runner::LogMessage;
+use server::http_server::create_router;
+use server::jobs::{self, JobOutcome};
+use server::log_search;
+use server::state::AppState;
+use server::{unix_timestamp, with_db};
+use std::time::Duration;
+
+/// Ein abgeschlossener Job, dessen Log der Agent `a1` über den LogStore geschrieben hat.
+async fn finished_job(state: &AppState, project_id: Option<&str>, output: &str) -> String {
+    let pool = &state.db_pool;
+    let job_id = create_job(pool, project_id, &["make"]).await;
+    with_db!(pool, |pool| {
+        sqlx::query(
+            "UPDATE jobs SET status = 'running', agent_id = 'a1', started_at = $1 WHERE id = $2",
+        )
+        .bind(unix_timestamp())
+        .bind(&job_id)
+        .execute(pool)
+        .await
+        .unwrap();
+    });
+    let message = LogMessage {
+        job_id: job_id.clone(),
+        output: output.to_string(),
+        ..Default::default()
+    };
+    state.logs.append("a1", &message).await.unwrap();
+    let outcome = JobOutcome {
+        success: true,
+        failure_reason: None,
+        failure_message: None,
+    };
+    jobs::finish_job(
+        pool,
+        &state.ws_clients,
+        &state.logs,
+        "a1",
+        &job_id,
+        &outcome,
+    )
+    .await
+    .unwrap()
+    .expect("laufender Job wird abgeschlossen");
+    job_id
+}
+
+async fn wait_indexed(state: &AppState, job_ids: &[&str]) {
+    for _ in 0..200 {
+        let mut indexed = true;
+        for job_id in job_ids {
+            indexed &= with_db!(&state.db_pool, |pool| {
+                sqlx::query_scalar::<_, bool>("SELECT log_indexed FROM jobs WHERE id = $1")
+                    .bind(job_id)
+                    .fetch_one(pool)
+                    .await
+                    .unwrap()
+            });
+        }
+        if indexed {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Logs nicht indexiert");
+}
+
+async fn search(router: &Router, token: &str, query: &str) -> Vec<Value> {
+    let uri = format!("/api/search/logs?{}", query);
+    let body = send_json(router, Method::GET, &uri, token, None, StatusCode::OK).await;
+    body.as_array().unwrap().clone()
+}
+
+fn lines(matches: &[Value]) -> Vec<&str> {
+    matches
+        .iter()
+        .map(|m| m["output"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn both_backends_match_the_same_lines() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let admin = create_user(&db.pool, "admin", true).await;
+        let token = api_token(&db.pool, &admin).await;
+        create_agent(&db.pool, "a1", "online").await;
+        log_search::spawn_log_indexer(state.db_pool.clone(), state.logs.clone());
+
+        let job_id = finished_job(
+            &state,
+            None,
+            "\x1b[1mCompiling\x1b[0m api\n\
+             error: Connection refused by db:5432\n\
+             warning: connection reset\n\
+             retrying in 5s\n\
+             done",
+        )
+        .await;
+        wait_indexed(&state, &[&job_id]).await;
+
+        // Alle Begriffe müssen vorkommen, in beliebiger Reihenfolge und Schreibweise
+        for q in ["connection%20refused", "REFUSED%20connection"] {
+            let matches = search(&router, &token, &format!("q={}", q)).await;
+            assert_eq!(
+                lines(&matches),
+                ["error: Connection refused by db:5432"],
+                "{} ({})",
+                q,
+                db.backend()
+            );
+        }
+        // Ganze Wörter, keine Präfixe
+        assert!(search(&router, &token, "q=connect").await.is_empty());
+        // ANSI-Sequenzen zerteilen keine Wörter
+        assert_eq!(
+            lines(&search(&router, &token, "q=compiling").await),
+            ["Compiling api"]
+        );
+        // Operatoren der Suchsyntax sind gewöhnliche Begriffe
+        assert!(search(&router, &token, "q=refused%20OR%20%22")
+            .await
+            .is_empty());
+        assert!(search(&router, &token, "q=refused%20NOT%20reset")
+            .await
+            .is_empty());
+
+        // Umgebung des Treffers, am Anfang des Logs abgeschnitten
+        let matches = search(&router, &token, "q=refused&context=2").await;
+        assert_eq!(matches[0]["line"], 1);
+        assert_eq!(matches[0]["before"], serde_json::json!(["Compiling api"]));
+        assert_eq!(
+            matches[0]["after"],
+            serde_json::json!(["warning: connection reset", "retrying in 5s"])
+        );
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn search_only_returns_jobs_of_member_projects() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        create_agent(pool, "a1", "online").await;
+        log_search::spawn_log_indexer(state.db_pool.clone(), state.logs.clone());
+
+        let alpha = create_project(pool, "alpha").await;
+        let beta = create_project(pool, "beta").await;
+        let in_alpha = finished_job(&state, Some(&alpha), "panic in alpha").await;
+        let in_beta = finished_job(&state, Some(&beta), "panic in beta").await;
+        let unassigned = finished_job(&state, None, "panic without project").await;
+        wait_indexed(&state, &[&in_alpha, &in_beta, &unassigned]).await;
+
+        let admin = create_user(pool, "admin", true).await;
+        let viewer = create_user(pool, "viewer", false).await;
+        let outsider = create_user(pool, "outsider", false).await;
+        add_member(pool, &alpha, &viewer, Role::Viewer).await;
+
+        let job_ids = |matches: Vec<Value>| -> Vec<String> {
+            let mut ids: Vec<String> = matches
+                .iter()
+                .map(|m| m["job_id"].as_str().unwrap().to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let mut all = vec![in_alpha.clone(), in_beta.clone(), unassigned.clone()];
+        all.sort();
+        let admin_token = api_token(pool, &admin).await;
+        assert_eq!(
+            job_ids(search(&router, &admin_token, "q=panic").await),
+            all,
+            "{}",
+            db.backend()
+        );
+        let viewer_token = api_token(pool, &viewer).await;
+        assert_eq!(
+            job_ids(search(&router, &viewer_token, "q=panic").await),
+            vec![in_alpha.clone()]
+        );
+        // Auch ein ausdrücklich angefragtes fremdes Projekt bleibt verborgen
+        assert!(
+            search(&router, &viewer_token, &format!("q=panic&project={}", beta))
+                .await
+                .is_empty()
+        );
+        let outsider_token = api_token(pool, &outsider).await;
+        assert!(search(&router, &outsider_token, "q=panic").await.is_empty());
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn unreadable_log_does_not_block_the_indexer() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        let admin = create_user(pool, "admin", true).await;
+        let token = api_token(pool, &admin).await;
+        create_agent(pool, "a1", "online").await;
+
+        // Ein Chunk im Index, dessen Datei fehlt; der Job steht in der Reihenfolge vorne
+        let broken = create_job(pool, None, &["make"]).await;
+        with_db!(pool, |pool| {
+            sqlx::query("UPDATE jobs SET status = 'failed', finished_at = 1 WHERE id = $1")
+                .bind(&broken)
+                .execute(pool)
+                .await
+                .unwrap();
+            sqlx::query(
+                "INSERT INTO job_log_chunks \
+                 (job_id, seq, byte_offset, byte_length, line_offset, line_count) \
+                 VALUES ($1, 0, 0, 10, 0, 1)",
+            )
+            .bind(&broken)
+            .execute(pool)
+            .await
+            .unwrap();
+        });
+        let healthy = finished_job(&state, None, "tests passed").await;
+        log_search::spawn_log_indexer(state.db_pool.clone(), state.logs.clone());
+
+        wait_indexed(&state, &[&broken, &healthy]).await;
+        assert_eq!(
+            lines(&search(&router, &token, "q=passed").await),
+            ["tests passed"],
+            "{}",
+            db.backend()
+        );
+        db.close().await;
+    }
+}
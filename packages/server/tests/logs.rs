@@ -0,0 +1,348 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{
+    api_token, create_job, create_user, enrollment_token, fetch_job, send, send_json, TestDb,
+};
+use server::agent_auth;
+use server::grpc_server::runner::{
+    agent_request::Payload, runner_service_client::RunnerServiceClient,
+    server_command::Payload as CommandPayload, AgentRequest, Heartbeat, JobResult, LogMessage,
+    LogStream, RegisterAck, RegisterAgent, ServerCommand,
+};
+use server::grpc_server::{MyRunnerService, RunnerServiceServer};
+use server::http_server::create_router;
+use server::liveness::Liveness;
+use server::state::AppState;
+use server::{protocol, scheduler};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tonic::transport::server::TcpIncoming;
+use tonic::Streaming;
+
+fn log(job_id: &str, seq: u64, step_index: u32, output: &str) -> AgentRequest {
+    let mut message = LogMessage {
+        job_id: job_id.to_string(),
+        output: output.to_string(),
+        step_index: Some(step_index),
+        timestamp_ms: 1_700_000_000_000 + seq,
+        ..Default::default()
+    };
+    message.set_stream(LogStream::Stdout);
+    AgentRequest {
+        seq,
+        payload: Some(Payload::Log(message)),
+    }
+}
+
+fn result(job_id: &str, seq: u64) -> AgentRequest {
+    AgentRequest {
+        seq,
+        payload: Some(Payload::Result(JobResult {
+            job_id: job_id.to_string(),
+            success: true,
+            ..Default::default()
+        })),
+    }
+}
+
+/// Der gRPC-Server auf einem freien Port.
+async fn serve(state: &AppState) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = MyRunnerService {
+        db_pool: state.db_pool.clone(),
+        live_agents: state.live_agents.clone(),
+        ws_clients: state.ws_clients.clone(),
+        logs: state.logs.clone(),
+        liveness: state.liveness.clone(),
+        shutdown: state.shutdown.clone(),
+    };
+    let server = tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(RunnerServiceServer::new(service))
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap())
+            .await
+            .unwrap();
+    });
+    (addr, server)
+}
+
+/// Registriert den Agent `a1` über einen neuen Stream, auf Wunsch als Resume
+/// mit den Jobs, die noch laufen.
+async fn connect(
+    addr: SocketAddr,
+    credential: &str,
+    resume_session_id: &str,
+    job_ids: &[String],
+) -> (
+    mpsc::Sender<AgentRequest>,
+    Streaming<ServerCommand>,
+    RegisterAck,
+) {
+    let mut client = RunnerServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let (tx, rx) = mpsc::channel(16);
+    tx.send(AgentRequest {
+        seq: 0,
+        payload: Some(Payload::Register(RegisterAgent {
+            agent_id: "a1".to_string(),
+            hostname: "test".to_string(),
+            protocol_version: protocol::CURRENT_VERSION,
+            resume_session_id: resume_session_id.to_string(),
+            job_ids: job_ids.to_vec(),
+            labels: vec!["os=linux".to_string()],
+            concurrency: 2,
+            ..Default::default()
+        })),
+    })
+    .await
+    .unwrap();
+    let mut request = tonic::Request::new(ReceiverStream::new(rx));
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", credential).parse().unwrap(),
+    );
+    let mut inbound = client.communicate(request).await.unwrap().into_inner();
+    let Some(CommandPayload::RegisterAck(ack)) = inbound.next().await.unwrap().unwrap().payload
+    else {
+        panic!("erste Antwort ist kein RegisterAck");
+    };
+    assert!(ack.accepted);
+    (tx, inbound, ack)
+}
+
+async fn dispatch(db: &TestDb, state: &AppState, inbound: &mut Streaming<ServerCommand>) -> String {
+    let job_id = create_job(&db.pool, None, &["make"]).await;
+    let dispatched = scheduler::dispatch_pending(
+        &db.pool,
+        &state.live_agents,
+        &state.ws_clients,
+        &state.config.scheduling,
+    )
+    .await
+    .unwrap();
+    assert_eq!(dispatched, 1, "{}", db.backend());
+    let command = inbound.next().await.unwrap().unwrap();
+    assert!(matches!(command.payload, Some(CommandPayload::Job(run)) if run.job_id == job_id));
+    job_id
+}
+
+async fn wait_finished(db: &TestDb, job_id: &str) {
+    for _ in 0..100 {
+        if fetch_job(&db.pool, job_id).await.status == "success" {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("Job nicht abgeschlossen ({})", db.backend());
+}
+
+#[tokio::test]
+async fn streamed_logs_are_served_over_http() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        let admin = create_user(pool, "admin", true).await;
+        let token = api_token(pool, &admin).await;
+        let enrollment = enrollment_token(pool, None).await;
+        let credential = agent_auth::enroll_agent(pool, &enrollment, "a1")
+            .await
+            .unwrap();
+
+        let (addr, server) = serve(&state).await;
+
+        // Der Agent registriert sich über den gRPC-Stream
+        let (tx, mut inbound, _) = connect(addr, &credential, "", &[]).await;
+        let job_id = dispatch(&db, &state, &mut inbound).await;
+
+        tx.send(log(&job_id, 1, 0, "\x1b[32mcompiling\x1b[0m"))
+            .await
+            .unwrap();
+        tx.send(log(&job_id, 2, 0, "done\nok")).await.unwrap();
+        tx.send(result(&job_id, 3)).await.unwrap();
+        // Der Server verarbeitet die Nachrichten der Reihe nach, das Ergebnis zuletzt
+        wait_finished(&db, &job_id).await;
+
+        let uri = format!("/api/jobs/{}/log", job_id);
+        let (status, raw) = send(&router, Method::GET, &uri, &token, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", raw);
+        assert_eq!(
+            raw,
+            "\x1b[32mcompiling\x1b[0m\ndone\nok\n",
+            "{}",
+            db.backend()
+        );
+        // Labels und Parallelität aus der Registrierung
+        let agents = send_json(
+            &router,
+            Method::GET,
+            "/api/agents",
+            &token,
+            None,
+            StatusCode::OK,
+        )
+        .await;
+        let agent = &agents[0];
+        assert_eq!(agent["id"], "a1");
+        assert_eq!(agent["labels"], serde_json::json!(["os=linux"]));
+        assert_eq!(agent["concurrency"], 2);
+        let (status, plain) = send(
+            &router,
+            Method::GET,
+            &format!("{}?format=plain&offset=1", uri),
+            &token,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(plain, "done\nok\n");
+
+        drop(tx);
+        server.abort();
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn failed_message_is_replayed_once() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        let admin = create_user(pool, "admin", true).await;
+        let token = api_token(pool, &admin).await;
+        let enrollment = enrollment_token(pool, None).await;
+        let credential = agent_auth::enroll_agent(pool, &enrollment, "a1")
+            .await
+            .unwrap();
+        let (addr, server) = serve(&state).await;
+
+        let (tx, mut inbound, ack) = connect(addr, &credential, "", &[]).await;
+        let job_id = dispatch(&db, &state, &mut inbound).await;
+        tx.send(log(&job_id, 1, 0, "one")).await.unwrap();
+        // Der Heartbeat bestätigt die verarbeitete Nachricht
+        tx.send(AgentRequest {
+            seq: 0,
+            payload: Some(Payload::Heartbeat(Heartbeat::default())),
+        })
+        .await
+        .unwrap();
+        let command = inbound.next().await.unwrap().unwrap();
+        assert!(matches!(command.payload, Some(CommandPayload::Ack(ack)) if ack.seq == 1));
+
+        // Ein Verzeichnis anstelle der nächsten Chunk-Datei: das Log von Schritt 1 scheitert
+        let blocker = state.config.job_log_dir(&job_id).join("000001-step1.log");
+        std::fs::create_dir(&blocker).unwrap();
+        tx.send(log(&job_id, 2, 1, "two")).await.unwrap();
+        tx.send(log(&job_id, 3, 0, "three")).await.unwrap();
+        // Der Server beendet den Stream, statt Nachricht 3 zu verarbeiten
+        let end = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(_)) = inbound.next().await {}
+        })
+        .await;
+        assert!(end.is_ok(), "Stream nicht beendet ({})", db.backend());
+        std::fs::remove_dir(&blocker).unwrap();
+
+        // Nach dem Resume sendet der Agent alles ab der bestätigten Nachricht erneut
+        let (tx, _inbound, resumed) = connect(
+            addr,
+            &credential,
+            &ack.session_id,
+            std::slice::from_ref(&job_id),
+        )
+        .await;
+        assert!(resumed.resumed);
+        assert_eq!(resumed.last_seq, 1);
+        tx.send(log(&job_id, 1, 0, "one")).await.unwrap();
+        tx.send(log(&job_id, 2, 1, "two")).await.unwrap();
+        tx.send(log(&job_id, 3, 0, "three")).await.unwrap();
+        tx.send(result(&job_id, 4)).await.unwrap();
+        wait_finished(&db, &job_id).await;
+
+        let uri = format!("/api/jobs/{}/log", job_id);
+        let (status, raw) = send(&router, Method::GET, &uri, &token, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", raw);
+        assert_eq!(raw, "one\ntwo\nthree\n", "{}", db.backend());
+
+        drop(tx);
+        server.abort();
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn remote_drain_survives_a_heartbeat_gap() {
+    for db in TestDb::all().await {
+        let mut state = db.app_state();
+        // Kurze Frist, damit der Watchdog den Agent schnell offline setzt
+        state.liveness = Liveness::new(Duration::from_secs(1), Duration::from_secs(2));
+        state.liveness.spawn_watchdog(
+            db.pool.clone(),
+            state.ws_clients.clone(),
+            state.logs.clone(),
+        );
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        let admin = create_user(pool, "admin", true).await;
+        let token = api_token(pool, &admin).await;
+        let enrollment = enrollment_token(pool, None).await;
+        let credential = agent_auth::enroll_agent(pool, &enrollment, "a1")
+            .await
+            .unwrap();
+        let (addr, server) = serve(&state).await;
+        let (tx, mut inbound, _) = connect(addr, &credential, "", &[]).await;
+
+        let drained = send_json(
+            &router,
+            Method::POST,
+            "/api/agents/a1/drain",
+            &token,
+            None,
+            StatusCode::ACCEPTED,
+        )
+        .await;
+        assert_eq!(drained["status"], "draining");
+        let command = inbound.next().await.unwrap().unwrap();
+        assert!(matches!(command.payload, Some(CommandPayload::Drain(_))));
+
+        // Ohne Heartbeats läuft die Frist ab, der nächste Heartbeat lässt den Agent im Drain
+        wait_status(&router, &token, "offline").await;
+        tx.send(AgentRequest {
+            seq: 0,
+            payload: Some(Payload::Heartbeat(Heartbeat::default())),
+        })
+        .await
+        .unwrap();
+        wait_status(&router, &token, "draining").await;
+
+        drop(tx);
+        server.abort();
+        db.close().await;
+    }
+}
+
+async fn wait_status(router: &axum::Router, token: &str, status: &str) {
+    for _ in 0..100 {
+        let agents = send_json(
+            router,
+            Method::GET,
+            "/api/agents",
+            token,
+            None,
+            StatusCode::OK,
+        )
+        .await;
+        if agents[0]["status"] == status {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Agent wurde nicht {}", status);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{connect_agent, create_agent, create_job, send, TestDb};
+use server::http_server::create_router;
+use server::jobs::{self, JobOutcome};
+use server::{scheduler, with_db};
+
+/// Dashboards und Alerts bauen auf diesen Namen auf, siehe `metrics.rs`.
+const STABLE_METRICS: [(&str, &str); 9] = [
+    ("deliversphere_jobs", "gauge"),
+    ("deliversphere_jobs_finished_total", "counter"),
+    ("deliversphere_job_queue_wait_seconds", "histogram"),
+    ("deliversphere_job_duration_seconds", "histogram"),
+    ("deliversphere_agents", "gauge"),
+    ("deliversphere_grpc_streams", "gauge"),
+    ("deliversphere_websocket_clients", "gauge"),
+    ("deliversphere_db_query_duration_seconds", "histogram"),
+    ("deliversphere_heartbeat_lag_seconds", "histogram"),
+];
+
+/// Wert einer Zeile ohne Labels, z.B. `deliversphere_job_duration_seconds_count`.
+fn sample(body: &str, name: &str) -> f64 {
+    body.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .map_or(0.0, |value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn finished_job_is_exported_under_stable_names() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+
+        let (status, before) = send(&router, Method::GET, "/metrics", "", None).await;
+        assert_eq!(status, StatusCode::OK, "{}", db.backend());
+
+        create_agent(pool, "a1", "online").await;
+        let _commands = connect_agent(&state.live_agents, "a1");
+        let job_id = create_job(pool, None, &["make"]).await;
+        // Der Job wartet 30s, bevor ein Agent ihn übernimmt
+        with_db!(pool, |pool| {
+            sqlx::query("UPDATE jobs SET created_at = created_at - 30 WHERE id = $1")
+                .bind(&job_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+        let dispatched = scheduler::dispatch_pending(
+            pool,
+            &state.live_agents,
+            &state.ws_clients,
+            &state.config.scheduling,
+        )
+        .await
+        .unwrap();
+        assert_eq!(dispatched, 1, "{}", db.backend());
+        let outcome = JobOutcome {
+            success: true,
+            failure_reason: None,
+            failure_message: None,
+        };
+        let job = jobs::finish_job(
+            pool,
+            &state.ws_clients,
+            &state.logs,
+            "a1",
+            &job_id,
+            &outcome,
+        )
+        .await
+        .unwrap()
+        .expect("laufender Job wird abgeschlossen");
+        assert_eq!(job.status, "success");
+
+        let (status, after) = send(&router, Method::GET, "/metrics", "", None).await;
+        assert_eq!(status, StatusCode::OK);
+        for (name, kind) in STABLE_METRICS {
+            assert!(
+                after.contains(&format!("# TYPE {} {}\n", name, kind)),
+                "{} fehlt ({}):\n{}",
+                name,
+                db.backend(),
+                after
+            );
+        }
+        assert!(after.contains("deliversphere_jobs{status=\"success\"} 1\n"));
+        assert!(after.contains("deliversphere_agents{status=\"online\"} 1\n"));
+
+        // Warte- und Laufzeit werden beim Abschluss erfasst
+        for histogram in [
+            "deliversphere_job_queue_wait_seconds",
+            "deliversphere_job_duration_seconds",
+        ] {
+            let count = format!("{}_count", histogram);
+            assert_eq!(
+                sample(&after, &count),
+                sample(&before, &count) + 1.0,
+                "{} ({})",
+                histogram,
+                db.backend()
+            );
+        }
+        let queue_wait = sample(&after, "deliversphere_job_queue_wait_seconds_sum")
+            - sample(&before, "deliversphere_job_queue_wait_seconds_sum");
+        // created_at setzt die Datenbank, started_at der Server; beide auf Sekunden gerundet
+        assert!(queue_wait >= 29.0, "queue wait {}", queue_wait);
+        db.close().await;
+    }
+}
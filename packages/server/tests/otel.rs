@@ -0,0 +1,93 @@
+mod common;
+
+use common::{connect_agent, create_agent, create_job, TestDb};
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::TraceServiceServer;
+use server::config::OtelConfig;
+use server::jobs::{self, JobOutcome};
+use server::otel::{self, DevCollector};
+use server::scheduler;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tonic::transport::server::TcpIncoming;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_trace_reaches_collector() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (lines_tx, mut lines) = mpsc::unbounded_channel();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(TraceServiceServer::new(DevCollector::with_sink(lines_tx)))
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap()),
+    );
+    otel::init(&OtelConfig {
+        endpoint: Some(format!("http://{}", addr)),
+        service_name: "deliversphere-test".to_string(),
+    })
+    .unwrap();
+
+    let db = TestDb::sqlite().await;
+    let state = db.app_state();
+    let pool = &db.pool;
+    create_agent(pool, "a1", "online").await;
+    let _commands = connect_agent(&state.live_agents, "a1");
+    let job_id = create_job(pool, None, &["make"]).await;
+    scheduler::dispatch_pending(
+        pool,
+        &state.live_agents,
+        &state.ws_clients,
+        &state.config.scheduling,
+    )
+    .await
+    .unwrap();
+    let outcome = JobOutcome {
+        success: true,
+        failure_reason: None,
+        failure_message: None,
+    };
+    jobs::finish_job(
+        pool,
+        &state.ws_clients,
+        &state.logs,
+        "a1",
+        &job_id,
+        &outcome,
+    )
+    .await
+    .unwrap()
+    .expect("laufender Job wird abgeschlossen");
+    // Exportiert die gepufferten Spans
+    tokio::task::spawn_blocking(otel::shutdown).await.unwrap();
+
+    // Zeilen des Collectors: "<trace_id> <span_id> parent=<span_id> <name> ..."
+    let mut spans = HashMap::new();
+    while spans.len() < 3 {
+        let line = tokio::time::timeout(Duration::from_secs(10), lines.recv())
+            .await
+            .expect("Collector hat nicht alle Spans empfangen")
+            .unwrap();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        spans.insert(
+            fields[3].to_string(),
+            (
+                fields[0].to_string(),
+                fields[1].to_string(),
+                fields[2].trim_start_matches("parent=").to_string(),
+            ),
+        );
+    }
+
+    // Die Trace-ID ist die Job-ID, alle Spans des Servers hängen unter `job`
+    let trace_id = job_id.replace('-', "");
+    let (root_trace, root_span, root_parent) = &spans["job"];
+    assert_eq!(root_trace, &trace_id);
+    assert_eq!(root_parent, "-");
+    for name in ["queue_wait", "dispatch"] {
+        let (trace, _, parent) = &spans[name];
+        assert_eq!(trace, &trace_id, "{}", name);
+        assert_eq!(parent, root_span, "{}", name);
+    }
+    db.close().await;
+}
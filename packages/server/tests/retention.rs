@@ -0,0 +1,152 @@
+mod common;
+
+use common::{create_job, create_project, TestDb};
+use server::config::ServerConfig;
+use server::db::DbPool;
+use server::retention::{self, PruneReason};
+use server::{unix_timestamp, with_db};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Ein abgeschlossener Job mit `bytes` Bytes Log, angelegt vor `age` Sekunden.
+async fn finished_job(
+    pool: &DbPool,
+    config: &ServerConfig,
+    project_id: Option<&str>,
+    status: &str,
+    age: i64,
+    bytes: usize,
+) -> String {
+    let job_id = create_job(pool, project_id, &["make"]).await;
+    with_db!(pool, |pool| {
+        sqlx::query("UPDATE jobs SET status = $1, created_at = $2 WHERE id = $3")
+            .bind(status)
+            .bind(unix_timestamp() - age)
+            .bind(&job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO job_log_chunks \
+             (job_id, seq, byte_offset, byte_length, line_offset, line_count) \
+             VALUES ($1, 0, 0, $2, 0, 1)",
+        )
+        .bind(&job_id)
+        .bind(bytes as i64)
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO job_log_lines (job_id, line_no, content) VALUES ($1, 0, 'ok')")
+            .bind(&job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+    });
+    let dir = config.job_log_dir(&job_id);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("000000-job.log"), vec![b'x'; bytes]).unwrap();
+    job_id
+}
+
+async fn pin(pool: &DbPool, job_id: &str) {
+    with_db!(pool, |pool| {
+        sqlx::query("UPDATE jobs SET pinned = $1 WHERE id = $2")
+            .bind(true)
+            .bind(job_id)
+            .execute(pool)
+            .await
+            .unwrap();
+    });
+}
+
+/// Zeilen in den Tabellen, die an einem Job hängen.
+async fn rows(pool: &DbPool, job_id: &str) -> [i64; 4] {
+    let mut counts = [0; 4];
+    for (i, table) in ["jobs", "job_steps", "job_log_chunks", "job_log_lines"]
+        .iter()
+        .enumerate()
+    {
+        let column = if *table == "jobs" { "id" } else { "job_id" };
+        let query = format!("SELECT COUNT(*) FROM {} WHERE {} = $1", table, column);
+        counts[i] = with_db!(pool, |pool| {
+            sqlx::query_scalar(&query)
+                .bind(job_id)
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        });
+    }
+    counts
+}
+
+#[tokio::test]
+async fn retention_plans_reasons_and_keeps_pinned_jobs() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let mut config = (*db.app_state().config).clone();
+        config.retention.max_age = Some(Duration::from_secs(30 * DAY as u64));
+        config.retention.max_jobs_per_project = Some(3);
+        config.retention.max_storage_bytes = Some(1600);
+        config.retention.keep_last_successful = true;
+
+        let alpha = create_project(pool, "alpha").await;
+        let beta = create_project(pool, "beta").await;
+        let alpha = Some(alpha.as_str());
+        let beta = Some(beta.as_str());
+        // Alpha: zu alt, aber angepinnt bzw. der letzte erfolgreiche Job
+        let pinned = finished_job(pool, &config, alpha, "failed", 100 * DAY, 100).await;
+        pin(pool, &pinned).await;
+        let too_old = finished_job(pool, &config, alpha, "failed", 90 * DAY, 100).await;
+        let last_success = finished_job(pool, &config, alpha, "success", 40 * DAY, 100).await;
+        // Alpha: der vierte neue Job ist einer zu viel
+        let fourth = finished_job(pool, &config, alpha, "failed", 40, 100).await;
+        for age in [30, 20, 10] {
+            finished_job(pool, &config, alpha, "failed", age, 100).await;
+        }
+        // Beta: über dem Speicherlimit, die ältesten Jobs gehen zuerst
+        let oldest = finished_job(pool, &config, beta, "failed", 300, 300).await;
+        let older = finished_job(pool, &config, beta, "cancelled", 200, 300).await;
+        finished_job(pool, &config, beta, "success", 100, 1000).await;
+        // Laufende Jobs zählen nicht mit
+        create_job(pool, beta, &["make"]).await;
+
+        let report = retention::plan(pool, &config).await.unwrap();
+        let reasons: HashMap<&str, PruneReason> = report
+            .jobs
+            .iter()
+            .map(|job| (job.id.as_str(), job.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            HashMap::from([
+                (too_old.as_str(), PruneReason::MaxAge),
+                (fourth.as_str(), PruneReason::MaxJobsPerProject),
+                (oldest.as_str(), PruneReason::MaxStorage),
+                (older.as_str(), PruneReason::MaxStorage),
+            ]),
+            "{}",
+            db.backend()
+        );
+        assert_eq!(report.storage_bytes, 2300);
+        assert_eq!(report.freed_bytes, 800);
+        // Der Probelauf ändert nichts
+        assert_eq!(rows(pool, &too_old).await, [1, 1, 1, 1]);
+        assert!(config.job_log_dir(&too_old).exists());
+
+        // Inzwischen angepinnt: bleibt trotz Report erhalten
+        pin(pool, &older).await;
+        let deleted = retention::apply(pool, &config, &report).await.unwrap();
+        assert_eq!(deleted, 3);
+        for job_id in [&pinned, &older, &last_success] {
+            assert_eq!(rows(pool, job_id).await, [1, 1, 1, 1], "{}", db.backend());
+            assert!(config.job_log_dir(job_id).exists());
+        }
+        for job_id in [&too_old, &fourth, &oldest] {
+            assert_eq!(rows(pool, job_id).await, [0, 0, 0, 0], "{}", db.backend());
+            assert!(!config.job_log_dir(job_id).exists());
+        }
+        db.close().await;
+    }
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{connect_agent, create_agent, create_job, fetch_job, TestDb};
+use server::agent_metrics;
+use server::config::SchedulingConfig;
+use server::grpc_server::runner::{server_command::Payload as CommandPayload, AgentTelemetry};
+use server::scheduler;
+use server::{with_db, LiveAgentMap, WsClientMap};
+
+#[tokio::test]
+async fn dispatch_assigns_pending_jobs_to_free_eligible_agents() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let live_agents = LiveAgentMap::default();
+        let ws_clients = WsClientMap::default();
+        let config = SchedulingConfig {
+            min_free_disk_bytes: Some(1024),
+            min_available_memory_bytes: None,
+        };
+        for agent_id in ["full-disk", "healthy", "draining"] {
+            create_agent(
+                pool,
+                agent_id,
+                if agent_id == "draining" {
+                    "draining"
+                } else {
+                    "online"
+                },
+            )
+            .await;
+        }
+        let mut full_disk = connect_agent(&live_agents, "full-disk");
+        let mut healthy = connect_agent(&live_agents, "healthy");
+        let mut draining = connect_agent(&live_agents, "draining");
+        for (agent_id, disk_free_bytes) in
+            [("full-disk", 10), ("healthy", 4096), ("draining", 4096)]
+        {
+            let telemetry = AgentTelemetry {
+                disk_free_bytes,
+                ..Default::default()
+            };
+            agent_metrics::record(pool, agent_id, &telemetry)
+                .await
+                .unwrap();
+        }
+
+        let first = create_job(pool, None, &["make", "make test"]).await;
+        let second = create_job(pool, None, &["true"]).await;
+        // Innerhalb derselben Sekunde wäre die Reihenfolge zufällig.
+        with_db!(pool, |pool| {
+            sqlx::query("UPDATE jobs SET created_at = created_at - 10 WHERE id = $1")
+                .bind(&first)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+        let dispatched = scheduler::dispatch_pending(pool, &live_agents, &ws_clients, &config)
+            .await
+            .unwrap();
+        assert_eq!(dispatched, 1, "{}", db.backend());
+
+        let job = fetch_job(pool, &first).await;
+        assert_eq!(job.status, "running");
+        assert_eq!(job.agent_id.as_deref(), Some("healthy"));
+        assert!(job.started_at.is_some());
+        match healthy.try_recv().unwrap().unwrap().payload {
+            Some(CommandPayload::Job(run)) => {
+                assert_eq!(run.job_id, first);
+                assert_eq!(run.commands, vec!["make", "make test"]);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(full_disk.try_recv().is_err());
+        assert!(draining.try_recv().is_err());
+
+        // Ein Agent bekommt erst nach seinem Job den nächsten.
+        let dispatched = scheduler::dispatch_pending(pool, &live_agents, &ws_clients, &config)
+            .await
+            .unwrap();
+        assert_eq!(dispatched, 0);
+        assert_eq!(fetch_job(pool, &second).await.status, "pending");
+
+        // Ist der Stream schon zu, bleibt der Job wartend.
+        create_agent(pool, "gone", "online").await;
+        drop(connect_agent(&live_agents, "gone"));
+        agent_metrics::record(
+            pool,
+            "gone",
+            &AgentTelemetry {
+                disk_free_bytes: 4096,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let dispatched = scheduler::dispatch_pending(pool, &live_agents, &ws_clients, &config)
+            .await
+            .unwrap();
+        assert_eq!(dispatched, 0);
+        let job = fetch_job(pool, &second).await;
+        assert_eq!(job.status, "pending");
+        assert_eq!(job.agent_id, None);
+        assert_eq!(job.started_at, None);
+        db.close().await;
+    }
+}
+
+#[tokio::test]
+async fn agents_run_up_to_their_concurrency() {
+    for db in TestDb::all().await {
+        let pool = &db.pool;
+        let live_agents = LiveAgentMap::default();
+        let ws_clients = WsClientMap::default();
+        create_agent(pool, "a1", "online").await;
+        create_agent(pool, "a2", "online").await;
+        with_db!(pool, |pool| {
+            sqlx::query("UPDATE agents SET concurrency = 2 WHERE id = 'a1'")
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+        let _a1 = connect_agent(&live_agents, "a1");
+        let _a2 = connect_agent(&live_agents, "a2");
+        let mut jobs = Vec::new();
+        for _ in 0..4 {
+            jobs.push(create_job(pool, None, &["make"]).await);
+        }
+
+        let dispatched =
+            scheduler::dispatch_pending(pool, &live_agents, &ws_clients, &Default::default())
+                .await
+                .unwrap();
+        assert_eq!(dispatched, 3, "{}", db.backend());
+        let mut assigned = Vec::new();
+        for job_id in &jobs {
+            assigned.extend(fetch_job(pool, job_id).await.agent_id);
+        }
+        assigned.sort();
+        assert_eq!(assigned, ["a1", "a1", "a2"]);
+        db.close().await;
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{api_token, connect_agent, create_agent, create_job, create_user, send_json, TestDb};
+use server::grpc_server::runner::{FailureReason, StepResult, StepStatus};
+use server::http_server::create_router;
+use server::jobs::{self, JobOutcome};
+use server::scheduler;
+
+fn step(job_id: &str, index: u32, status: StepStatus) -> StepResult {
+    let mut step = StepResult {
+        job_id: job_id.to_string(),
+        index,
+        command: format!("step {}", index),
+        started_at_ms: 1_000,
+        ..Default::default()
+    };
+    step.set_status(status);
+    if status != StepStatus::Running {
+        step.exit_code = Some(0);
+        step.finished_at_ms = 3_500;
+        step.duration_ms = 2_500;
+    }
+    step
+}
+
+#[tokio::test]
+async fn steps_follow_the_dispatched_job() {
+    for db in TestDb::all().await {
+        let state = db.app_state();
+        let router = create_router(state.clone());
+        let pool = &db.pool;
+        let admin = create_user(pool, "admin", true).await;
+        let token = api_token(pool, &admin).await;
+        create_agent(pool, "a1", "online").await;
+        create_agent(pool, "a2", "online").await;
+        let _a1 = connect_agent(&state.live_agents, "a1");
+        let job_id = create_job(pool, None, &["step 0", "step 1", "step 2"]).await;
+
+        // Solange der Job wartet, nimmt der Server keine Schritte an
+        let early = jobs::record_step(
+            pool,
+            &state.ws_clients,
+            "a1",
+            &step(&job_id, 0, StepStatus::Running),
+        )
+        .await
+        .unwrap();
+        assert!(early.is_none(), "{}", db.backend());
+
+        scheduler::dispatch_pending(
+            pool,
+            &state.live_agents,
+            &state.ws_clients,
+            &state.config.scheduling,
+        )
+        .await
+        .unwrap();
+        let done = jobs::record_step(
+            pool,
+            &state.ws_clients,
+            "a1",
+            &step(&job_id, 0, StepStatus::Success),
+        )
+        .await
+        .unwrap()
+        .expect("Schritt des laufenden Jobs");
+        assert_eq!(done.status, "success");
+        assert_eq!(done.duration_ms, Some(2_500));
+        jobs::record_step(
+            pool,
+            &state.ws_clients,
+            "a1",
+            &step(&job_id, 1, StepStatus::Running),
+        )
+        .await
+        .unwrap()
+        .expect("Schritt des laufenden Jobs");
+        // Ein anderer Agent kann die Schritte nicht überschreiben
+        let foreign = jobs::record_step(
+            pool,
+            &state.ws_clients,
+            "a2",
+            &step(&job_id, 1, StepStatus::Failed),
+        )
+        .await
+        .unwrap();
+        assert!(foreign.is_none());
+
+        let outcome = JobOutcome::failed(FailureReason::Timeout, "step 1 timed out");
+        jobs::finish_job(
+            pool,
+            &state.ws_clients,
+            &state.logs,
+            "a1",
+            &job_id,
+            &outcome,
+        )
+        .await
+        .unwrap()
+        .expect("laufender Job wird abgeschlossen");
+
+        // Der laufende Schritt wurde abgebrochen, der Rest übersprungen
+        let steps = send_json(
+            &router,
+            Method::GET,
+            &format!("/api/jobs/{}/steps", job_id),
+            &token,
+            None,
+            StatusCode::OK,
+        )
+        .await;
+        let statuses: Vec<&str> = steps
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| step["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            ["success", "cancelled", "skipped"],
+            "{}",
+            db.backend()
+        );
+        db.close().await;
+    }
+}